@@ -0,0 +1,215 @@
+use std::fs;
+use std::hint::black_box;
+
+use chip_8::core::chip::Chip8;
+use chip_8::core::display::Display;
+use chip_8::core::handlers;
+use chip_8::core::instruction::Instruction;
+use chip_8::core::keypad::Keypad;
+use chip_8::core::quirks::Quirks;
+use chip_8::core::state::ChipState;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+// Instructions run per iteration of each mix
+const MIX_LENGTH: usize = 1024;
+
+struct Machine {
+    state: ChipState,
+    quirks: Quirks,
+    display: Display,
+    keypad: Keypad,
+    rng: StdRng,
+}
+
+impl Machine {
+    fn new(quirks: Quirks) -> Machine {
+        let mut state = ChipState::init();
+        state.pc = 0x200;
+        state.vi = 0x300;
+        state.registers = [
+            0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 1, 2, 3, 4, 5, 6, 7, 8,
+        ];
+
+        Machine {
+            state,
+            quirks,
+            display: Display::new(),
+            keypad: Keypad::new(),
+            rng: StdRng::seed_from_u64(0x8),
+        }
+    }
+
+    fn execute(&mut self, instruction: Instruction) {
+        handlers::execute(
+            instruction,
+            &mut self.state,
+            &self.quirks,
+            &mut self.display,
+            &mut self.keypad,
+            &mut self.rng,
+        );
+    }
+}
+
+// Repeats a pattern of already decoded instructions, so only executing them
+// is measured. Each pattern keeps the stack balanced and I in memory.
+fn mixes() -> Vec<(&'static str, Vec<Instruction>)> {
+    use Instruction::*;
+
+    let patterns: [(&str, &[Instruction]); 4] = [
+        (
+            "arithmetic",
+            &[
+                Load(0, 0x2A),
+                Add(1, 3),
+                Move(2, 0),
+                Or(3, 1),
+                And(4, 2),
+                Xor(5, 3),
+                AddRegisters(6, 7),
+                Sub(7, 6),
+                ShiftRight(8, 9),
+                SubReverse(9, 8),
+                ShiftLeft(10, 11),
+                Random(11, 0x0F),
+            ],
+        ),
+        (
+            "branches",
+            &[
+                SkipEqual(0, 0x11),
+                SkipNotEqual(1, 0x11),
+                SkipEqualRegisters(2, 3),
+                SkipNotEqualRegisters(4, 5),
+                Call(0x400),
+                Return,
+                Jump(0x200),
+            ],
+        ),
+        (
+            "memory",
+            &[
+                LoadIndex(0x300),
+                StoreRegisters(7),
+                LoadIndex(0x300),
+                LoadRegisters(7),
+                LoadIndex(0x310),
+                StoreBcd(0),
+                AddIndex(1),
+                LoadFont(2),
+            ],
+        ),
+        (
+            "timers",
+            &[
+                SetDelay(0),
+                ReadDelay(1),
+                SetSound(2),
+                SkipPressed(3),
+                SkipNotPressed(4),
+            ],
+        ),
+    ];
+
+    patterns
+        .iter()
+        .map(|(name, pattern)| {
+            let mix = pattern.iter().copied().cycle().take(MIX_LENGTH).collect();
+            (*name, mix)
+        })
+        .collect()
+}
+
+fn execute(c: &mut Criterion) {
+    let mut group = c.benchmark_group("execute");
+    group.throughput(Throughput::Elements(MIX_LENGTH as u64));
+
+    for (name, mix) in mixes() {
+        let mut machine = Machine::new(Quirks::for_chip8());
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for &instruction in &mix {
+                    machine.execute(black_box(instruction));
+                }
+                // Skips move PC on without a fetch to bring it back
+                machine.state.pc = 0x200;
+            })
+        });
+    }
+    group.finish();
+}
+
+// 15 row sprites across the screen, including the edges where clipping
+// decides whether they wrap
+fn draw(c: &mut Criterion) {
+    let mut group = c.benchmark_group("draw");
+    let positions: Vec<(u8, u8)> = (0..64).map(|i| (i * 7 % 64, i * 5 % 32)).collect();
+    group.throughput(Throughput::Elements(positions.len() as u64));
+
+    for (name, clipping) in [("clipped", true), ("wrapped", false)] {
+        let mut quirks = Quirks::for_chip8();
+        quirks.set_clipping(clipping);
+        let mut machine = Machine::new(quirks);
+        machine.state.vi = 0x50;
+
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for &(x, y) in &positions {
+                    machine.state.registers[0] = x;
+                    machine.state.registers[1] = y;
+                    machine.execute(Instruction::Draw(0, 1, 15));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+const FRAMES: u32 = 60;
+const IPF: u32 = 1000;
+
+// With display wait off every frame runs all of its instructions
+fn run_rom(rom: &[u8]) -> Chip8 {
+    let mut chip = Chip8::new();
+    chip.set_seed(0x8);
+    chip.quirks().set_display_wait(false);
+    chip.load_rom(rom).unwrap();
+
+    for _ in 0..FRAMES {
+        chip.step(IPF);
+    }
+    chip
+}
+
+// A second of every bundled ROM without input. ROMs left waiting on FX0A
+// would only count that instruction again and again, so they are skipped.
+fn roms(c: &mut Criterion) {
+    let mut group = c.benchmark_group("roms");
+
+    let mut paths: Vec<_> = fs::read_dir("roms")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let rom = fs::read(&path).unwrap();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+
+        let chip = run_rom(&rom);
+        if chip.state().should_wait {
+            continue;
+        }
+
+        group.throughput(Throughput::Elements(chip.cycles()));
+        group.bench_with_input(BenchmarkId::new("frames", name), &rom, |b, rom| {
+            b.iter(|| run_rom(rom).cycles())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, execute, draw, roms);
+criterion_main!(benches);
@@ -0,0 +1,53 @@
+use std::hint::black_box;
+
+use chip_8::core::chip::Chip8;
+use chip_8::core::instruction::Instruction;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(0x10000));
+    group.bench_function("every opcode", |b| {
+        b.iter(|| {
+            for code in 0..=0xFFFF {
+                black_box(Instruction::decode(black_box(code)));
+            }
+        })
+    });
+    group.finish();
+}
+
+// Pong plays itself without input. Display wait is off so a frame runs all
+// of its instructions instead of stopping at the first sprite.
+fn pong() -> Chip8 {
+    let rom = std::fs::read("roms/pong.rom").unwrap();
+    let mut chip = Chip8::new();
+    chip.quirks().set_display_wait(false);
+    chip.load_rom(&rom).unwrap();
+    chip
+}
+
+fn step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("step");
+
+    for ipf in [1_000, 10_000, 100_000] {
+        let mut chip = pong();
+        group.throughput(Throughput::Elements(ipf as u64));
+        group.bench_with_input(BenchmarkId::new("pong", ipf), &ipf, |b, &ipf| {
+            b.iter(|| chip.step(ipf))
+        });
+
+        #[cfg(feature = "jit")]
+        {
+            let mut chip = pong();
+            chip.set_jit(true).unwrap();
+            group.bench_with_input(BenchmarkId::new("pong jit", ipf), &ipf, |b, &ipf| {
+                b.iter(|| chip.step(ipf))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode, step);
+criterion_main!(benches);
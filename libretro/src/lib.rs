@@ -0,0 +1,655 @@
+// Entry points are only called by libretro frontends, following libretro.h
+#![allow(clippy::missing_safety_doc)]
+
+mod ffi;
+
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use chip_8::cheat::{self, CheatList};
+use chip_8::core::chip::Chip8;
+use chip_8::core::display::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip_8::core::snapshot::SNAPSHOT_SIZE;
+use chip_8::palette::Palette;
+use chip_8::synth::{Synth, SAMPLE_RATE};
+
+use crate::ffi::*;
+
+const FPS: f64 = 60.0;
+const DEFAULT_IPF: u32 = 10;
+// Netplay peers and rewinds must draw the same random numbers
+const SEED: u64 = 0x8;
+
+const IPF_VARIABLE: &CStr = c"chip8_ipf";
+const IPF_DESCRIPTION: &CStr = c"Instructions per frame; 10|5|15|20|30|50|100|200|500|1000";
+
+// The keypad on a RetroPad, directions on 5/7/8/9 like WASD on the keyboard
+const BUTTON_MAPPING: [(c_uint, u8); 16] = [
+    (RETRO_DEVICE_ID_JOYPAD_UP, 0x5),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, 0x8),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, 0x7),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, 0x9),
+    (RETRO_DEVICE_ID_JOYPAD_A, 0x6),
+    (RETRO_DEVICE_ID_JOYPAD_B, 0x4),
+    (RETRO_DEVICE_ID_JOYPAD_X, 0x2),
+    (RETRO_DEVICE_ID_JOYPAD_Y, 0x1),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, 0x0),
+    (RETRO_DEVICE_ID_JOYPAD_START, 0xF),
+    (RETRO_DEVICE_ID_JOYPAD_L, 0x3),
+    (RETRO_DEVICE_ID_JOYPAD_R, 0xC),
+    (RETRO_DEVICE_ID_JOYPAD_L2, 0xD),
+    (RETRO_DEVICE_ID_JOYPAD_R2, 0xE),
+    (RETRO_DEVICE_ID_JOYPAD_L3, 0xA),
+    (RETRO_DEVICE_ID_JOYPAD_R3, 0xB),
+];
+
+struct Callbacks {
+    environment: Option<retro_environment_t>,
+    video_refresh: Option<retro_video_refresh_t>,
+    audio_sample_batch: Option<retro_audio_sample_batch_t>,
+    input_poll: Option<retro_input_poll_t>,
+    input_state: Option<retro_input_state_t>,
+}
+
+struct Core {
+    chip: Chip8,
+    rom: Vec<u8>,
+    synth: Synth,
+    palette: Palette,
+    ipf: u32,
+    frame: Vec<u32>,
+    cheats: BTreeMap<c_uint, CheatList>,
+    // Memory as the last frame left it, to find what the frontend wrote since
+    memory: [u8; 4096],
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+impl Core {
+    fn new(rom: Vec<u8>) -> Result<Core, String> {
+        let chip = boot(&rom)?;
+
+        Ok(Core {
+            memory: chip.state().memory,
+            chip,
+            rom,
+            synth: Synth::new(),
+            palette: Palette::default(),
+            ipf: DEFAULT_IPF,
+            frame: vec![0; SCREEN_WIDTH as usize * SCREEN_HEIGHT],
+            cheats: BTreeMap::new(),
+        })
+    }
+
+    fn poll_input(&mut self, input_state: retro_input_state_t) {
+        for (button, key) in BUTTON_MAPPING {
+            let pressed = unsafe { input_state(0, RETRO_DEVICE_JOYPAD, 0, button) } != 0;
+            let keypad = self.chip.keypad();
+
+            if pressed {
+                keypad.press(key);
+            } else if keypad.is_pressed(key) {
+                keypad.release(key);
+            }
+        }
+    }
+
+    // Frontends write memory through the pointer from retro_get_memory_data,
+    // e.g. for cheats and achievements. Only the code decoded or compiled
+    // from the bytes they changed is dropped.
+    fn invalidate_written(&mut self) {
+        let memory = self.chip.state().memory;
+        let mut start = None;
+
+        for address in 0..=memory.len() {
+            let changed = address < memory.len() && memory[address] != self.memory[address];
+            match (changed, start) {
+                (true, None) => start = Some(address),
+                (false, Some(first)) => {
+                    self.chip.invalidate(first, address);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        self.memory = memory;
+    }
+
+    fn render(&mut self) {
+        let display = self.chip.display();
+
+        for row in 0..SCREEN_HEIGHT {
+            for column in 0..SCREEN_WIDTH as usize {
+                let (r, g, b) = self.palette.color(display.is_lit(column, row));
+                self.frame[row * SCREEN_WIDTH as usize + column] =
+                    (r as u32) << 16 | (g as u32) << 8 | b as u32;
+            }
+        }
+    }
+
+    fn audio(&mut self) -> Vec<i16> {
+        let samples = self.synth.frame(self.chip.is_sound_playing());
+
+        // Libretro wants interleaved stereo
+        samples
+            .iter()
+            .flat_map(|sample| {
+                let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                [value, value]
+            })
+            .collect()
+    }
+}
+
+// A panic must not unwind into the frontend, which would abort it. The core
+// that panicked is dropped, since its state can no longer be trusted.
+fn catch<T>(fallback: T, body: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(value) => value,
+        Err(_) => {
+            *lock(&CORE) = None;
+            fallback
+        }
+    }
+}
+
+// Locks survive a panic caught while they were held
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn boot(rom: &[u8]) -> Result<Chip8, String> {
+    let mut chip = Chip8::new();
+    chip.set_seed(SEED);
+    chip.load_rom(rom)?;
+    Ok(chip)
+}
+
+unsafe fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    let callback = lock(&CALLBACKS).environment;
+
+    match callback {
+        Some(callback) => callback(cmd, data),
+        None => false,
+    }
+}
+
+unsafe fn read_ipf() -> Option<u32> {
+    let mut variable = retro_variable {
+        key: IPF_VARIABLE.as_ptr(),
+        value: ptr::null(),
+    };
+
+    if !environment(
+        RETRO_ENVIRONMENT_GET_VARIABLE,
+        &mut variable as *mut _ as *mut c_void,
+    ) || variable.value.is_null()
+    {
+        return None;
+    }
+
+    CStr::from_ptr(variable.value).to_str().ok()?.parse().ok()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_environment(callback: retro_environment_t) {
+    lock(&CALLBACKS).environment = Some(callback);
+
+    let variables = [
+        retro_variable {
+            key: IPF_VARIABLE.as_ptr(),
+            value: IPF_DESCRIPTION.as_ptr(),
+        },
+        retro_variable {
+            key: ptr::null(),
+            value: ptr::null(),
+        },
+    ];
+
+    callback(
+        RETRO_ENVIRONMENT_SET_VARIABLES,
+        variables.as_ptr() as *mut c_void,
+    );
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: retro_video_refresh_t) {
+    lock(&CALLBACKS).video_refresh = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: retro_audio_sample_t) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: retro_audio_sample_batch_t) {
+    lock(&CALLBACKS).audio_sample_batch = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: retro_input_poll_t) {
+    lock(&CALLBACKS).input_poll = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: retro_input_state_t) {
+    lock(&CALLBACKS).input_state = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *lock(&CORE) = None;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut retro_system_info) {
+    *info = retro_system_info {
+        library_name: c"chip-8-rs".as_ptr(),
+        library_version: c"0.1.0".as_ptr(),
+        valid_extensions: c"ch8|c8|rom".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut retro_system_av_info) {
+    *info = retro_system_av_info {
+        geometry: retro_game_geometry {
+            base_width: SCREEN_WIDTH as c_uint,
+            base_height: SCREEN_HEIGHT as c_uint,
+            max_width: SCREEN_WIDTH as c_uint,
+            max_height: SCREEN_HEIGHT as c_uint,
+            aspect_ratio: 2.0,
+        },
+        timing: retro_system_timing {
+            fps: FPS,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    catch((), || {
+        if let Some(core) = lock(&CORE).as_mut() {
+            // Loaded once already, so it fits
+            core.chip = boot(&core.rom).unwrap();
+            core.memory = core.chip.state().memory;
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_run() {
+    catch((), || {
+        let (video_refresh, audio_sample_batch, input_poll, input_state) = {
+            let callbacks = lock(&CALLBACKS);
+            (
+                callbacks.video_refresh,
+                callbacks.audio_sample_batch,
+                callbacks.input_poll,
+                callbacks.input_state,
+            )
+        };
+
+        let mut updated = false;
+        environment(
+            RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE,
+            &mut updated as *mut bool as *mut c_void,
+        );
+        let ipf = if updated { read_ipf() } else { None };
+
+        let mut guard = lock(&CORE);
+        let Some(core) = guard.as_mut() else {
+            return;
+        };
+
+        if let Some(ipf) = ipf {
+            core.ipf = ipf;
+        }
+
+        if let Some(input_poll) = input_poll {
+            input_poll();
+        }
+        if let Some(input_state) = input_state {
+            core.poll_input(input_state);
+        }
+
+        core.invalidate_written();
+        core.chip.step(core.ipf);
+
+        for cheats in core.cheats.values_mut() {
+            cheats.apply(&mut core.chip);
+        }
+        core.memory = core.chip.state().memory;
+
+        if let Some(audio_sample_batch) = audio_sample_batch {
+            let samples = core.audio();
+            audio_sample_batch(samples.as_ptr(), samples.len() / 2);
+        }
+
+        if let Some(video_refresh) = video_refresh {
+            core.render();
+            video_refresh(
+                core.frame.as_ptr() as *const c_void,
+                SCREEN_WIDTH as c_uint,
+                SCREEN_HEIGHT as c_uint,
+                SCREEN_WIDTH as usize * 4,
+            );
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    SNAPSHOT_SIZE
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    catch(false, || {
+        let guard = lock(&CORE);
+        let Some(core) = guard.as_ref() else {
+            return false;
+        };
+
+        let Ok(snapshot) = core.chip.save_state() else {
+            return false;
+        };
+        if size < snapshot.len() {
+            return false;
+        }
+
+        ptr::copy_nonoverlapping(snapshot.as_ptr(), data as *mut u8, snapshot.len());
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    catch(false, || {
+        let mut guard = lock(&CORE);
+        let Some(core) = guard.as_mut() else {
+            return false;
+        };
+
+        let snapshot = std::slice::from_raw_parts(data as *const u8, size);
+        let loaded = core.chip.load_state(snapshot).is_ok();
+        core.memory = core.chip.state().memory;
+        loaded
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    catch((), || {
+        if let Some(core) = lock(&CORE).as_mut() {
+            core.cheats.clear();
+        }
+    })
+}
+
+// Codes are <address>:<value> in hex, several joined with +
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(index: c_uint, enabled: bool, code: *const c_char) {
+    catch((), || {
+        let mut guard = lock(&CORE);
+        let Some(core) = guard.as_mut() else {
+            return;
+        };
+
+        core.cheats.remove(&index);
+
+        if !enabled || code.is_null() {
+            return;
+        }
+
+        let code = CStr::from_ptr(code).to_string_lossy();
+        if let Ok(codes) = cheat::parse_codes(&code) {
+            let mut cheats = CheatList::new();
+            for cheat in codes {
+                cheats.add(cheat);
+            }
+            core.cheats.insert(index, cheats);
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const retro_game_info) -> bool {
+    catch(false, || {
+        if game.is_null() || (*game).data.is_null() {
+            return false;
+        }
+
+        let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+        if !environment(
+            RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+            &mut format as *mut c_uint as *mut c_void,
+        ) {
+            return false;
+        }
+
+        let rom = std::slice::from_raw_parts((*game).data as *const u8, (*game).size).to_vec();
+        let mut core = match Core::new(rom) {
+            Ok(core) => core,
+            Err(_) => return false,
+        };
+
+        if let Some(ipf) = read_ipf() {
+            core.ipf = ipf;
+        }
+
+        *lock(&CORE) = Some(core);
+        true
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const retro_game_info,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *lock(&CORE) = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    catch(ptr::null_mut(), || {
+        let mut guard = lock(&CORE);
+
+        match guard.as_mut() {
+            Some(core) if id == RETRO_MEMORY_SYSTEM_RAM => {
+                core.chip.state_mut().memory.as_mut_ptr() as *mut c_void
+            }
+            _ => ptr::null_mut(),
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    catch(0, || {
+        let guard = lock(&CORE);
+
+        match guard.as_ref() {
+            Some(core) if id == RETRO_MEMORY_SYSTEM_RAM => core.chip.state().memory.len(),
+            _ => 0,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every test goes through the one global core
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    unsafe extern "C" fn accept(_cmd: c_uint, _data: *mut c_void) -> bool {
+        true
+    }
+
+    fn load(rom: &[u8]) {
+        let game = retro_game_info {
+            path: ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: ptr::null(),
+        };
+
+        unsafe {
+            retro_set_environment(accept);
+            assert!(retro_load_game(&game));
+        }
+    }
+
+    fn serialize() -> Vec<u8> {
+        let mut data = vec![0u8; retro_serialize_size()];
+        assert!(unsafe { retro_serialize(data.as_mut_ptr() as *mut c_void, data.len()) });
+        data
+    }
+
+    fn registers() -> [u8; 16] {
+        lock(&CORE).as_ref().unwrap().chip.state().registers
+    }
+
+    // 200..21C: RND V0, FF to RND VE, FF  21E: JP 200
+    fn random_rom() -> Vec<u8> {
+        let mut rom: Vec<u8> = (0..15).flat_map(|x| [0xC0 | x, 0xFF]).collect();
+        rom.extend_from_slice(&[0x12, 0x00]);
+        rom
+    }
+
+    #[test]
+    fn draws_the_same_numbers_after_loading_a_snapshot() {
+        let _lock = LOCK.lock().unwrap();
+        load(&random_rom());
+
+        unsafe { retro_run() };
+        let snapshot = serialize();
+
+        let mut expected = Vec::new();
+        for _ in 0..3 {
+            unsafe { retro_run() };
+            expected.push(registers());
+        }
+
+        assert!(unsafe { retro_unserialize(snapshot.as_ptr() as *const c_void, snapshot.len()) });
+        assert_eq!(serialize(), snapshot);
+
+        for registers_then in expected {
+            unsafe { retro_run() };
+            assert_eq!(registers(), registers_then);
+        }
+
+        retro_unload_game();
+    }
+
+    #[test]
+    fn runs_code_the_frontend_wrote() {
+        let _lock = LOCK.lock().unwrap();
+
+        // 200: LD V0, 01  202: JP 200
+        load(&[0x60, 0x01, 0x12, 0x00]);
+        let memory = retro_get_memory_data(RETRO_MEMORY_SYSTEM_RAM) as *mut u8;
+
+        unsafe { retro_run() };
+        assert_eq!(registers()[0], 0x01);
+
+        unsafe { *memory.add(0x201) = 0x05 };
+        unsafe { retro_run() };
+        assert_eq!(registers()[0], 0x05);
+
+        retro_unload_game();
+    }
+
+    #[test]
+    fn refuses_snapshots_pointing_outside_memory() {
+        let _lock = LOCK.lock().unwrap();
+        load(&random_rom());
+
+        // PC follows the magic, memory, registers and I
+        let mut snapshot = serialize();
+        snapshot[4 + 4096 + 16 + 2..][..2].copy_from_slice(&[0xFF, 0xFF]);
+        assert!(!unsafe { retro_unserialize(snapshot.as_ptr() as *const c_void, snapshot.len()) });
+
+        unsafe { retro_run() };
+        assert!(lock(&CORE).is_some());
+
+        retro_unload_game();
+    }
+
+    #[test]
+    fn drops_the_core_instead_of_unwinding_into_the_frontend() {
+        let _lock = LOCK.lock().unwrap();
+        load(&random_rom());
+
+        let result = catch(true, || {
+            let _core = lock(&CORE);
+            panic!("the core broke");
+        });
+        assert!(result);
+        assert!(lock(&CORE).is_none());
+
+        // Nothing is left poisoned
+        unsafe { retro_run() };
+        load(&random_rom());
+        unsafe { retro_run() };
+
+        retro_unload_game();
+    }
+
+    #[test]
+    fn boots_every_peer_with_the_same_numbers() {
+        let _lock = LOCK.lock().unwrap();
+
+        let mut runs = Vec::new();
+        for _ in 0..2 {
+            load(&random_rom());
+            unsafe { retro_run() };
+            runs.push(registers());
+            retro_reset();
+            unsafe { retro_run() };
+            runs.push(registers());
+        }
+
+        assert!(runs.iter().all(|run| *run == runs[0]));
+        retro_unload_game();
+    }
+}
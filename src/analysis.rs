@@ -0,0 +1,452 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::core::access::{memory_access, Access};
+use crate::core::chip::MEM_OFFSET;
+use crate::coverage::Coverage;
+use crate::disasm;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub start: u16,
+    // Address of the last instruction
+    pub last: u16,
+    pub successors: Vec<u16>,
+    // Subroutine called by the last instruction
+    pub call: Option<u16>,
+}
+
+// What the analyzer found by following the ROM from its load address. Code
+// only reached through computed jumps (BNNN) is not found, unless a coverage
+// map shows it ran.
+pub struct Analysis {
+    rom: Vec<u8>,
+    // Reachable instructions and the size each takes
+    instructions: BTreeMap<u16, u16>,
+    pub blocks: BTreeMap<u16, Block>,
+    // The entry point and every call target
+    pub subroutines: BTreeSet<u16>,
+    // Subroutine to the subroutines it calls
+    pub calls: BTreeMap<u16, BTreeSet<u16>>,
+    pub indirect_jumps: Vec<u16>,
+    // (instruction, address written) for writes into reachable code
+    pub code_writes: Vec<(u16, u16)>,
+}
+
+enum Flow {
+    Next,
+    Stop,
+    Jump(u16),
+    Call(u16),
+    Skip,
+    Indirect,
+}
+
+impl Analysis {
+    pub fn run(rom: &[u8]) -> Analysis {
+        Analysis::run_from(rom, BTreeSet::new(), Vec::new())
+    }
+
+    // Also follows the code from every byte the run executed. Each stretch of
+    // executed bytes starts a block.
+    pub fn with_coverage(rom: &[u8], coverage: &Coverage) -> Analysis {
+        let mut leaders = BTreeSet::new();
+        let mut entries = Vec::new();
+
+        let end = MEM_OFFSET as usize + rom.len();
+        let mut address = MEM_OFFSET as usize;
+        while address < end {
+            if !coverage.is_executed(address as u16) {
+                address += 1;
+                continue;
+            }
+
+            leaders.insert(address as u16);
+            while address < end && coverage.is_executed(address as u16) {
+                entries.push(address as u16);
+                address += 2;
+            }
+        }
+
+        Analysis::run_from(rom, leaders, entries)
+    }
+
+    fn run_from(rom: &[u8], leaders: BTreeSet<u16>, entries: Vec<u16>) -> Analysis {
+        let mut analysis = Analysis {
+            rom: rom.to_vec(),
+            instructions: BTreeMap::new(),
+            blocks: BTreeMap::new(),
+            subroutines: BTreeSet::from([MEM_OFFSET]),
+            calls: BTreeMap::new(),
+            indirect_jumps: Vec::new(),
+            code_writes: Vec::new(),
+        };
+
+        let leaders = analysis.descend(leaders, entries);
+        analysis.build_blocks(&leaders);
+        analysis.build_call_graph();
+        analysis.find_code_writes();
+        analysis
+    }
+
+    pub fn fetch(&self, address: u16) -> Option<u16> {
+        let offset = address.checked_sub(MEM_OFFSET)? as usize;
+        let bytes = self.rom.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn instructions(&self) -> impl Iterator<Item = u16> + '_ {
+        self.instructions.keys().copied()
+    }
+
+    pub fn is_code(&self, address: u16) -> bool {
+        self.instructions
+            .range(..=address)
+            .next_back()
+            .is_some_and(|(start, size)| address < start + size)
+    }
+
+    // XO-CHIP's long I load takes 4 bytes, which skips have to hop over
+    fn size(&self, address: u16) -> u16 {
+        match self.fetch(address) {
+            Some(0xF000) => 4,
+            _ => 2,
+        }
+    }
+
+    fn flow(code: u16) -> Flow {
+        match (code >> 12, code & 0xFF) {
+            // Return, exit
+            (0, 0xEE) | (0, 0xFD) => Flow::Stop,
+            (1, _) => Flow::Jump(code & 0xFFF),
+            (2, _) => Flow::Call(code & 0xFFF),
+            (3 | 4 | 5 | 9, _) | (0xE, 0x9E | 0xA1) => Flow::Skip,
+            (0xB, _) => Flow::Indirect,
+            _ => Flow::Next,
+        }
+    }
+
+    fn successors(&self, address: u16, code: u16) -> Vec<u16> {
+        let next = address + self.size(address);
+
+        match Analysis::flow(code) {
+            Flow::Next | Flow::Call(_) => vec![next],
+            Flow::Jump(target) => vec![target],
+            Flow::Skip => vec![next, next + self.size(next)],
+            Flow::Stop | Flow::Indirect => Vec::new(),
+        }
+    }
+
+    // Finds every instruction reachable from the load address and the given
+    // entries, and returns the addresses that start a block
+    fn descend(&mut self, mut leaders: BTreeSet<u16>, mut pending: Vec<u16>) -> BTreeSet<u16> {
+        leaders.insert(MEM_OFFSET);
+        pending.push(MEM_OFFSET);
+
+        while let Some(address) = pending.pop() {
+            if self.instructions.contains_key(&address) {
+                continue;
+            }
+            let Some(code) = self.fetch(address) else {
+                continue;
+            };
+
+            self.instructions.insert(address, self.size(address));
+            let successors = self.successors(address, code);
+
+            match Analysis::flow(code) {
+                Flow::Call(target) => {
+                    self.subroutines.insert(target);
+                    leaders.insert(target);
+                    pending.push(target);
+                    // Returns land after the call
+                    leaders.extend(&successors);
+                }
+                Flow::Jump(_) | Flow::Skip => leaders.extend(&successors),
+                Flow::Indirect => self.indirect_jumps.push(address),
+                Flow::Next | Flow::Stop => {}
+            }
+
+            pending.extend(successors);
+        }
+
+        leaders
+    }
+
+    fn build_blocks(&mut self, leaders: &BTreeSet<u16>) {
+        for &start in leaders {
+            if !self.instructions.contains_key(&start) {
+                continue;
+            }
+
+            let mut last = start;
+            loop {
+                let code = self.fetch(last).unwrap_or(0);
+                let next = last + self.instructions[&last];
+                let falls_through = matches!(Analysis::flow(code), Flow::Next);
+
+                if !falls_through
+                    || leaders.contains(&next)
+                    || !self.instructions.contains_key(&next)
+                {
+                    break;
+                }
+                last = next;
+            }
+
+            let code = self.fetch(last).unwrap_or(0);
+            let call = match Analysis::flow(code) {
+                Flow::Call(target) => Some(target),
+                _ => None,
+            };
+
+            let block = Block {
+                start,
+                last,
+                successors: self.successors(last, code),
+                call,
+            };
+            self.blocks.insert(start, block);
+        }
+    }
+
+    // A subroutine is made of the blocks reachable from its entry without
+    // following calls
+    fn build_call_graph(&mut self) {
+        for &entry in &self.subroutines {
+            let mut callees = BTreeSet::new();
+            let mut seen = BTreeSet::new();
+            let mut pending = vec![entry];
+
+            while let Some(start) = pending.pop() {
+                let Some(block) = self.blocks.get(&start) else {
+                    continue;
+                };
+                if !seen.insert(start) {
+                    continue;
+                }
+
+                callees.extend(block.call);
+                pending.extend(&block.successors);
+            }
+
+            self.calls.insert(entry, callees);
+        }
+    }
+
+    // I is only tracked within a block, from an LD I, NNN to its first change
+    fn find_code_writes(&mut self) {
+        let mut writes = Vec::new();
+
+        for block in self.blocks.values() {
+            let mut vi = None;
+            for (&address, _) in self.instructions.range(block.start..=block.last) {
+                let code = self.fetch(address).unwrap_or(0);
+
+                let access = vi.and_then(|vi| memory_access(code, vi));
+                if let Some((Access::Write, start, length)) = access {
+                    writes.extend(
+                        (start..start + length)
+                            .filter(|target| self.is_code(*target))
+                            .map(|target| (address, target)),
+                    );
+                }
+
+                vi = match (code >> 12, code & 0xF0FF) {
+                    (0xA, _) => Some(code & 0xFFF),
+                    (0xF, 0xF000 | 0xF01E | 0xF055 | 0xF065) => None,
+                    _ => vi,
+                };
+            }
+        }
+
+        self.code_writes = writes;
+    }
+
+    // Names for subroutines and jump targets, for the disassembler
+    pub fn labels(&self) -> BTreeMap<u16, String> {
+        let mut labels = BTreeMap::new();
+
+        for block in self.blocks.values() {
+            let code = self.fetch(block.last).unwrap_or(0);
+            if let Flow::Jump(target) = Analysis::flow(code) {
+                labels.insert(target, format!("loc_{:03X}", target));
+            }
+        }
+
+        for &entry in &self.subroutines {
+            labels.insert(entry, format!("sub_{:03X}", entry));
+        }
+        labels.insert(MEM_OFFSET, "main".to_string());
+
+        labels
+    }
+
+    // Labeled disassembly of the reachable code, with the bytes in between
+    // summarized as data
+    pub fn listing(&self) -> String {
+        let labels = self.labels();
+        let mut out = String::new();
+
+        writeln!(
+            out,
+            "; {} blocks, {} subroutines, {} indirect jumps, {} writes into code",
+            self.blocks.len(),
+            self.subroutines.len(),
+            self.indirect_jumps.len(),
+            self.code_writes.len()
+        )
+        .unwrap();
+
+        let mut expected = MEM_OFFSET;
+        for (&address, &size) in &self.instructions {
+            if address > expected {
+                writeln!(out, "\n; data {:03X}-{:03X}", expected, address - 1).unwrap();
+            }
+            expected = address + size;
+
+            if let Some(label) = labels.get(&address) {
+                writeln!(out, "\n{}:", label).unwrap();
+            }
+
+            let code = self.fetch(address).unwrap_or(0);
+            let mut line = format!(
+                "  {:03X}  {:04X}  {}",
+                address,
+                code,
+                disasm::disassemble_labeled(code, &labels)
+            );
+
+            if self.indirect_jumps.contains(&address) {
+                line.push_str("  ; indirect jump, targets unknown");
+            }
+            for (_, target) in self.code_writes.iter().filter(|(at, _)| *at == address) {
+                line.push_str(&format!("  ; writes into code at {:03X}", target));
+            }
+
+            writeln!(out, "{}", line).unwrap();
+        }
+
+        let end = MEM_OFFSET + self.rom.len() as u16;
+        if end > expected {
+            writeln!(out, "\n; data {:03X}-{:03X}", expected, end - 1).unwrap();
+        }
+
+        out
+    }
+
+    // Graphviz graph of the blocks, with calls dashed and blocks that end in
+    // an indirect jump or write into code in red
+    pub fn to_dot(&self) -> String {
+        let labels = self.labels();
+        let mut out = String::from("digraph rom {\n  node [shape=box fontname=monospace];\n");
+
+        for block in self.blocks.values() {
+            let mut text = String::new();
+            if let Some(label) = labels.get(&block.start) {
+                text.push_str(&format!("{}:\\l", label));
+            }
+
+            let mut flagged = self.indirect_jumps.contains(&block.last);
+            for (&address, _) in self.instructions.range(block.start..=block.last) {
+                let code = self.fetch(address).unwrap_or(0);
+                text.push_str(&format!(
+                    "{:03X}  {}\\l",
+                    address,
+                    disasm::disassemble_labeled(code, &labels)
+                ));
+                flagged |= self.code_writes.iter().any(|(at, _)| *at == address);
+            }
+
+            let color = if flagged { " color=red" } else { "" };
+            writeln!(out, "  b{:03X} [label=\"{}\"{}];", block.start, text, color).unwrap();
+
+            for target in &block.successors {
+                if self.blocks.contains_key(target) {
+                    writeln!(out, "  b{:03X} -> b{:03X};", block.start, target).unwrap();
+                }
+            }
+            if let Some(target) = block.call {
+                writeln!(
+                    out,
+                    "  b{:03X} -> b{:03X} [style=dashed];",
+                    block.start, target
+                )
+                .unwrap();
+            }
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 200: CALL 208  202: SE V0, 1  204: JP 200  206: JP 206
+    // 208: LD I, 202  20A: LD [I], V0  20C: RET
+    const ROM: [u8; 14] = [
+        0x22, 0x08, 0x30, 0x01, 0x12, 0x00, 0x12, 0x06, 0xA2, 0x02, 0xF0, 0x55, 0x00, 0xEE,
+    ];
+
+    #[test]
+    fn splits_blocks_at_branches() {
+        let analysis = Analysis::run(&ROM);
+
+        let starts: Vec<u16> = analysis.blocks.keys().copied().collect();
+        assert_eq!(starts, [0x200, 0x202, 0x204, 0x206, 0x208]);
+        assert_eq!(analysis.blocks[&0x200].call, Some(0x208));
+        assert_eq!(analysis.blocks[&0x202].successors, [0x204, 0x206]);
+        assert_eq!(analysis.blocks[&0x208].last, 0x20C);
+    }
+
+    #[test]
+    fn builds_the_call_graph() {
+        let analysis = Analysis::run(&ROM);
+
+        assert_eq!(analysis.subroutines, BTreeSet::from([0x200, 0x208]));
+        assert_eq!(analysis.calls[&0x200], BTreeSet::from([0x208]));
+        assert!(analysis.calls[&0x208].is_empty());
+        assert_eq!(analysis.labels()[&0x208], "sub_208");
+    }
+
+    #[test]
+    fn flags_indirect_jumps_and_writes_into_code() {
+        let analysis = Analysis::run(&ROM);
+        assert_eq!(analysis.code_writes, [(0x20A, 0x202)]);
+
+        // 200: JP V0, 300
+        let analysis = Analysis::run(&[0xB3, 0x00]);
+        assert_eq!(analysis.indirect_jumps, [0x200]);
+        assert!(analysis.listing().contains("indirect jump"));
+    }
+
+    #[test]
+    fn follows_code_the_run_executed() {
+        // 200: JP V0, 204  202: DW 0xFFFF  204: LD V1, 2  206: JP 206
+        let rom = [0xB2, 0x04, 0xFF, 0xFF, 0x61, 0x02, 0x12, 0x06];
+        assert!(!Analysis::run(&rom).is_code(0x204));
+
+        let mut coverage = Coverage::new();
+        coverage.record(0x200, 0xB204, 0);
+        coverage.record(0x204, 0x6102, 0);
+        coverage.record(0x206, 0x1206, 0);
+        let analysis = Analysis::with_coverage(&rom, &coverage);
+
+        assert!(analysis.is_code(0x204));
+        assert!(!analysis.is_code(0x202));
+        assert_eq!(analysis.blocks[&0x204].successors, [0x206]);
+        assert!(analysis.listing().contains("; data 202-203"));
+    }
+
+    #[test]
+    fn writes_dot_graphs() {
+        let dot = Analysis::run(&ROM).to_dot();
+
+        assert!(dot.contains("b200 -> b208 [style=dashed];"));
+        assert!(dot.contains("b202 -> b206;"));
+        assert!(dot.contains("b208 [label=\"sub_208:\\l208  LD I, 0x202\\l"));
+    }
+}
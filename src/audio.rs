@@ -1,33 +1,51 @@
-use sdl2::audio::AudioCallback;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::AudioSubsystem;
 
-pub struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
+use chip_8::synth::SAMPLE_RATE;
+
+// Frames worth of samples allowed to pile up before the queue is dropped
+const MAX_QUEUED_FRAMES: u32 = 4;
+
+pub struct Speaker {
+    queue: AudioQueue<f32>,
+    pub muted: bool,
 }
 
-impl AudioCallback for SquareWave {
-    type Channel = f32;
-
-    fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+impl Speaker {
+    pub fn new(audio_subsystem: &AudioSubsystem) -> Speaker {
+        let desired_spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE as i32),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let queue = audio_subsystem
+            .open_queue::<f32, _>(None, &desired_spec)
+            .unwrap();
+        queue.resume();
+
+        Speaker {
+            queue,
+            muted: false,
         }
     }
-}
 
-impl SquareWave {
-    pub fn new(phase_inc: f32, phase: f32, volume: f32) -> SquareWave {
-        SquareWave {
-            phase_inc,
-            phase,
-            volume,
+    pub fn play(&mut self, samples: &[f32]) {
+        let frame_bytes = std::mem::size_of_val(samples) as u32;
+
+        // Keep latency bounded if the emulator runs ahead of the device
+        if self.queue.size() > frame_bytes * MAX_QUEUED_FRAMES {
+            self.queue.clear();
+        }
+
+        let result = if self.muted {
+            self.queue.queue_audio(&vec![0.0; samples.len()])
+        } else {
+            self.queue.queue_audio(samples)
+        };
+
+        if let Err(e) = result {
+            eprintln!("Could not queue audio: {}", e);
         }
     }
 }
@@ -0,0 +1,142 @@
+use std::fs::File;
+
+use serde_json::Value;
+
+use crate::core::quirks::Quirks;
+use crate::palette::{Palette, Rgb};
+
+// Octo saves programs as GIFs with a label drawn on them, hiding a JSON
+// payload of the source and its options in the low two bits of every pixel
+pub struct Cartridge {
+    pub program: String,
+    pub tickrate: Option<u32>,
+    pub palette: Option<Palette>,
+    options: Value,
+}
+
+impl Cartridge {
+    pub fn load(path: &str) -> Result<Cartridge, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        Cartridge::read(file).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn read(input: impl std::io::Read) -> Result<Cartridge, String> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(input).map_err(|e| e.to_string())?;
+
+        let mut pixels = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().map_err(|e| e.to_string())? {
+            pixels.extend_from_slice(&frame.buffer);
+        }
+
+        Cartridge::decode(&pixels)
+    }
+
+    pub fn decode(pixels: &[u8]) -> Result<Cartridge, String> {
+        // Four pixels make a byte, highest bits first
+        let bytes: Vec<u8> = pixels
+            .chunks_exact(4)
+            .map(|chunk| chunk.iter().fold(0, |byte, pixel| byte << 2 | (pixel & 3)))
+            .collect();
+
+        let not_a_cartridge = || "not an Octo cartridge".to_string();
+        let size = bytes.get(..4).ok_or_else(not_a_cartridge)?;
+        let size = u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize;
+        let payload = bytes.get(4..4 + size).ok_or_else(not_a_cartridge)?;
+
+        let json: Value = serde_json::from_slice(payload).map_err(|_| not_a_cartridge())?;
+        let program = json["program"].as_str().ok_or_else(not_a_cartridge)?;
+        let options = json["options"].clone();
+
+        let color = |name: &str| options[name].as_str().and_then(parse_color);
+        let palette = match (color("backgroundColor"), color("fillColor")) {
+            (Some(background), Some(foreground)) => Some(Palette::new(background, foreground)),
+            _ => None,
+        };
+
+        Ok(Cartridge {
+            program: program.to_string(),
+            tickrate: options["tickrate"].as_u64().map(|ipf| ipf as u32),
+            palette,
+            options,
+        })
+    }
+
+    // Octo names quirks after the SCHIP behaviour they turn on, while ours
+    // are true for the original COSMAC one. Quirks the cartridge leaves out
+    // keep their current setting.
+    pub fn apply_quirks(&self, quirks: &mut Quirks) {
+        let flag = |name: &str| self.options[name].as_bool();
+
+        if let Some(on) = flag("shiftQuirks") {
+            quirks.set_shifting(!on);
+        }
+        if let Some(on) = flag("loadStoreQuirks") {
+            quirks.set_increment_index(!on);
+        }
+        if let Some(on) = flag("jumpQuirks") {
+            quirks.set_jumping(!on);
+        }
+        if let Some(on) = flag("logicQuirks") {
+            quirks.set_vf_reset(on);
+        }
+        if let Some(on) = flag("vBlankQuirks") {
+            quirks.set_display_wait(on);
+        }
+        if let Some(on) = flag("clipQuirks") {
+            quirks.set_clipping(on);
+        }
+    }
+}
+
+fn parse_color(value: &str) -> Option<Rgb> {
+    let value = value.trim_start_matches('#');
+    let rgb = u32::from_str_radix(value, 16).ok()?;
+
+    (value.len() == 6).then_some(((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(payload: &str) -> Vec<u8> {
+        let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(payload.as_bytes());
+
+        // Label colors live in the upper bits and must not disturb the payload
+        bytes
+            .iter()
+            .flat_map(|byte| [6, 4, 2, 0].map(|shift| 0xF0 | (byte >> shift) & 3))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_program_and_options() {
+        let pixels = encode(
+            r##"{"program": ": main\n  jump main", "options": {"tickrate": 20,
+            "backgroundColor": "#996600", "fillColor": "#FFCC00",
+            "shiftQuirks": true, "vBlankQuirks": false}}"##,
+        );
+
+        let cartridge = Cartridge::decode(&pixels).unwrap();
+        assert_eq!(cartridge.program, ": main\n  jump main");
+        assert_eq!(cartridge.tickrate, Some(20));
+
+        let palette = cartridge.palette.as_ref().unwrap();
+        assert_eq!(palette.background, (0x99, 0x66, 0x00));
+        assert_eq!(palette.foreground, (0xFF, 0xCC, 0x00));
+
+        let mut quirks = Quirks::for_chip8();
+        cartridge.apply_quirks(&mut quirks);
+        assert!(!quirks.has_shifting());
+        assert!(!quirks.has_display_wait());
+        assert!(quirks.has_increment_index());
+    }
+
+    #[test]
+    fn rejects_plain_images() {
+        assert!(Cartridge::decode(&[0; 64]).is_err());
+    }
+}
@@ -0,0 +1,305 @@
+use std::fs;
+use std::path::Path;
+
+use crate::core::chip::Chip8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Equal(u8),
+    Unchanged,
+    Changed,
+    Increased,
+    Decreased,
+}
+
+// Narrows down the addresses that could hold a value by comparing memory
+// against the snapshot taken at the previous filter
+pub struct Search {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl Search {
+    pub fn new(memory: &[u8]) -> Search {
+        Search {
+            snapshot: memory.to_vec(),
+            candidates: (0..memory.len() as u16).collect(),
+        }
+    }
+
+    pub fn filter(&mut self, memory: &[u8], comparison: Comparison) {
+        let snapshot = &self.snapshot;
+
+        self.candidates.retain(|address| {
+            let before = snapshot[*address as usize];
+            let now = memory[*address as usize];
+
+            match comparison {
+                Comparison::Equal(value) => now == value,
+                Comparison::Unchanged => now == before,
+                Comparison::Changed => now != before,
+                Comparison::Increased => now > before,
+                Comparison::Decreased => now < before,
+            }
+        });
+
+        self.snapshot = memory.to_vec();
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    // Written every frame
+    Freeze,
+    // Written once each time the cheat is turned on
+    Poke,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cheat {
+    pub address: u16,
+    pub value: u8,
+    pub mode: Mode,
+    pub enabled: bool,
+    pub name: String,
+    applied: bool,
+}
+
+impl Cheat {
+    pub fn new(address: u16, value: u8, mode: Mode, name: &str) -> Cheat {
+        Cheat {
+            address: address & 0xFFF,
+            value,
+            mode,
+            enabled: true,
+            name: name.to_string(),
+            applied: false,
+        }
+    }
+}
+
+// Codes in the <address>:<value> hex form cheat databases use, several
+// joined with +, all frozen
+pub fn parse_codes(codes: &str) -> Result<Vec<Cheat>, String> {
+    codes
+        .split('+')
+        .map(|code| {
+            let invalid = || format!("invalid cheat code '{}'", code);
+            let (address, value) = code.trim().split_once(':').ok_or_else(invalid)?;
+            let address = u16::from_str_radix(address, 16).map_err(|_| invalid())?;
+            let value = u8::from_str_radix(value, 16).map_err(|_| invalid())?;
+
+            Ok(Cheat::new(address, value, Mode::Freeze, code.trim()))
+        })
+        .collect()
+}
+
+#[derive(Default)]
+pub struct CheatList {
+    cheats: Vec<Cheat>,
+}
+
+impl CheatList {
+    pub fn new() -> CheatList {
+        CheatList { cheats: Vec::new() }
+    }
+
+    // Cheat files are kept next to the ROM, game.ch8 uses game.cheats
+    pub fn path_for(rom_path: &str) -> String {
+        Path::new(rom_path)
+            .with_extension("cheats")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    pub fn load(path: &str) -> Result<CheatList, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        CheatList::parse(&text)
+    }
+
+    // One cheat per line: <address> <value> <freeze|poke> <on|off> [name],
+    // address and value in hex
+    pub fn parse(text: &str) -> Result<CheatList, String> {
+        let mut cheats = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || format!("invalid cheat on line {}", number + 1);
+            // Fields may be padded with any run of whitespace, the name is
+            // whatever follows the fourth
+            let mut rest = line;
+            let mut next = || -> Result<&str, String> {
+                let field = rest.split_whitespace().next().ok_or_else(invalid)?;
+                rest = &rest.trim_start()[field.len()..];
+                Ok(field)
+            };
+
+            let address = u16::from_str_radix(next()?, 16).map_err(|_| invalid())?;
+            let value = u8::from_str_radix(next()?, 16).map_err(|_| invalid())?;
+            let mode = match next()? {
+                "freeze" => Mode::Freeze,
+                "poke" => Mode::Poke,
+                _ => return Err(invalid()),
+            };
+            let enabled = match next()? {
+                "on" => true,
+                "off" => false,
+                _ => return Err(invalid()),
+            };
+            let name = rest.trim();
+
+            let mut cheat = Cheat::new(address, value, mode, name);
+            cheat.enabled = enabled;
+            cheats.push(cheat);
+        }
+
+        Ok(CheatList { cheats })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_text()).map_err(|e| e.to_string())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# address value freeze|poke on|off name\n");
+
+        for cheat in &self.cheats {
+            let mode = match cheat.mode {
+                Mode::Freeze => "freeze",
+                Mode::Poke => "poke",
+            };
+            let state = if cheat.enabled { "on" } else { "off" };
+
+            text.push_str(&format!(
+                "{:03X} {:02X} {} {} {}\n",
+                cheat.address, cheat.value, mode, state, cheat.name
+            ));
+        }
+
+        text
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn add(&mut self, cheat: Cheat) {
+        self.cheats.push(cheat);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        (index < self.cheats.len()).then(|| self.cheats.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+    }
+
+    // Returns the new state, or None when there is no such cheat
+    pub fn toggle(&mut self, index: usize) -> Option<bool> {
+        let cheat = self.cheats.get_mut(index)?;
+        cheat.enabled = !cheat.enabled;
+        cheat.applied = false;
+        Some(cheat.enabled)
+    }
+
+    // Called once per frame after the instructions ran
+    pub fn apply(&mut self, chip: &mut Chip8) {
+        for cheat in self.cheats.iter_mut().filter(|c| c.enabled) {
+            if cheat.mode == Mode::Freeze || !cheat.applied {
+                chip.poke(cheat.address, cheat.value);
+                cheat.applied = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrows_candidates_across_snapshots() {
+        let mut memory = [0u8; 8];
+        memory[2] = 3;
+        memory[5] = 3;
+
+        let mut search = Search::new(&memory);
+        search.filter(&memory, Comparison::Equal(3));
+        assert_eq!(search.candidates(), &[2, 5]);
+
+        memory[2] = 2;
+        memory[5] = 4;
+        search.filter(&memory, Comparison::Decreased);
+        assert_eq!(search.candidates(), &[2]);
+
+        search.filter(&memory, Comparison::Unchanged);
+        assert_eq!(search.candidates(), &[2]);
+    }
+
+    #[test]
+    fn freezes_every_frame_and_pokes_once() {
+        let mut chip = Chip8::new();
+        let mut cheats = CheatList::new();
+        cheats.add(Cheat::new(0x300, 9, Mode::Freeze, "lives"));
+        cheats.add(Cheat::new(0x301, 5, Mode::Poke, "level"));
+
+        cheats.apply(&mut chip);
+        chip.poke(0x300, 1);
+        chip.poke(0x301, 1);
+        cheats.apply(&mut chip);
+
+        assert_eq!(chip.state().memory[0x300], 9);
+        assert_eq!(chip.state().memory[0x301], 1);
+
+        cheats.toggle(1);
+        cheats.toggle(1);
+        cheats.apply(&mut chip);
+        assert_eq!(chip.state().memory[0x301], 5);
+    }
+
+    #[test]
+    fn round_trips_the_cheat_file() {
+        let mut cheats = CheatList::new();
+        cheats.add(Cheat::new(0x3F0, 0x63, Mode::Freeze, "Infinite lives"));
+        cheats.add(Cheat::new(0x2A4, 0x12, Mode::Poke, ""));
+        cheats.toggle(1);
+
+        let parsed = CheatList::parse(&cheats.to_text()).unwrap();
+        assert_eq!(parsed.cheats(), cheats.cheats());
+    }
+
+    #[test]
+    fn parses_hand_aligned_lines() {
+        let cheats =
+            CheatList::parse("3F0  63\tfreeze   on  infinite  lives\n3F1 0A poke off").unwrap();
+
+        assert_eq!(cheats.cheats()[0].value, 0x63);
+        assert_eq!(cheats.cheats()[0].name, "infinite  lives");
+        assert!(!cheats.cheats()[1].enabled);
+        assert_eq!(cheats.cheats()[1].name, "");
+    }
+
+    #[test]
+    fn parses_joined_codes() {
+        let cheats = parse_codes("3F0:63+3F1:0A").unwrap();
+
+        assert_eq!(cheats.len(), 2);
+        assert_eq!((cheats[1].address, cheats[1].value), (0x3F1, 0x0A));
+        assert!(parse_codes("3F0=63").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(CheatList::parse("3F0 63 sometimes on").is_err());
+        assert!(CheatList::parse("3F0").is_err());
+    }
+}
@@ -0,0 +1,288 @@
+use chip_8::cartridge::Cartridge;
+use chip_8::core::quirks::Quirks;
+use chip_8::palette::Palette;
+use chip_8::rom;
+use chip_8::synth::{Synth, Waveform, SAMPLE_RATE};
+use chip_8::trace::Tracer;
+
+pub const USAGE: &str = "usage: chip_8 <path> <ipf> [options]
+       chip_8 dap            serve the Debug Adapter Protocol on stdin and stdout
+       chip_8 analyze <path> [--dot <file>] [--coverage <file>]
+                             list the reachable code and write its control-flow graph
+       chip_8 lint <path> [--frames <n>] [--ipf <n>] [--quirks <preset>]
+                             report which quirks the ROM depends on
+       chip_8 jit-check <path> [--frames <n>] [--ipf <n>] [--quirks <preset>]
+                             run with and without the JIT and report where they differ
+
+options:
+  --entry <name>        ROM to run from a zip archive holding several
+  --cartridge <file>    take quirks, palette and ipf from an Octo cartridge GIF
+  --patch <file>        apply an IPS or BPS patch, by default <rom>.ips or <rom>.bps if present
+  --headless <frames>   run without a window for the given number of frames
+  --tui                 play in the terminal instead of opening a window
+  --quirks <preset>     chip8 (default), schip or xochip behaviour, or auto to guess from the ROM
+  --screenshot <file>   write the framebuffer to a PNG when a headless run ends
+  --scale <n>           scale screenshots with the palette instead of native 64x32
+  --record <file>       capture every frame to a .gif or a numbered .png/.ppm sequence
+  --wav <file>          write everything the buzzer plays to a 16-bit WAV file
+  --palette <bg>:<fg>   colors as rrggbb, e.g. 26110d:9b4231
+  --waveform <name>     square, pulse[:<duty>], triangle, sine or noise
+  --frequency <hz>      buzzer pitch, 440 by default
+  --volume <level>      buzzer volume between 0 and 1, 0.05 by default
+  --attack <ms>         time the buzzer takes to fade in, 2 by default
+  --release <ms>        time the buzzer takes to fade out, 10 by default
+  --trace <file>        log every executed instruction, - for stdout
+  --trace-range <a-b>   only trace addresses in this hex range, e.g. 200-2ff
+  --trace-ops <list>    only trace these opcode classes by first nibble, e.g. 8,d,f
+  --profile <file>      write a hotspot and subroutine report on exit, - for stdout
+  --profile-top <n>     entries listed per report section, 20 by default
+  --coverage <file>     write a map of executed, read and written bytes on exit
+  --coverage-image <file>  the same map as a PNG
+  --gdb <port>          wait for gdb on a local port and let it control the ROM
+  --script <file>       run a Rhai script with hooks on frames, addresses and memory writes
+  --cheats              search memory and manage cheats by typing commands on stdin
+  --jit                 compile straight-line code to native code, needs the jit feature";
+
+pub const NO_JIT: &str = "this build has no JIT, rebuild with --features jit";
+#[cfg(not(feature = "scripting"))]
+const NO_SCRIPTING: &str = "this build has no scripting, rebuild with --features scripting";
+
+pub struct Options {
+    pub rom_path: String,
+    pub ipf: u32,
+    pub entry: Option<String>,
+    pub patch: Option<String>,
+    pub headless: Option<u32>,
+    pub tui: bool,
+    pub quirks: Quirks,
+    // As given to --quirks, auto picks one from the ROM's opcodes
+    pub preset: Option<String>,
+    pub screenshot: Option<String>,
+    pub scale: Option<usize>,
+    pub record: Option<String>,
+    pub wav: Option<String>,
+    pub palette: Palette,
+    pub waveform: Waveform,
+    pub frequency: f32,
+    pub volume: f32,
+    pub attack: f32,
+    pub release: f32,
+    pub trace: Option<String>,
+    pub trace_range: Option<(u16, u16)>,
+    pub trace_ops: Option<Vec<u8>>,
+    pub profile: Option<String>,
+    pub profile_top: usize,
+    pub coverage: Option<String>,
+    pub coverage_image: Option<String>,
+    pub gdb: Option<u16>,
+    #[cfg(feature = "scripting")]
+    pub script: Option<String>,
+    pub cheats: bool,
+    pub jit: bool,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        if args.len() < 2 {
+            return Err(USAGE.to_string());
+        }
+
+        let mut options = Options {
+            rom_path: args[0].clone(),
+            ipf: parse_number(&args[1])?,
+            entry: None,
+            patch: None,
+            headless: None,
+            tui: false,
+            quirks: Quirks::for_chip8(),
+            preset: None,
+            screenshot: None,
+            scale: None,
+            record: None,
+            wav: None,
+            palette: Palette::default(),
+            waveform: Waveform::Square,
+            frequency: 440.0,
+            volume: 0.05,
+            attack: 2.0,
+            release: 10.0,
+            trace: None,
+            trace_range: None,
+            trace_ops: None,
+            profile: None,
+            profile_top: 20,
+            coverage: None,
+            coverage_image: None,
+            gdb: None,
+            #[cfg(feature = "scripting")]
+            script: None,
+            cheats: false,
+            jit: false,
+        };
+
+        let mut rest = args[2..].iter();
+        let mut cartridge = None;
+        let mut palette_set = false;
+
+        while let Some(flag) = rest.next() {
+            let mut value = || {
+                rest.next()
+                    .ok_or(format!("missing value for {}\n\n{}", flag, USAGE))
+            };
+
+            match flag.as_str() {
+                "--entry" => options.entry = Some(value()?.clone()),
+                "--cartridge" => cartridge = Some(value()?.clone()),
+                "--patch" => options.patch = Some(value()?.clone()),
+                "--headless" => options.headless = Some(parse_number(value()?)?),
+                "--tui" => options.tui = true,
+                "--quirks" => {
+                    let preset = value()?;
+                    options.preset = Some(preset.clone());
+                    if preset != "auto" {
+                        options.quirks = Quirks::from_name(preset)
+                            .ok_or(format!("unknown quirks preset '{}'", preset))?;
+                    }
+                }
+                "--screenshot" => options.screenshot = Some(value()?.clone()),
+                "--scale" => options.scale = Some(parse_number(value()?)?),
+                "--record" => options.record = Some(value()?.clone()),
+                "--wav" => options.wav = Some(value()?.clone()),
+                "--palette" => {
+                    options.palette = Palette::parse(value()?)?;
+                    palette_set = true;
+                }
+                "--waveform" => options.waveform = Waveform::parse(value()?)?,
+                "--frequency" => options.frequency = parse_frequency(value()?)?,
+                "--volume" => options.volume = parse_number(value()?)?,
+                "--attack" => options.attack = parse_number(value()?)?,
+                "--release" => options.release = parse_number(value()?)?,
+                "--trace" => options.trace = Some(value()?.clone()),
+                "--trace-range" => options.trace_range = Some(parse_range(value()?)?),
+                "--trace-ops" => options.trace_ops = Some(parse_nibbles(value()?)?),
+                "--profile" => options.profile = Some(value()?.clone()),
+                "--profile-top" => options.profile_top = parse_number(value()?)?,
+                "--coverage" => options.coverage = Some(value()?.clone()),
+                "--coverage-image" => options.coverage_image = Some(value()?.clone()),
+                "--gdb" => options.gdb = Some(parse_number(value()?)?),
+                #[cfg(feature = "scripting")]
+                "--script" => options.script = Some(value()?.clone()),
+                #[cfg(not(feature = "scripting"))]
+                "--script" => return Err(NO_SCRIPTING.to_string()),
+                "--cheats" => options.cheats = true,
+                "--jit" => options.jit = true,
+                _ => return Err(format!("unknown option {}\n\n{}", flag, USAGE)),
+            }
+        }
+
+        // A cartridge run as the ROM brings its settings along
+        if cartridge.is_none()
+            && options.preset.as_deref() != Some("auto")
+            && rom::is_cartridge(&options.rom_path)
+        {
+            cartridge = Some(options.rom_path.clone());
+        }
+
+        // The cartridge's quirks apply on top of --quirks and its speed
+        // replaces ipf, while --palette wins over its colors
+        if let Some(path) = cartridge {
+            if options.preset.as_deref() == Some("auto") {
+                return Err("--cartridge sets the quirks, --quirks auto can't".to_string());
+            }
+
+            let cartridge = Cartridge::load(&path)?;
+            cartridge.apply_quirks(&mut options.quirks);
+
+            if let Some(tickrate) = cartridge.tickrate {
+                options.ipf = tickrate;
+            }
+
+            if let (Some(palette), false) = (cartridge.palette, palette_set) {
+                options.palette = palette;
+            }
+        }
+
+        if options.cheats && options.tui {
+            return Err("--cheats reads commands from the terminal --tui plays in".to_string());
+        }
+
+        if options.jit && !cfg!(feature = "jit") {
+            return Err(NO_JIT.to_string());
+        }
+
+        if options.gdb.is_some() && options.headless.is_some() {
+            return Err("--gdb needs a window or --tui to run in".to_string());
+        }
+
+        Ok(options)
+    }
+
+    pub fn synth(&self) -> Synth {
+        let mut synth = Synth::new();
+        synth.waveform = self.waveform;
+        synth.frequency = self.frequency;
+        synth.volume = self.volume.clamp(0.0, 1.0);
+        synth.set_envelope(self.attack, self.release);
+        synth
+    }
+
+    pub fn tracer(&self) -> Result<Option<Tracer>, String> {
+        let path = match &self.trace {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let mut tracer = Tracer::create(path)?;
+
+        if let Some((start, end)) = self.trace_range {
+            tracer.set_range(start, end);
+        }
+
+        if let Some(nibbles) = &self.trace_ops {
+            tracer.set_classes(nibbles);
+        }
+
+        Ok(Some(tracer))
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("invalid number '{}'", value))
+}
+
+// Pitches at or above half the sample rate can't be played back
+fn parse_frequency(value: &str) -> Result<f32, String> {
+    let frequency: f32 = parse_number(value)?;
+    let limit = SAMPLE_RATE as f32 / 2.0;
+
+    if frequency > 0.0 && frequency < limit {
+        Ok(frequency)
+    } else {
+        Err(format!("the frequency must be between 0 and {} Hz", limit))
+    }
+}
+
+fn parse_hex(value: &str) -> Result<u16, String> {
+    u16::from_str_radix(value.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("invalid address '{}'", value))
+}
+
+fn parse_range(value: &str) -> Result<(u16, u16), String> {
+    let (start, end) = value
+        .split_once('-')
+        .ok_or(format!("invalid range '{}', expected start-end", value))?;
+
+    Ok((parse_hex(start)?, parse_hex(end)?))
+}
+
+fn parse_nibbles(value: &str) -> Result<Vec<u8>, String> {
+    value
+        .split(',')
+        .map(|nibble| match u8::from_str_radix(nibble.trim(), 16) {
+            Ok(n) if n < 0x10 => Ok(n),
+            _ => Err(format!("invalid opcode class '{}'", nibble)),
+        })
+        .collect()
+}
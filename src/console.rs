@@ -0,0 +1,181 @@
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use chip_8::cheat::{Cheat, CheatList, Comparison, Mode, Search};
+use chip_8::core::chip::Chip8;
+
+const HELP: &str = "cheat commands:
+  search                  start a new search from the current memory
+  eq <value>              keep addresses now holding the value
+  same, changed, up, down compare with memory at the previous command
+  list                    show the remaining addresses and their values
+  freeze <addr> <value> [name]
+  poke <addr> <value> [name]
+  cheats                  show cheats
+  toggle <n>, remove <n>
+  save                    write cheats to the ROM's cheat file";
+
+const LIST_LIMIT: usize = 32;
+
+// Applies the ROM's cheats every frame and, when enabled, takes cheat
+// commands typed on stdin while the game runs
+pub struct CheatConsole {
+    cheats: CheatList,
+    path: String,
+    input: Option<Receiver<String>>,
+    search: Option<Search>,
+}
+
+impl CheatConsole {
+    pub fn new(rom_path: &str, interactive: bool) -> Result<CheatConsole, String> {
+        let path = CheatList::path_for(rom_path);
+
+        let cheats = if Path::new(&path).exists() {
+            CheatList::load(&path)?
+        } else {
+            CheatList::new()
+        };
+
+        let input = if interactive {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                for line in io::stdin().lock().lines().map_while(Result::ok) {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            println!("{}", HELP);
+            Some(receiver)
+        } else {
+            None
+        };
+
+        Ok(CheatConsole {
+            cheats,
+            path,
+            input,
+            search: None,
+        })
+    }
+
+    pub fn update(&mut self, chip: &mut Chip8) {
+        let lines: Vec<String> = match &self.input {
+            Some(input) => input.try_iter().collect(),
+            None => Vec::new(),
+        };
+
+        for line in lines {
+            if let Err(message) = self.run(line.trim(), chip) {
+                println!("{}", message);
+            }
+        }
+
+        self.cheats.apply(chip);
+    }
+
+    fn run(&mut self, line: &str, chip: &Chip8) -> Result<(), String> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(()),
+        };
+        let args: Vec<&str> = words.collect();
+        let memory = &chip.state().memory;
+
+        match command {
+            "search" => {
+                self.search = Some(Search::new(memory));
+                println!("{} candidates", memory.len());
+            }
+            "eq" | "same" | "changed" | "up" | "down" => {
+                let comparison = match command {
+                    "eq" => Comparison::Equal(parse_value(args.first())?),
+                    "same" => Comparison::Unchanged,
+                    "changed" => Comparison::Changed,
+                    "up" => Comparison::Increased,
+                    _ => Comparison::Decreased,
+                };
+
+                let search = self.search.as_mut().ok_or("start with search")?;
+                search.filter(memory, comparison);
+                println!("{} candidates", search.candidates().len());
+            }
+            "list" => {
+                let search = self.search.as_ref().ok_or("start with search")?;
+                for address in search.candidates().iter().take(LIST_LIMIT) {
+                    println!("  {:03X}  {}", address, memory[*address as usize]);
+                }
+            }
+            "freeze" | "poke" => {
+                let address = parse_address(args.first())?;
+                let value = parse_value(args.get(1))?;
+                let mode = if command == "freeze" {
+                    Mode::Freeze
+                } else {
+                    Mode::Poke
+                };
+
+                let name = args.get(2..).unwrap_or(&[]).join(" ");
+                self.cheats.add(Cheat::new(address, value, mode, &name));
+            }
+            "cheats" => {
+                for (index, cheat) in self.cheats.cheats().iter().enumerate() {
+                    println!(
+                        "  {}  {:03X}={:02X} {:?} {} {}",
+                        index,
+                        cheat.address,
+                        cheat.value,
+                        cheat.mode,
+                        if cheat.enabled { "on" } else { "off" },
+                        cheat.name
+                    );
+                }
+            }
+            "toggle" => {
+                let enabled = self
+                    .cheats
+                    .toggle(parse_index(args.first())?)
+                    .ok_or("no such cheat")?;
+                println!("{}", if enabled { "on" } else { "off" });
+            }
+            "remove" => {
+                self.cheats
+                    .remove(parse_index(args.first())?)
+                    .ok_or("no such cheat")?;
+            }
+            "save" => {
+                self.cheats.save(&self.path)?;
+                println!("saved to {}", self.path);
+            }
+            _ => println!("{}", HELP),
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_address(word: Option<&&str>) -> Result<u16, String> {
+    let word = word.ok_or("missing address")?;
+    u16::from_str_radix(word.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("invalid address '{}'", word))
+}
+
+// Values are decimal unless they start with 0x, as games show them
+fn parse_value(word: Option<&&str>) -> Result<u8, String> {
+    let word = word.ok_or("missing value")?;
+    match word.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => word.parse(),
+    }
+    .map_err(|_| format!("invalid value '{}'", word))
+}
+
+fn parse_index(word: Option<&&str>) -> Result<usize, String> {
+    let word = word.ok_or("missing cheat number")?;
+    word.parse()
+        .map_err(|_| format!("invalid cheat number '{}'", word))
+}
@@ -0,0 +1,19 @@
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// The memory an opcode reads or writes as data, as (access, start, length).
+// Must be called before the opcode runs, while `vi` still points at its operands.
+pub fn memory_access(code: u16, vi: u16) -> Option<(Access, u16, u16)> {
+    let x = (code >> 8) & 0xF;
+
+    match (code >> 12, code & 0xFF) {
+        (0xD, _) => Some((Access::Read, vi, code & 0xF)),
+        (0xF, 0x33) => Some((Access::Write, vi, 3)),
+        (0xF, 0x55) => Some((Access::Write, vi, x + 1)),
+        (0xF, 0x65) => Some((Access::Read, vi, x + 1)),
+        _ => None,
+    }
+}
@@ -1,124 +1,362 @@
-use sdl2::audio::{AudioDevice, AudioSpecDesired};
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::EventPump;
-use std::fs::File;
-use std::io::prelude::*;
-
-use crate::audio::SquareWave;
-use crate::screen::Screen;
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
 
+use crate::core::access::{memory_access, Access};
+use crate::core::display::Display;
 use crate::core::handlers;
-use crate::core::opcode::OpCode;
+use crate::core::instruction::Instruction;
+#[cfg(feature = "jit")]
+use crate::core::jit::Jit;
+use crate::core::keypad::Keypad;
 use crate::core::quirks::Quirks;
-use crate::core::state::ChipState;
+use crate::core::snapshot;
+use crate::core::state::{ChipState, ADDRESS_MASK};
+use crate::coverage::Coverage;
+use crate::patch;
+use crate::profile::Profiler;
+use crate::rom;
+use crate::trace::Tracer;
 
-const MEM_OFFSET: u16 = 0x200;
-const DEBUG: bool = false;
+pub const MEM_OFFSET: u16 = 0x200;
+const MEMORY_SIZE: usize = 4096;
 
 pub struct Chip8 {
     state: ChipState,
-    display: Screen,
-    sound_device: AudioDevice<SquareWave>,
-    event_pump: EventPump,
+    display: Display,
+    keypad: Keypad,
     quirks: Quirks,
+    sound_playing: bool,
+    cycles: u64,
+    frames: u64,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    rng: ChaCha12Rng,
+    // Instructions decoded at each address, cleared when memory changes
+    decoded: Vec<Option<Instruction>>,
+    #[cfg(feature = "jit")]
+    jit: Option<Jit>,
 }
 
 impl Chip8 {
     pub fn new() -> Chip8 {
-        // Initialize SDL2 and Event Pump
-        let sdl_context = sdl2::init().unwrap();
-        let event_pump = sdl_context.event_pump().unwrap();
+        Chip8::with_quirks(Quirks::for_chip8())
+    }
 
-        // Initialize SDL2 Audio Subsystem
-        let audio_subsystem = sdl_context.audio().unwrap();
+    pub fn with_quirks(quirks: Quirks) -> Chip8 {
+        Chip8 {
+            state: ChipState::init(),
+            display: Display::new(),
+            keypad: Keypad::new(),
+            quirks,
+            sound_playing: false,
+            cycles: 0,
+            frames: 0,
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            rng: ChaCha12Rng::from_entropy(),
+            decoded: vec![None; MEMORY_SIZE],
+            #[cfg(feature = "jit")]
+            jit: None,
+        }
+    }
 
-        let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
-            channels: Some(1),
-            samples: None,
-        };
+    // Applies a patch kept next to the ROM, if there is one
+    pub fn read_rom(&mut self, path: &str) -> Result<(), String> {
+        self.open_rom(path, None, patch::find_for(path).as_deref())?;
+        Ok(())
+    }
 
-        let device = audio_subsystem
-            .open_playback(None, &desired_spec, |spec| {
-                // initialize the audio callback
-                SquareWave::new(440.0 / spec.freq as f32, 0.0, 0.05)
-            })
-            .unwrap();
+    // Reads a raw ROM or one inside a zip archive, then patches it. Returns
+    // the ROM as loaded so callers can inspect it.
+    pub fn open_rom(
+        &mut self,
+        path: &str,
+        entry: Option<&str>,
+        patch: Option<&str>,
+    ) -> Result<Vec<u8>, String> {
+        let mut data = rom::read(path, entry)?;
 
-        // Return new instance
-        Chip8 {
-            state: ChipState::init(),
-            display: Screen::new(&sdl_context),
-            sound_device: device,
-            event_pump: event_pump,
-            quirks: Quirks::for_chip8(),
+        if let Some(patch) = patch {
+            data = patch::load(patch, &data)?;
         }
+
+        self.load_rom(&data)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        Ok(data)
     }
 
-    pub fn read_rom(&mut self, path: &String) {
-        let mut file = File::open(&path).unwrap();
-        let mut data = Vec::new();
-        file.read_to_end(&mut data).unwrap();
+    pub fn load_rom(&mut self, data: &[u8]) -> Result<(), String> {
+        rom::validate(data)?;
+
+        let start = MEM_OFFSET as usize;
+        self.state.memory[start..start + data.len()].copy_from_slice(data);
+        self.invalidate_all();
+        Ok(())
+    }
+
+    pub fn state(&self) -> &ChipState {
+        &self.state
+    }
 
-        for (index, byte) in data.iter_mut().enumerate() {
-            let address = index + MEM_OFFSET as usize;
-            self.state.memory[address] = *byte;
+    // Writes a byte from outside the program, such as a cheat, dropping only
+    // the code decoded from it
+    pub fn poke(&mut self, address: u16, value: u8) {
+        let address = address as usize;
+        if self.state.memory[address] != value {
+            self.state.memory[address] = value;
+            self.invalidate(address, address + 1);
         }
     }
 
-    pub fn render(&mut self) {
-        if self.state.should_draw {
-            self.display.render();
-            self.state.should_draw = false;
+    // Callers may write anywhere in memory, so decoded instructions are dropped
+    pub fn state_mut(&mut self) -> &mut ChipState {
+        self.invalidate_all();
+        &mut self.state
+    }
+
+    pub fn save_state(&self) -> Result<Vec<u8>, String> {
+        snapshot::encode(
+            &self.state,
+            &self.display,
+            &self.keypad,
+            &self.rng,
+            self.sound_playing,
+            self.cycles,
+        )
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let snapshot = snapshot::decode(data)?;
+
+        self.state = snapshot.state;
+        self.display = snapshot.display;
+        self.keypad = snapshot.keypad;
+        self.rng = snapshot.rng;
+        self.sound_playing = snapshot.sound_playing;
+        self.cycles = snapshot.cycles;
+        self.invalidate_all();
+
+        Ok(())
+    }
+
+    pub fn quirks(&mut self) -> &mut Quirks {
+        &mut self.quirks
+    }
+
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+
+    pub fn keypad(&mut self) -> &mut Keypad {
+        &mut self.keypad
+    }
+
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) {
+        self.coverage = coverage;
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    // Runs straight-line code compiled to native code. Tracing, profiling and
+    // coverage still go through the interpreter, which sees every instruction.
+    #[cfg(feature = "jit")]
+    pub fn set_jit(&mut self, enabled: bool) -> Result<(), String> {
+        self.jit = if enabled {
+            Some(Jit::new(self.quirks)?)
+        } else {
+            None
         };
+        Ok(())
+    }
+
+    // Makes CXNN repeat the same numbers on every run
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = ChaCha12Rng::seed_from_u64(seed);
+    }
+
+    // Instructions executed since power on
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Frames ended since power on
+    pub fn frames(&self) -> u64 {
+        self.frames
     }
 
     pub fn is_running(&self) -> bool {
         self.state.running
     }
 
-    pub fn fetch(&self) -> OpCode {
+    pub fn stop(&mut self) {
+        self.state.running = false;
+    }
+
+    pub fn is_sound_playing(&self) -> bool {
+        self.sound_playing
+    }
+
+    // Returns whether the screen changed since the last call
+    pub fn take_redraw(&mut self) -> bool {
+        let should_draw = self.state.should_draw;
+        self.state.should_draw = false;
+        should_draw
+    }
+
+    pub fn request_redraw(&mut self) {
+        self.state.should_draw = true;
+    }
+
+    fn fetch(&self) -> u16 {
         let addr = self.state.pc as usize;
-        let bytes = <[u8; 2]>::try_from(&self.state.memory[addr..=addr + 1]).unwrap();
-        OpCode::from(u16::from_be_bytes(bytes))
+        let next = (addr + 1) % MEMORY_SIZE;
+        u16::from_be_bytes([self.state.memory[addr], self.state.memory[next]])
+    }
+
+    fn decode(&mut self, code: u16) -> Instruction {
+        let pc = self.state.pc as usize;
+
+        *self.decoded[pc].get_or_insert_with(|| Instruction::decode(code))
+    }
+
+    // Drops what was decoded or compiled from the bytes in start..end,
+    // including an instruction starting just before them. Public for memory
+    // written behind the emulator's back, e.g. by a libretro frontend.
+    pub fn invalidate(&mut self, start: usize, end: usize) {
+        // Writes past the end of memory wrap to its start
+        if end > MEMORY_SIZE {
+            self.invalidate(0, end - MEMORY_SIZE);
+        }
+        // An instruction at the last address runs on into the first
+        if start == 0 {
+            self.decoded[MEMORY_SIZE - 1] = None;
+        }
+
+        let start = start.min(MEMORY_SIZE);
+        let end = end.min(MEMORY_SIZE);
+        self.decoded[start.saturating_sub(1)..end].fill(None);
+
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            jit.invalidate(start, end);
+        }
+    }
+
+    fn invalidate_all(&mut self) {
+        self.decoded.fill(None);
+
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            jit.clear();
+        }
     }
 
     pub fn step(&mut self, ipf: u32) {
-        // If not waiting for input key
-        if !self.state.should_wait {
-            // Check events, if exit then set running to false
-            for event in self.event_pump.poll_iter() {
-                match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => self.state.running = false,
-                    _ => {}
-                }
+        // Run N instructions per seconds
+        let mut left = ipf;
+        while left > 0 {
+            #[cfg(feature = "jit")]
+            if let Some(count) = self.run_compiled(left) {
+                left -= count;
+                continue;
+            }
+
+            left -= 1;
+            if self.step_instruction() {
+                break;
             }
         }
 
-        // Run N instructions per seconds
-        for _ in 0..ipf {
-            let code = self.fetch();
+        self.end_frame();
+    }
 
-            handlers::decode_and_run(
-                code,
-                &mut self.state,
-                &self.quirks,
-                &mut self.display,
-                &mut self.event_pump,
-            );
+    // Runs the compiled block at PC, returns how many instructions it ran
+    #[cfg(feature = "jit")]
+    fn run_compiled(&mut self, left: u32) -> Option<u32> {
+        if self.tracer.is_some() || self.profiler.is_some() || self.coverage.is_some() {
+            return None;
+        }
+
+        let count = self
+            .jit
+            .as_mut()?
+            .run(&mut self.state, &self.quirks, left)?;
+        self.cycles += count as u64;
+        Some(count)
+    }
+
+    // Runs a single instruction, returns whether the rest of the frame should be skipped
+    pub fn step_instruction(&mut self) -> bool {
+        // Debuggers and snapshots can leave PC anywhere
+        self.state.pc &= ADDRESS_MASK;
+        let raw = self.fetch();
+        let instruction = self.decode(raw);
+        let is_draw = matches!(instruction, Instruction::Draw(..));
+        let pc = self.state.pc;
+        let registers = self.state.registers;
+
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(pc, raw, self.state.vi);
+        }
+
+        if let Some((Access::Write, start, length)) = memory_access(raw, self.state.vi) {
+            self.invalidate(start as usize, start as usize + length as usize);
+        }
+
+        handlers::execute(
+            instruction,
+            &mut self.state,
+            &self.quirks,
+            &mut self.display,
+            &mut self.keypad,
+            &mut self.rng,
+        );
+
+        if !self.state.did_jump && !self.state.should_wait {
+            self.state.pc = (self.state.pc + 2) & ADDRESS_MASK;
+        };
 
-            if !self.state.did_jump && !self.state.should_wait {
-                self.state.pc += 2;
-            };
+        self.state.did_jump = false;
+
+        if let Some(tracer) = &mut self.tracer {
+            if tracer.wants(pc, raw) {
+                tracer.record(self.cycles, pc, raw, &registers, &self.state);
+            }
+        }
 
-            self.state.did_jump = false;
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(pc, raw);
         }
 
+        self.cycles += 1;
+
+        // Leave the rest of the frame for after vblank once a sprite is drawn
+        is_draw && self.quirks.has_display_wait()
+    }
+
+    // Everything that happens once per 60 Hz frame after the instructions ran
+    pub fn end_frame(&mut self) {
+        self.frames += 1;
+
+        // Releases not picked up by FX0A during this frame are dropped
+        self.keypad.clear_released();
+
         // Decrease delay timer
         if self.state.delay_timer > 0 {
             self.state.delay_timer -= 1;
@@ -126,17 +364,89 @@ impl Chip8 {
 
         // Decrese sound timer and play sound until reach zero
         if self.state.sound_timer > 0 {
-            self.sound_device.resume();
+            self.sound_playing = true;
             self.state.sound_timer -= 1;
         } else {
-            self.sound_device.pause();
+            self.sound_playing = false;
         }
+    }
+
+    // The opcode at PC, without running it
+    pub fn peek_opcode(&self) -> u16 {
+        self.fetch()
+    }
+}
 
-        // Render frame
-        self.render();
+impl Default for Chip8 {
+    fn default() -> Self {
+        Chip8::new()
+    }
+}
 
-        if DEBUG {
-            println!("{:?}", self.state);
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_code_written_after_it_was_decoded() {
+        // 200: CALL 20C  202: LD V0, 60  204: LD V1, 05  206: LD I, 20C
+        // 208: LD [I], V1  20A: CALL 20C  20C: LD V2, 3  20E: RET
+        let rom = [
+            0x22, 0x0C, 0x60, 0x60, 0x61, 0x05, 0xA2, 0x0C, 0xF1, 0x55, 0x22, 0x0C, 0x62, 0x03,
+            0x00, 0xEE,
+        ];
+        let mut chip = Chip8::new();
+        chip.load_rom(&rom).unwrap();
+
+        for _ in 0..10 {
+            chip.step_instruction();
+        }
+
+        // 20C now holds LD V0, 5
+        assert_eq!(chip.state().registers[0], 5);
+        assert_eq!(chip.state().registers[2], 3);
+    }
+
+    #[test]
+    fn runs_roms_that_leave_memory_or_the_stack() {
+        // RET with nothing to return to, JP FFE, LD I, FFF then LD B, V0
+        let roms: [&[u8]; 3] = [
+            &[0x00, 0xEE],
+            &[0x1F, 0xFE],
+            &[0xAF, 0xFF, 0xF0, 0x33, 0x12, 0x04],
+        ];
+
+        for rom in roms {
+            let mut chip = Chip8::new();
+            chip.load_rom(rom).unwrap();
+            for _ in 0..10 {
+                chip.step(100);
+            }
+        }
+    }
+
+    #[test]
+    fn halts_on_a_return_with_an_empty_stack() {
+        let mut chip = Chip8::new();
+        chip.load_rom(&[0x00, 0xEE]).unwrap();
+        chip.step(10);
+
+        assert!(!chip.is_running());
+        assert_eq!(chip.state().pc, 0x200);
+    }
+
+    #[test]
+    fn runs_an_instruction_across_the_end_of_memory() {
+        // FFF: LD V5, 42 split across FFF and 000
+        let mut chip = Chip8::new();
+        chip.load_rom(&[0x1F, 0xFF]).unwrap();
+        chip.poke(0xFFF, 0x65);
+        chip.poke(0x000, 0x42);
+
+        chip.step_instruction();
+        chip.step_instruction();
+
+        assert_eq!(chip.state().registers[5], 0x42);
+        assert_eq!(chip.state().pc, 0x001);
     }
 }
@@ -0,0 +1,28 @@
+pub const SCREEN_WIDTH: u8 = 64;
+pub const SCREEN_HEIGHT: usize = 32;
+
+pub struct Display {
+    pub screen_memory: [u64; SCREEN_HEIGHT],
+}
+
+impl Display {
+    pub fn new() -> Display {
+        Display {
+            screen_memory: [0u64; SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.screen_memory = [0u64; SCREEN_HEIGHT];
+    }
+
+    pub fn is_lit(&self, column: usize, row: usize) -> bool {
+        self.screen_memory[row] & (1u64 << (SCREEN_WIDTH as usize - 1 - column)) != 0
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Display::new()
+    }
+}
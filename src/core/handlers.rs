@@ -1,57 +1,81 @@
-use std::collections::HashMap;
-
-use crate::core::opcode::OpCode;
+use crate::core::display::{Display, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::core::instruction::Instruction;
+use crate::core::keypad::Keypad;
 use crate::core::quirks::Quirks;
-use crate::core::state::ChipState;
-use crate::screen::{Screen, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::core::state::{ChipState, ADDRESS_MASK, STACK_SIZE};
 use rand::Rng;
-use sdl2::event::Event;
-use sdl2::keyboard::{Keycode, Scancode};
-use sdl2::EventPump;
 
-pub fn decode_and_run(
-    opcode: OpCode,
+pub fn execute(
+    instruction: Instruction,
     state: &mut ChipState,
     quirks: &Quirks,
-    screen: &mut Screen,
-    events: &mut EventPump,
+    display: &mut Display,
+    keypad: &mut Keypad,
+    rng: &mut impl Rng,
 ) {
-    match opcode {
-        OpCode(0, 0, 0xE, 0) => run_00e0(screen),
-        OpCode(0, 0, 0xE, 0xE) => run_00ee(state),
-        OpCode(1, _, _, _) => run_1nnn(opcode.get_3n(), state),
-        OpCode(2, _, _, _) => run_2nnn(opcode.get_3n(), state),
-        OpCode(3, x, _, _) => run_3xnn(x.into(), opcode.get_2n(), state),
-        OpCode(4, x, _, _) => run_4xnn(x.into(), opcode.get_2n(), state),
-        OpCode(5, x, y, 0) => run_5xy0(x.into(), y.into(), state),
-        OpCode(6, x, _, _) => run_6xnn(x.into(), opcode.get_2n(), state),
-        OpCode(7, x, _, _) => run_7xnn(x.into(), opcode.get_2n(), state),
-        OpCode(9, x, y, 0) => run_9xy0(x.into(), y.into(), state),
-        OpCode(0xA, _, _, _) => run_annn(opcode.get_3n(), state),
-        OpCode(0xB, x, _, _) => run_bnnn(x.into(), opcode.get_3n(), state, !quirks.has_jumping()),
-        OpCode(0xC, x, _, _) => run_cxnn(x.into(), opcode.get_2n(), state),
-        OpCode(0xD, x, y, n) => run_dxyn(x.into(), y.into(), n, state, screen),
-        OpCode(0xE, x, 9, 0xE) => run_ex9e(x.into(), state, events),
-        OpCode(0xE, x, 0xA, 1) => run_exa1(x.into(), state, events),
-        OpCode(0xF, x, _, _) => run_fxnn(x.into(), opcode.get_2n(), state, events),
+    let index_quirk = quirks.has_increment_index();
+
+    match instruction {
+        Instruction::Clear => run_00e0(display),
+        Instruction::Return => run_00ee(state),
+        Instruction::Jump(nnn) => run_1nnn(nnn, state),
+        Instruction::Call(nnn) => run_2nnn(nnn, state),
+        Instruction::SkipEqual(x, nn) => run_3xnn(x.into(), nn, state),
+        Instruction::SkipNotEqual(x, nn) => run_4xnn(x.into(), nn, state),
+        Instruction::SkipEqualRegisters(x, y) => run_5xy0(x.into(), y.into(), state),
+        Instruction::Load(x, nn) => run_6xnn(x.into(), nn, state),
+        Instruction::Add(x, nn) => run_7xnn(x.into(), nn, state),
+        Instruction::SkipNotEqualRegisters(x, y) => run_9xy0(x.into(), y.into(), state),
+        Instruction::LoadIndex(nnn) => run_annn(nnn, state),
+        Instruction::JumpOffset(x, nnn) => run_bnnn(x.into(), nnn, state, !quirks.has_jumping()),
+        Instruction::Random(x, nn) => run_cxnn(x.into(), nn, state, rng),
+        Instruction::Draw(x, y, n) => {
+            run_dxyn(x.into(), y.into(), n, state, display, quirks.has_clipping())
+        }
+        Instruction::SkipPressed(x) => run_ex9e(x.into(), state, keypad),
+        Instruction::SkipNotPressed(x) => run_exa1(x.into(), state, keypad),
+        Instruction::ReadDelay(x) => run_fxnn(x.into(), 0x07, state, keypad, index_quirk),
+        Instruction::WaitKey(x) => run_fxnn(x.into(), 0x0A, state, keypad, index_quirk),
+        Instruction::SetDelay(x) => run_fxnn(x.into(), 0x15, state, keypad, index_quirk),
+        Instruction::SetSound(x) => run_fxnn(x.into(), 0x18, state, keypad, index_quirk),
+        Instruction::AddIndex(x) => run_fxnn(x.into(), 0x1E, state, keypad, index_quirk),
+        Instruction::LoadFont(x) => run_fxnn(x.into(), 0x29, state, keypad, index_quirk),
+        Instruction::StoreBcd(x) => run_fxnn(x.into(), 0x33, state, keypad, index_quirk),
+        Instruction::StoreRegisters(x) => run_fxnn(x.into(), 0x55, state, keypad, index_quirk),
+        Instruction::LoadRegisters(x) => run_fxnn(x.into(), 0x65, state, keypad, index_quirk),
         // Logic Operations
-        OpCode(8, x, y, 0) => run_8xy0(x.into(), y.into(), state),
-        OpCode(8, x, y, 1) => run_8xy1(x.into(), y.into(), state),
-        OpCode(8, x, y, 2) => run_8xy2(x.into(), y.into(), state),
-        OpCode(8, x, y, 3) => run_8xy3(x.into(), y.into(), state),
-        OpCode(8, x, y, 4) => run_8xy4(x.into(), y.into(), state),
-        OpCode(8, x, y, 5) => run_8xy5(x.into(), y.into(), state),
-        OpCode(8, x, y, n) => run_8xyn(x.into(), y.into(), n, state, quirks.has_shifting()),
-        _ => {}
+        Instruction::Move(x, y) => run_8xy0(x.into(), y.into(), state),
+        Instruction::Or(x, y) => run_8xy1(x.into(), y.into(), state, quirks.has_vf_reset()),
+        Instruction::And(x, y) => run_8xy2(x.into(), y.into(), state, quirks.has_vf_reset()),
+        Instruction::Xor(x, y) => run_8xy3(x.into(), y.into(), state, quirks.has_vf_reset()),
+        Instruction::AddRegisters(x, y) => run_8xy4(x.into(), y.into(), state),
+        Instruction::Sub(x, y) => run_8xy5(x.into(), y.into(), state),
+        Instruction::ShiftRight(x, y) => {
+            run_8xyn(x.into(), y.into(), 0x6, state, quirks.has_shifting())
+        }
+        Instruction::SubReverse(x, y) => {
+            run_8xyn(x.into(), y.into(), 0x7, state, quirks.has_shifting())
+        }
+        Instruction::ShiftLeft(x, y) => {
+            run_8xyn(x.into(), y.into(), 0xE, state, quirks.has_shifting())
+        }
+        Instruction::Unknown(_) => {}
     }
 }
 
-fn run_00e0(screen: &mut Screen) {
-    screen.clear()
+fn run_00e0(display: &mut Display) {
+    display.clear()
 }
 
+// Returning with nothing on the stack halts on the 00EE
 fn run_00ee(state: &mut ChipState) {
-    state.pc = state.stack.pop().unwrap()
+    match state.stack.pop() {
+        Some(address) => state.pc = address,
+        None => {
+            state.running = false;
+            state.did_jump = true;
+        }
+    }
 }
 
 fn run_1nnn(nnn: u16, state: &mut ChipState) {
@@ -60,6 +84,13 @@ fn run_1nnn(nnn: u16, state: &mut ChipState) {
 }
 
 fn run_2nnn(nnn: u16, state: &mut ChipState) {
+    // Halts on the call instead of growing the stack without end
+    if state.stack.len() >= STACK_SIZE {
+        state.running = false;
+        state.did_jump = true;
+        return;
+    }
+
     state.stack.push(state.pc);
     state.pc = nnn;
     state.did_jump = true;
@@ -102,22 +133,30 @@ fn run_7xnn(x: usize, nn: u8, state: &mut ChipState) {
 
 fn run_8xy0(x: usize, y: usize, state: &mut ChipState) {
     state.registers[x] = state.registers[y];
-    state.registers[15] = 0;
 }
 
-fn run_8xy1(x: usize, y: usize, state: &mut ChipState) {
-    state.registers[x] = state.registers[x] | state.registers[y];
-    state.registers[15] = 0;
+fn run_8xy1(x: usize, y: usize, state: &mut ChipState, reset_vf: bool) {
+    state.registers[x] |= state.registers[y];
+
+    if reset_vf {
+        state.registers[15] = 0;
+    }
 }
 
-fn run_8xy2(x: usize, y: usize, state: &mut ChipState) {
-    state.registers[x] = state.registers[x] & state.registers[y];
-    state.registers[15] = 0;
+fn run_8xy2(x: usize, y: usize, state: &mut ChipState, reset_vf: bool) {
+    state.registers[x] &= state.registers[y];
+
+    if reset_vf {
+        state.registers[15] = 0;
+    }
 }
 
-fn run_8xy3(x: usize, y: usize, state: &mut ChipState) {
-    state.registers[x] = state.registers[x] ^ state.registers[y];
-    state.registers[15] = 0;
+fn run_8xy3(x: usize, y: usize, state: &mut ChipState, reset_vf: bool) {
+    state.registers[x] ^= state.registers[y];
+
+    if reset_vf {
+        state.registers[15] = 0;
+    }
 }
 
 fn run_8xy4(x: usize, y: usize, state: &mut ChipState) {
@@ -149,10 +188,6 @@ fn run_8xy5(x: usize, y: usize, state: &mut ChipState) {
     state.registers[15] = carry;
 }
 
-fn run_8xy6() {}
-fn run_8xy7() {}
-fn run_8xye() {}
-
 fn run_8xyn(x: usize, y: usize, n: u8, state: &mut ChipState, change_x: bool) {
     let vx = state.registers[x];
     let vy = state.registers[y];
@@ -227,36 +262,44 @@ fn run_annn(nnn: u16, state: &mut ChipState) {
 fn run_bnnn(x: usize, nnn: u16, state: &mut ChipState, use_x: bool) {
     let x = if use_x { x } else { 0 };
 
-    let address = nnn + state.registers[x as usize] as u16;
+    let address = nnn + state.registers[x] as u16;
 
-    state.pc = address;
+    state.pc = address & ADDRESS_MASK;
     state.did_jump = true;
 }
 
-fn run_cxnn(x: usize, nn: u8, state: &mut ChipState) {
-    let mut rng = rand::thread_rng();
-
+fn run_cxnn(x: usize, nn: u8, state: &mut ChipState, rng: &mut impl Rng) {
     let random: u8 = rng.gen();
 
     state.registers[x] = random & nn;
 }
 
-fn run_dxyn(x: usize, y: usize, n: u8, state: &mut ChipState, screen: &mut Screen) {
+fn run_dxyn(
+    x: usize,
+    y: usize,
+    n: u8,
+    state: &mut ChipState,
+    display: &mut Display,
+    clipping: bool,
+) {
     let vx = state.registers[x] & (SCREEN_WIDTH - 1);
     let vy = state.registers[y] & (SCREEN_HEIGHT as u8 - 1);
 
     state.registers[15] = 0;
 
     for index in 0..n {
-        if index + vy <= 31 {
+        // Without clipping, sprites going off an edge come back on the other side
+        if index + vy <= 31 || !clipping {
             let wrap_pos = (vy + index) & (SCREEN_HEIGHT - 1) as u8;
-            let line = &mut screen.screen_memory[wrap_pos as usize];
+            let line = &mut display.screen_memory[wrap_pos as usize];
 
-            let address = state.vi + index as u16;
+            let sprite = state.memory[state.index_address(index as u16)] as u64;
 
-            let sprite = state.memory[address as usize] as u64;
-
-            let offset_sprite = sprite << (SCREEN_WIDTH - 8) >> vx;
+            let offset_sprite = if clipping {
+                sprite << (SCREEN_WIDTH - 8) >> vx
+            } else {
+                (sprite << (SCREEN_WIDTH - 8)).rotate_right(vx as u32)
+            };
 
             let new_line = *line ^ offset_sprite;
 
@@ -276,137 +319,796 @@ fn run_dxyn(x: usize, y: usize, n: u8, state: &mut ChipState, screen: &mut Scree
     state.should_draw = true;
 }
 
-fn run_ex9e(x: usize, state: &mut ChipState, events: &mut EventPump) {
+fn run_ex9e(x: usize, state: &mut ChipState, keypad: &Keypad) {
     let vx = state.registers[x];
-    let key = u8_to_scancode(vx);
 
-    if events
-        .keyboard_state()
-        .pressed_scancodes()
-        .any(|code| code == key)
-    {
+    if keypad.is_pressed(vx) {
         state.skip()
     }
 }
 
-fn run_exa1(x: usize, state: &mut ChipState, events: &mut EventPump) {
+fn run_exa1(x: usize, state: &mut ChipState, keypad: &Keypad) {
     let vx = state.registers[x];
-    let key = u8_to_scancode(vx);
 
-    if events
-        .keyboard_state()
-        .pressed_scancodes()
-        .all(|code| code != key)
-    {
+    if !keypad.is_pressed(vx) {
         state.skip()
     }
 }
 
-fn run_fxnn(x: usize, nn: u8, state: &mut ChipState, events: &mut EventPump) {
-    fn wait_for_key(x: usize, state: &mut ChipState, events: &mut EventPump) {
+fn run_fxnn(x: usize, nn: u8, state: &mut ChipState, keypad: &mut Keypad, increment_index: bool) {
+    fn wait_for_key(x: usize, state: &mut ChipState, keypad: &mut Keypad) {
         state.should_wait = true;
 
-        for event in events.poll_iter() {
-            match event {
-                Event::KeyUp {
-                    keycode: Some(keycode),
-                    ..
-                } => match keycode_to_u8(keycode) {
-                    Some(key) => {
-                        state.registers[x as usize] = key;
-                        state.should_wait = false;
-                    }
-                    _ => {}
-                },
-                _ => {}
-            }
+        if let Some(key) = keypad.take_released() {
+            state.registers[x] = key;
+            state.should_wait = false;
         }
     }
 
     fn load_to_memory(x: u16, state: &mut ChipState, update_vi: bool) {
         for i in 0..=x {
-            let mem_address = state.vi + i;
-            state.memory[mem_address as usize] = state.registers[i as usize];
+            let mem_address = state.index_address(i);
+            state.memory[mem_address] = state.registers[i as usize];
         }
 
         if update_vi {
-            state.vi += x + 1;
+            state.vi = state.index_address(x + 1) as u16;
         }
     }
 
-    fn load_from_memory(x: u16, state: &mut ChipState) {
+    fn load_from_memory(x: u16, state: &mut ChipState, update_vi: bool) {
         for i in 0..=x {
-            let addr = state.vi + i;
-            state.registers[i as usize] = state.memory[addr as usize];
+            let addr = state.index_address(i);
+            state.registers[i as usize] = state.memory[addr];
+        }
+
+        if update_vi {
+            state.vi = state.index_address(x + 1) as u16;
         }
     }
 
     fn binary_coded_decimal(vx: u8, state: &mut ChipState) {
-        let address = state.vi;
-
-        state.memory[(address) as usize] = vx / 100;
-        state.memory[(address + 1) as usize] = (vx % 100) / 10;
-        state.memory[(address + 2) as usize] = vx % 10;
+        state.memory[state.index_address(0)] = vx / 100;
+        state.memory[state.index_address(1)] = (vx % 100) / 10;
+        state.memory[state.index_address(2)] = vx % 10;
     }
 
     let vx = &mut state.registers[x];
 
     match nn {
         0x07 => *vx = state.delay_timer,
-        0x0A => wait_for_key(x, state, events),
+        0x0A => wait_for_key(x, state, keypad),
         0x15 => state.delay_timer = *vx,
         0x18 => state.sound_timer = *vx,
-        0x1E => state.vi += *vx as u16,
-        0x29 => state.vi = 0x50 + (*vx * 5) as u16,
+        0x1E => state.vi = state.vi.wrapping_add(*vx as u16) & ADDRESS_MASK,
+        0x29 => state.vi = 0x50 + (*vx & 0xF) as u16 * 5,
         0x33 => binary_coded_decimal(*vx, state),
-        0x55 => load_to_memory(x as u16, state, true),
-        0x65 => load_from_memory(x as u16, state),
+        0x55 => load_to_memory(x as u16, state, increment_index),
+        0x65 => load_from_memory(x as u16, state, increment_index),
         _ => {}
     }
 }
 
-fn u8_to_scancode(key: u8) -> Scancode {
-    let key_mapping = HashMap::from([
-        (0x1, Scancode::Num1),
-        (0x2, Scancode::Num2),
-        (0x3, Scancode::Num3),
-        (0xC, Scancode::Num4),
-        (0x4, Scancode::Q),
-        (0x5, Scancode::W),
-        (0x6, Scancode::E),
-        (0xD, Scancode::R),
-        (0x7, Scancode::A),
-        (0x8, Scancode::S),
-        (0x9, Scancode::D),
-        (0xE, Scancode::F),
-        (0xA, Scancode::Z),
-        (0x0, Scancode::X),
-        (0xB, Scancode::C),
-        (0xF, Scancode::V),
-    ]);
-
-    *key_mapping.get(&key).unwrap()
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn state() -> ChipState {
+        ChipState::init()
+    }
+
+    fn run(code: u16, state: &mut ChipState, display: &mut Display, keypad: &mut Keypad) {
+        execute(
+            Instruction::decode(code),
+            state,
+            &Quirks::for_chip8(),
+            display,
+            keypad,
+            &mut StdRng::seed_from_u64(0),
+        );
+    }
+
+    #[test]
+    fn test_00e0_clears_display() {
+        let mut display = Display::new();
+        display.screen_memory = [u64::MAX; SCREEN_HEIGHT];
+
+        run_00e0(&mut display);
+
+        assert_eq!(display.screen_memory, [0u64; SCREEN_HEIGHT]);
+    }
+
+    #[test]
+    fn test_00ee_returns_to_caller() {
+        let mut state = state();
+        state.stack.push(0x204);
+
+        run_00ee(&mut state);
+
+        assert_eq!(state.pc, 0x204);
+        assert!(state.stack.is_empty());
+    }
+
+    #[test]
+    fn test_00ee_halts_with_an_empty_stack() {
+        let mut state = state();
+        state.pc = 0x206;
+
+        run_00ee(&mut state);
+
+        assert_eq!(state.pc, 0x206);
+        assert!(state.did_jump);
+        assert!(!state.running);
+    }
+
+    #[test]
+    fn test_1nnn_jumps() {
+        let mut state = state();
+
+        run_1nnn(0x345, &mut state);
+
+        assert_eq!(state.pc, 0x345);
+        assert!(state.did_jump);
+    }
+
+    #[test]
+    fn test_2nnn_pushes_and_jumps() {
+        let mut state = state();
+        state.pc = 0x210;
+
+        run_2nnn(0x400, &mut state);
+
+        assert_eq!(state.pc, 0x400);
+        assert_eq!(state.stack, vec![0x210]);
+        assert!(state.did_jump);
+    }
+
+    #[test]
+    fn test_2nnn_halts_with_a_full_stack() {
+        let mut state = state();
+        state.pc = 0x210;
+        state.stack = vec![0x202; STACK_SIZE];
+
+        run_2nnn(0x400, &mut state);
+
+        assert_eq!(state.pc, 0x210);
+        assert_eq!(state.stack.len(), STACK_SIZE);
+        assert!(state.did_jump);
+        assert!(!state.running);
+    }
+
+    #[test]
+    fn test_3xnn_skips_when_equal() {
+        let mut state = state();
+        state.registers[2] = 0x42;
+
+        run_3xnn(2, 0x42, &mut state);
+        assert_eq!(state.pc, 0x202);
+
+        run_3xnn(2, 0x43, &mut state);
+        assert_eq!(state.pc, 0x202);
+    }
+
+    #[test]
+    fn test_4xnn_skips_when_not_equal() {
+        let mut state = state();
+        state.registers[2] = 0x42;
+
+        run_4xnn(2, 0x42, &mut state);
+        assert_eq!(state.pc, 0x200);
+
+        run_4xnn(2, 0x43, &mut state);
+        assert_eq!(state.pc, 0x202);
+    }
+
+    #[test]
+    fn test_5xy0_skips_when_registers_equal() {
+        let mut state = state();
+        state.registers[1] = 7;
+        state.registers[2] = 7;
+        state.registers[3] = 8;
+
+        run_5xy0(1, 2, &mut state);
+        assert_eq!(state.pc, 0x202);
+
+        run_5xy0(1, 3, &mut state);
+        assert_eq!(state.pc, 0x202);
+    }
+
+    #[test]
+    fn test_6xnn_sets_register() {
+        let mut state = state();
+
+        run_6xnn(0xA, 0x99, &mut state);
+
+        assert_eq!(state.registers[0xA], 0x99);
+    }
+
+    #[test]
+    fn test_7xnn_adds_without_carry() {
+        let mut state = state();
+        state.registers[1] = 0xFF;
+        state.registers[15] = 0;
+
+        run_7xnn(1, 0x02, &mut state);
+
+        assert_eq!(state.registers[1], 0x01);
+        assert_eq!(state.registers[15], 0);
+    }
+
+    #[test]
+    fn test_8xy0_copies_register() {
+        let mut state = state();
+        state.registers[2] = 0x12;
+        state.registers[15] = 1;
+
+        run_8xy0(1, 2, &mut state);
+
+        assert_eq!(state.registers[1], 0x12);
+        assert_eq!(state.registers[15], 1);
+    }
+
+    #[test]
+    fn test_8xy1_8xy2_8xy3_logic() {
+        let mut state = state();
+
+        for reset_vf in [true, false] {
+            for (run, expected) in [
+                (run_8xy1 as fn(usize, usize, &mut ChipState, bool), 0b1110),
+                (run_8xy2, 0b1000),
+                (run_8xy3, 0b0110),
+            ] {
+                state.registers[1] = 0b1100;
+                state.registers[2] = 0b1010;
+                state.registers[15] = 1;
+
+                run(1, 2, &mut state, reset_vf);
+
+                assert_eq!(state.registers[1], expected);
+                assert_eq!(state.registers[15], if reset_vf { 0 } else { 1 });
+            }
+        }
+    }
+
+    #[test]
+    fn test_8xy4_sets_carry() {
+        let mut state = state();
+        state.registers[1] = 0xF0;
+        state.registers[2] = 0x20;
+
+        run_8xy4(1, 2, &mut state);
+        assert_eq!(state.registers[1], 0x10);
+        assert_eq!(state.registers[15], 1);
+
+        state.registers[2] = 0x01;
+        run_8xy4(1, 2, &mut state);
+        assert_eq!(state.registers[1], 0x11);
+        assert_eq!(state.registers[15], 0);
+    }
+
+    #[test]
+    fn test_8xy4_flag_wins_over_vf_result() {
+        let mut state = state();
+        state.registers[15] = 0xFF;
+        state.registers[1] = 0x01;
+
+        run_8xy4(15, 1, &mut state);
+
+        assert_eq!(state.registers[15], 1);
+    }
+
+    #[test]
+    fn test_8xy5_sets_not_borrow() {
+        let mut state = state();
+        state.registers[1] = 0x10;
+        state.registers[2] = 0x01;
+
+        run_8xy5(1, 2, &mut state);
+        assert_eq!(state.registers[1], 0x0F);
+        assert_eq!(state.registers[15], 1);
+
+        state.registers[2] = 0x10;
+        run_8xy5(1, 2, &mut state);
+        assert_eq!(state.registers[1], 0xFF);
+        assert_eq!(state.registers[15], 0);
+    }
+
+    #[test]
+    fn test_8xy5_equal_values_do_not_borrow() {
+        let mut state = state();
+        state.registers[1] = 0x33;
+        state.registers[2] = 0x33;
+
+        run_8xy5(1, 2, &mut state);
+
+        assert_eq!(state.registers[1], 0);
+        assert_eq!(state.registers[15], 1);
+    }
+
+    #[test]
+    fn test_8xy7_sets_not_borrow() {
+        let mut state = state();
+        state.registers[1] = 0x01;
+        state.registers[2] = 0x10;
+
+        run_8xyn(1, 2, 7, &mut state, true);
+        assert_eq!(state.registers[1], 0x0F);
+        assert_eq!(state.registers[15], 1);
+
+        state.registers[1] = 0x11;
+        run_8xyn(1, 2, 7, &mut state, true);
+        assert_eq!(state.registers[1], 0xFF);
+        assert_eq!(state.registers[15], 0);
+    }
+
+    #[test]
+    fn test_8xy6_shifts_vy_with_shifting_quirk() {
+        let mut state = state();
+        state.registers[1] = 0b1000_0000;
+        state.registers[2] = 0b0000_0101;
+
+        run_8xyn(1, 2, 6, &mut state, true);
+
+        assert_eq!(state.registers[1], 0b0000_0010);
+        assert_eq!(state.registers[15], 1);
+    }
+
+    #[test]
+    fn test_8xy6_shifts_vx_without_shifting_quirk() {
+        let mut state = state();
+        state.registers[1] = 0b1000_0000;
+        state.registers[2] = 0b0000_0101;
+
+        run_8xyn(1, 2, 6, &mut state, false);
+
+        assert_eq!(state.registers[1], 0b0100_0000);
+        assert_eq!(state.registers[15], 0);
+    }
+
+    #[test]
+    fn test_8xye_shifts_vy_with_shifting_quirk() {
+        let mut state = state();
+        state.registers[1] = 0b0000_0001;
+        state.registers[2] = 0b1000_0001;
+
+        run_8xyn(1, 2, 0xE, &mut state, true);
+
+        assert_eq!(state.registers[1], 0b0000_0010);
+        assert_eq!(state.registers[15], 1);
+    }
 
-fn keycode_to_u8(key: Keycode) -> Option<u8> {
-    let key_mapping: HashMap<Keycode, u8> = HashMap::from([
-        (Keycode::Num1, 0x1),
-        (Keycode::Num2, 0x2),
-        (Keycode::Num3, 0x3),
-        (Keycode::Num4, 0xC),
-        (Keycode::Q, 0x4),
-        (Keycode::W, 0x5),
-        (Keycode::E, 0x6),
-        (Keycode::R, 0xD),
-        (Keycode::A, 0x7),
-        (Keycode::S, 0x8),
-        (Keycode::D, 0x9),
-        (Keycode::F, 0xE),
-        (Keycode::Z, 0xA),
-        (Keycode::X, 0x0),
-        (Keycode::C, 0xB),
-        (Keycode::V, 0xF),
-    ]);
-
-    key_mapping.get(&key).copied()
+    #[test]
+    fn test_8xye_shifts_vx_without_shifting_quirk() {
+        let mut state = state();
+        state.registers[1] = 0b0000_0001;
+        state.registers[2] = 0b1000_0001;
+
+        run_8xyn(1, 2, 0xE, &mut state, false);
+
+        assert_eq!(state.registers[1], 0b0000_0010);
+        assert_eq!(state.registers[15], 0);
+    }
+
+    #[test]
+    fn test_8xyn_matches_dedicated_handlers() {
+        for n in 0..=5 {
+            let mut dedicated = state();
+            let mut generic = state();
+
+            for state in [&mut dedicated, &mut generic] {
+                state.registers[1] = 0xC8;
+                state.registers[2] = 0x64;
+            }
+
+            match n {
+                0 => run_8xy0(1, 2, &mut dedicated),
+                1 => run_8xy1(1, 2, &mut dedicated, true),
+                2 => run_8xy2(1, 2, &mut dedicated, true),
+                3 => run_8xy3(1, 2, &mut dedicated, true),
+                4 => run_8xy4(1, 2, &mut dedicated),
+                _ => run_8xy5(1, 2, &mut dedicated),
+            }
+            run_8xyn(1, 2, n, &mut generic, true);
+
+            assert_eq!(dedicated.registers[1], generic.registers[1], "8XY{}", n);
+        }
+    }
+
+    #[test]
+    fn test_9xy0_skips_when_registers_differ() {
+        let mut state = state();
+        state.registers[1] = 7;
+        state.registers[2] = 7;
+        state.registers[3] = 8;
+
+        run_9xy0(1, 2, &mut state);
+        assert_eq!(state.pc, 0x200);
+
+        run_9xy0(1, 3, &mut state);
+        assert_eq!(state.pc, 0x202);
+    }
+
+    #[test]
+    fn test_annn_sets_index() {
+        let mut state = state();
+
+        run_annn(0x123, &mut state);
+
+        assert_eq!(state.vi, 0x123);
+    }
+
+    #[test]
+    fn test_bnnn_jumps_from_v0_with_jumping_quirk() {
+        let mut state = state();
+        state.registers[0] = 0x10;
+        state.registers[3] = 0x20;
+
+        run_bnnn(3, 0x300, &mut state, false);
+
+        assert_eq!(state.pc, 0x310);
+        assert!(state.did_jump);
+    }
+
+    #[test]
+    fn test_bnnn_jumps_from_vx_without_jumping_quirk() {
+        let mut state = state();
+        state.registers[0] = 0x10;
+        state.registers[3] = 0x20;
+
+        run_bnnn(3, 0x300, &mut state, true);
+
+        assert_eq!(state.pc, 0x320);
+    }
+
+    #[test]
+    fn test_bnnn_wraps_around_memory() {
+        let mut state = state();
+        state.registers[0] = 0xFF;
+
+        run_bnnn(0, 0xFF0, &mut state, true);
+
+        assert_eq!(state.pc, 0x0EF);
+    }
+
+    #[test]
+    fn test_cxnn_masks_random_value() {
+        let mut state = state();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            run_cxnn(1, 0x0F, &mut state, &mut rng);
+            assert_eq!(state.registers[1] & 0xF0, 0);
+        }
+
+        run_cxnn(1, 0x00, &mut state, &mut rng);
+        assert_eq!(state.registers[1], 0);
+    }
+
+    #[test]
+    fn test_dxyn_draws_sprite() {
+        let mut state = state();
+        let mut display = Display::new();
+        state.vi = 0x300;
+        state.memory[0x300] = 0b1111_0000;
+        state.memory[0x301] = 0b1001_0000;
+        state.registers[0] = 8;
+        state.registers[1] = 2;
+
+        run_dxyn(0, 1, 2, &mut state, &mut display, true);
+
+        assert_eq!(display.screen_memory[2], 0xF0u64 << 48);
+        assert_eq!(display.screen_memory[3], 0x90u64 << 48);
+        assert_eq!(state.registers[15], 0);
+        assert!(state.should_draw);
+    }
+
+    #[test]
+    fn test_dxyn_reads_the_sprite_around_memory() {
+        let mut state = state();
+        let mut display = Display::new();
+        state.vi = 0xFFF;
+        state.memory[0xFFF] = 0b1111_0000;
+        state.memory[0x000] = 0b1001_0000;
+
+        run_dxyn(0, 0, 2, &mut state, &mut display, true);
+
+        assert_eq!(display.screen_memory[0], 0xF0u64 << 56);
+        assert_eq!(display.screen_memory[1], 0x90u64 << 56);
+    }
+
+    #[test]
+    fn test_dxyn_sets_collision_when_erasing() {
+        let mut state = state();
+        let mut display = Display::new();
+        state.vi = 0x300;
+        state.memory[0x300] = 0b1100_0000;
+
+        run_dxyn(0, 0, 1, &mut state, &mut display, true);
+        assert_eq!(state.registers[15], 0);
+
+        run_dxyn(0, 0, 1, &mut state, &mut display, true);
+        assert_eq!(state.registers[15], 1);
+        assert_eq!(display.screen_memory[0], 0);
+    }
+
+    #[test]
+    fn test_dxyn_wraps_starting_position() {
+        let mut state = state();
+        let mut display = Display::new();
+        state.vi = 0x300;
+        state.memory[0x300] = 0b1000_0000;
+        state.registers[0] = 64 + 3;
+        state.registers[1] = 32 + 5;
+
+        run_dxyn(0, 1, 1, &mut state, &mut display, true);
+
+        assert!(display.is_lit(3, 5));
+    }
+
+    #[test]
+    fn test_dxyn_clips_at_right_edge() {
+        let mut state = state();
+        let mut display = Display::new();
+        state.vi = 0x300;
+        state.memory[0x300] = 0xFF;
+        state.registers[0] = 60;
+
+        run_dxyn(0, 1, 1, &mut state, &mut display, true);
+
+        assert_eq!(display.screen_memory[0], 0xF);
+    }
+
+    #[test]
+    fn test_dxyn_clips_at_bottom_edge() {
+        let mut state = state();
+        let mut display = Display::new();
+        state.vi = 0x300;
+        state.memory[0x300..0x304].copy_from_slice(&[0x80; 4]);
+        state.registers[1] = 30;
+
+        run_dxyn(0, 1, 4, &mut state, &mut display, true);
+
+        assert!(display.is_lit(0, 30));
+        assert!(display.is_lit(0, 31));
+        assert!(!display.is_lit(0, 0));
+        assert!(!display.is_lit(0, 1));
+    }
+
+    #[test]
+    fn test_dxyn_wraps_without_clipping() {
+        let mut state = state();
+        let mut display = Display::new();
+        state.vi = 0x300;
+        state.memory[0x300..0x302].copy_from_slice(&[0xFF; 2]);
+        state.registers[0] = 60;
+        state.registers[1] = 31;
+
+        run_dxyn(0, 1, 2, &mut state, &mut display, false);
+
+        assert_eq!(display.screen_memory[31], 0xF000_0000_0000_000F);
+        assert_eq!(display.screen_memory[0], 0xF000_0000_0000_000F);
+    }
+
+    #[test]
+    fn test_ex9e_skips_when_pressed() {
+        let mut state = state();
+        let mut keypad = Keypad::new();
+        state.registers[1] = 0xA;
+
+        run_ex9e(1, &mut state, &keypad);
+        assert_eq!(state.pc, 0x200);
+
+        keypad.press(0xA);
+        run_ex9e(1, &mut state, &keypad);
+        assert_eq!(state.pc, 0x202);
+    }
+
+    #[test]
+    fn test_exa1_skips_when_not_pressed() {
+        let mut state = state();
+        let mut keypad = Keypad::new();
+        state.registers[1] = 0xA;
+
+        run_exa1(1, &mut state, &keypad);
+        assert_eq!(state.pc, 0x202);
+
+        keypad.press(0xA);
+        run_exa1(1, &mut state, &keypad);
+        assert_eq!(state.pc, 0x202);
+    }
+
+    #[test]
+    fn test_fx07_fx15_fx18_timers() {
+        let mut state = state();
+        let mut keypad = Keypad::new();
+        state.registers[1] = 30;
+
+        run_fxnn(1, 0x15, &mut state, &mut keypad, true);
+        run_fxnn(1, 0x18, &mut state, &mut keypad, true);
+        run_fxnn(2, 0x07, &mut state, &mut keypad, true);
+
+        assert_eq!(state.delay_timer, 30);
+        assert_eq!(state.sound_timer, 30);
+        assert_eq!(state.registers[2], 30);
+    }
+
+    #[test]
+    fn test_fx0a_waits_for_release() {
+        let mut state = state();
+        let mut keypad = Keypad::new();
+
+        run_fxnn(3, 0x0A, &mut state, &mut keypad, true);
+        assert!(state.should_wait);
+
+        keypad.press(0x7);
+        run_fxnn(3, 0x0A, &mut state, &mut keypad, true);
+        assert!(state.should_wait);
+
+        keypad.release(0x7);
+        run_fxnn(3, 0x0A, &mut state, &mut keypad, true);
+        assert!(!state.should_wait);
+        assert_eq!(state.registers[3], 0x7);
+    }
+
+    #[test]
+    fn test_fx1e_adds_to_index() {
+        let mut state = state();
+        let mut keypad = Keypad::new();
+        state.vi = 0x300;
+        state.registers[1] = 0x20;
+
+        run_fxnn(1, 0x1E, &mut state, &mut keypad, true);
+
+        assert_eq!(state.vi, 0x320);
+    }
+
+    #[test]
+    fn test_fx1e_wraps_around_memory() {
+        let mut state = state();
+        let mut keypad = Keypad::new();
+        state.vi = 0xFF0;
+        state.registers[1] = 0x20;
+
+        run_fxnn(1, 0x1E, &mut state, &mut keypad, true);
+
+        assert_eq!(state.vi, 0x010);
+    }
+
+    #[test]
+    fn test_fx29_points_to_font() {
+        let mut state = state();
+        let mut keypad = Keypad::new();
+        state.registers[1] = 0xB;
+
+        run_fxnn(1, 0x29, &mut state, &mut keypad, true);
+        assert_eq!(state.vi, 0x50 + 0xB * 5);
+
+        // Only the low nibble selects the character
+        state.registers[1] = 0xFB;
+        run_fxnn(1, 0x29, &mut state, &mut keypad, true);
+        assert_eq!(state.vi, 0x50 + 0xB * 5);
+    }
+
+    #[test]
+    fn test_fx33_stores_bcd() {
+        let mut state = state();
+        let mut keypad = Keypad::new();
+        state.vi = 0x300;
+
+        for (value, digits) in [(254, [2, 5, 4]), (7, [0, 0, 7]), (40, [0, 4, 0])] {
+            state.registers[1] = value;
+            run_fxnn(1, 0x33, &mut state, &mut keypad, true);
+            assert_eq!(state.memory[0x300..0x303], digits);
+        }
+    }
+
+    #[test]
+    fn test_fx33_wraps_around_memory() {
+        let mut state = state();
+        let mut keypad = Keypad::new();
+        state.vi = 0xFFF;
+        state.registers[1] = 254;
+
+        run_fxnn(1, 0x33, &mut state, &mut keypad, true);
+
+        assert_eq!(state.memory[0xFFF], 2);
+        assert_eq!(state.memory[0x000..0x002], [5, 4]);
+    }
+
+    #[test]
+    fn test_fx55_and_fx65_wrap_around_memory() {
+        let mut state = state();
+        let mut keypad = Keypad::new();
+        state.vi = 0xFFE;
+        state.registers[0..4].copy_from_slice(&[1, 2, 3, 4]);
+
+        run_fxnn(3, 0x55, &mut state, &mut keypad, true);
+
+        assert_eq!(state.memory[0xFFE..], [1, 2]);
+        assert_eq!(state.memory[0x000..0x002], [3, 4]);
+        assert_eq!(state.vi, 0x002);
+
+        state.vi = 0xFFE;
+        state.registers = [0; 16];
+        run_fxnn(3, 0x65, &mut state, &mut keypad, true);
+
+        assert_eq!(state.registers[0..4], [1, 2, 3, 4]);
+        assert_eq!(state.vi, 0x002);
+    }
+
+    #[test]
+    fn test_fx55_increments_index_with_quirk() {
+        let mut state = state();
+        let mut keypad = Keypad::new();
+        state.vi = 0x300;
+        state.registers[0..4].copy_from_slice(&[1, 2, 3, 4]);
+
+        run_fxnn(2, 0x55, &mut state, &mut keypad, true);
+
+        assert_eq!(state.memory[0x300..0x304], [1, 2, 3, 0]);
+        assert_eq!(state.vi, 0x303);
+    }
+
+    #[test]
+    fn test_fx55_keeps_index_without_quirk() {
+        let mut state = state();
+        let mut keypad = Keypad::new();
+        state.vi = 0x300;
+        state.registers[0..3].copy_from_slice(&[1, 2, 3]);
+
+        run_fxnn(2, 0x55, &mut state, &mut keypad, false);
+
+        assert_eq!(state.memory[0x300..0x303], [1, 2, 3]);
+        assert_eq!(state.vi, 0x300);
+    }
+
+    #[test]
+    fn test_fx65_increments_index_with_quirk() {
+        let mut state = state();
+        let mut keypad = Keypad::new();
+        state.vi = 0x300;
+        state.memory[0x300..0x304].copy_from_slice(&[1, 2, 3, 4]);
+
+        run_fxnn(2, 0x65, &mut state, &mut keypad, true);
+
+        assert_eq!(state.registers[0..4], [1, 2, 3, 0]);
+        assert_eq!(state.vi, 0x303);
+    }
+
+    #[test]
+    fn test_fx65_keeps_index_without_quirk() {
+        let mut state = state();
+        let mut keypad = Keypad::new();
+        state.vi = 0x300;
+        state.memory[0x300..0x303].copy_from_slice(&[1, 2, 3]);
+
+        run_fxnn(2, 0x65, &mut state, &mut keypad, false);
+
+        assert_eq!(state.registers[0..3], [1, 2, 3]);
+        assert_eq!(state.vi, 0x300);
+    }
+
+    #[test]
+    fn test_decode_applies_chip8_quirks() {
+        let mut state = state();
+        let mut display = Display::new();
+        let mut keypad = Keypad::new();
+        state.registers[0] = 0x02;
+        state.registers[1] = 0x10;
+
+        // BNNN jumps from V0
+        run(0xB300, &mut state, &mut display, &mut keypad);
+        assert_eq!(state.pc, 0x302);
+
+        // 8XY6 shifts VY
+        run(0x8016, &mut state, &mut display, &mut keypad);
+        assert_eq!(state.registers[0], 0x08);
+
+        // FX55 moves the index
+        state.vi = 0x400;
+        run(0xF155, &mut state, &mut display, &mut keypad);
+        assert_eq!(state.vi, 0x402);
+    }
 }
@@ -0,0 +1,116 @@
+// An opcode decoded once, so running it again only needs a jump on the tag.
+// Registers are indices into V0 to VF.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    Clear,
+    Return,
+    Jump(u16),
+    Call(u16),
+    SkipEqual(u8, u8),
+    SkipNotEqual(u8, u8),
+    SkipEqualRegisters(u8, u8),
+    Load(u8, u8),
+    Add(u8, u8),
+    Move(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    AddRegisters(u8, u8),
+    Sub(u8, u8),
+    ShiftRight(u8, u8),
+    SubReverse(u8, u8),
+    ShiftLeft(u8, u8),
+    SkipNotEqualRegisters(u8, u8),
+    LoadIndex(u16),
+    JumpOffset(u8, u16),
+    Random(u8, u8),
+    Draw(u8, u8, u8),
+    SkipPressed(u8),
+    SkipNotPressed(u8),
+    ReadDelay(u8),
+    WaitKey(u8),
+    SetDelay(u8),
+    SetSound(u8),
+    AddIndex(u8),
+    LoadFont(u8),
+    StoreBcd(u8),
+    StoreRegisters(u8),
+    LoadRegisters(u8),
+    // Machine code calls (0NNN) and opcodes of other platforms, which do nothing
+    Unknown(u16),
+}
+
+impl Instruction {
+    pub fn decode(code: u16) -> Instruction {
+        let x = ((code >> 8) & 0xF) as u8;
+        let y = ((code >> 4) & 0xF) as u8;
+        let n = (code & 0xF) as u8;
+        let nn = (code & 0xFF) as u8;
+        let nnn = code & 0xFFF;
+
+        match (code >> 12, n) {
+            (0, _) if code == 0x00E0 => Instruction::Clear,
+            (0, _) if code == 0x00EE => Instruction::Return,
+            (1, _) => Instruction::Jump(nnn),
+            (2, _) => Instruction::Call(nnn),
+            (3, _) => Instruction::SkipEqual(x, nn),
+            (4, _) => Instruction::SkipNotEqual(x, nn),
+            (5, 0) => Instruction::SkipEqualRegisters(x, y),
+            (6, _) => Instruction::Load(x, nn),
+            (7, _) => Instruction::Add(x, nn),
+            (8, 0) => Instruction::Move(x, y),
+            (8, 1) => Instruction::Or(x, y),
+            (8, 2) => Instruction::And(x, y),
+            (8, 3) => Instruction::Xor(x, y),
+            (8, 4) => Instruction::AddRegisters(x, y),
+            (8, 5) => Instruction::Sub(x, y),
+            (8, 6) => Instruction::ShiftRight(x, y),
+            (8, 7) => Instruction::SubReverse(x, y),
+            (8, 0xE) => Instruction::ShiftLeft(x, y),
+            (9, 0) => Instruction::SkipNotEqualRegisters(x, y),
+            (0xA, _) => Instruction::LoadIndex(nnn),
+            (0xB, _) => Instruction::JumpOffset(x, nnn),
+            (0xC, _) => Instruction::Random(x, nn),
+            (0xD, _) => Instruction::Draw(x, y, n),
+            (0xE, _) if nn == 0x9E => Instruction::SkipPressed(x),
+            (0xE, _) if nn == 0xA1 => Instruction::SkipNotPressed(x),
+            (0xF, _) => match nn {
+                0x07 => Instruction::ReadDelay(x),
+                0x0A => Instruction::WaitKey(x),
+                0x15 => Instruction::SetDelay(x),
+                0x18 => Instruction::SetSound(x),
+                0x1E => Instruction::AddIndex(x),
+                0x29 => Instruction::LoadFont(x),
+                0x33 => Instruction::StoreBcd(x),
+                0x55 => Instruction::StoreRegisters(x),
+                0x65 => Instruction::LoadRegisters(x),
+                _ => Instruction::Unknown(code),
+            },
+            _ => Instruction::Unknown(code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_operands() {
+        assert_eq!(Instruction::decode(0x00E0), Instruction::Clear);
+        assert_eq!(Instruction::decode(0x2ABC), Instruction::Call(0xABC));
+        assert_eq!(
+            Instruction::decode(0x8AB4),
+            Instruction::AddRegisters(0xA, 0xB)
+        );
+        assert_eq!(Instruction::decode(0xD01F), Instruction::Draw(0, 1, 15));
+        assert_eq!(Instruction::decode(0xF355), Instruction::StoreRegisters(3));
+    }
+
+    #[test]
+    fn leaves_other_opcodes_unknown() {
+        for code in [0x0123, 0x5121, 0x8AB8, 0xE0FF, 0xF000] {
+            assert_eq!(Instruction::decode(code), Instruction::Unknown(code));
+        }
+    }
+}
@@ -0,0 +1,780 @@
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, InstBuilder, MemFlagsData, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::Module;
+
+use crate::core::chip::Chip8;
+use crate::core::instruction::Instruction;
+use crate::core::quirks::Quirks;
+use crate::core::state::{ChipState, ADDRESS_MASK};
+
+const MEMORY_SIZE: usize = 4096;
+// Same seed for both machines in compare, so CXNN agrees
+const SEED: u64 = 0x8;
+// Longest run of instructions compiled into one block
+const MAX_LENGTH: usize = 64;
+// Compiled blocks dropped before the module is rebuilt
+const MAX_STALE: usize = 4096;
+
+// The part of the state compiled code reads and writes
+#[repr(C)]
+struct Machine {
+    registers: [u8; 16],
+    vi: u16,
+    pc: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+}
+
+const VI_OFFSET: i32 = 16;
+const PC_OFFSET: i32 = 18;
+const DELAY_OFFSET: i32 = 20;
+const SOUND_OFFSET: i32 = 21;
+
+// Takes the instructions left in the frame and returns how many ran
+type Compiled = extern "C" fn(*mut Machine, u32) -> u32;
+
+struct Block {
+    // End of the bytes the block was compiled from, so writes there drop it
+    end: u16,
+    // Most instructions a run of the block can take
+    length: u32,
+    // None when the first instruction needs the interpreter
+    function: Option<Compiled>,
+}
+
+// Compiles straight-line code between branches into native functions.
+// Drawing, input, the stack, memory and random numbers are left to the
+// interpreter, so a block stops before any of them.
+pub struct Jit {
+    module: JITModule,
+    builder: FunctionBuilderContext,
+    // Indexed by the address each block starts at
+    blocks: Vec<Option<Block>>,
+    quirks: Quirks,
+    stale: usize,
+}
+
+impl Jit {
+    pub fn new(quirks: Quirks) -> Result<Jit, String> {
+        Ok(Jit {
+            module: new_module()?,
+            builder: FunctionBuilderContext::new(),
+            blocks: (0..MEMORY_SIZE).map(|_| None).collect(),
+            quirks,
+            stale: 0,
+        })
+    }
+
+    // Runs the block at PC if it fits in the instructions left this frame.
+    // Returns how many instructions ran, or None for the interpreter to run one.
+    pub fn run(&mut self, state: &mut ChipState, quirks: &Quirks, left: u32) -> Option<u32> {
+        // Blocks have the quirks they were compiled with built in
+        if *quirks != self.quirks {
+            self.quirks = *quirks;
+            self.clear();
+        }
+
+        let pc = state.pc & ADDRESS_MASK;
+        if self.blocks[pc as usize].is_none() {
+            self.blocks[pc as usize] = Some(self.compile(&state.memory, pc));
+        }
+
+        let block = self.blocks[pc as usize].as_ref().unwrap();
+        let function = block.function?;
+        if block.length > left {
+            return None;
+        }
+
+        let mut machine = Machine {
+            registers: state.registers,
+            vi: state.vi,
+            pc,
+            delay_timer: state.delay_timer,
+            sound_timer: state.sound_timer,
+        };
+        let count = function(&mut machine, left);
+
+        state.registers = machine.registers;
+        state.vi = machine.vi;
+        // Blocks ending at the top of memory run on from its start
+        state.pc = machine.pc & ADDRESS_MASK;
+        state.delay_timer = machine.delay_timer;
+        state.sound_timer = machine.sound_timer;
+
+        Some(count)
+    }
+
+    // Drops the blocks compiled from any byte in start..end
+    pub fn invalidate(&mut self, start: usize, end: usize) {
+        // Only blocks starting less than a block's length before can reach
+        let first = start.saturating_sub(MAX_LENGTH * 2);
+
+        for slot in &mut self.blocks[first..end] {
+            if let Some(block) = slot {
+                if block.end as usize > start {
+                    if block.function.is_some() {
+                        self.stale += 1;
+                    }
+                    *slot = None;
+                }
+            }
+        }
+
+        self.free_stale();
+    }
+
+    pub fn clear(&mut self) {
+        for block in self.blocks.iter_mut().filter_map(Option::take) {
+            if block.function.is_some() {
+                self.stale += 1;
+            }
+        }
+
+        self.free_stale();
+    }
+
+    // Code of dropped blocks stays in the module, which is only freed as a
+    // whole, so once there is enough of it every block is compiled again
+    fn free_stale(&mut self) {
+        if self.stale <= MAX_STALE {
+            return;
+        }
+
+        self.blocks.fill_with(|| None);
+        self.stale = 0;
+
+        // Worked when the JIT was created, so it works again
+        let module = std::mem::replace(&mut self.module, new_module().unwrap());
+        // No block points into the old module anymore
+        unsafe { module.free_memory() };
+    }
+
+    fn compile(&mut self, memory: &[u8], start: u16) -> Block {
+        let mut instructions = Vec::new();
+        let mut address = start as usize;
+
+        while instructions.len() < MAX_LENGTH && address + 1 < memory.len() {
+            let code = u16::from_be_bytes([memory[address], memory[address + 1]]);
+            let instruction = Instruction::decode(code);
+            if !is_compiled(instruction) {
+                break;
+            }
+
+            instructions.push((address as u16, instruction));
+            address += 2;
+
+            let room = instructions.len() < MAX_LENGTH && address + 1 < memory.len();
+            if is_skip(instruction) && room {
+                // A skip over a jump is how CHIP-8 branches, take both at once
+                let code = u16::from_be_bytes([memory[address], memory[address + 1]]);
+                if let jump @ Instruction::Jump(_) = Instruction::decode(code) {
+                    instructions.push((address as u16, jump));
+                    address += 2;
+                }
+            }
+
+            if is_branch(instruction) {
+                break;
+            }
+        }
+
+        if instructions.is_empty() {
+            return Block {
+                end: start + 2,
+                length: 0,
+                function: None,
+            };
+        }
+
+        // Verification failures are bugs in translate, not in the ROM
+        let function = self
+            .translate(start, &instructions, address as u16)
+            .unwrap();
+
+        Block {
+            end: address as u16,
+            length: instructions.len() as u32,
+            function: Some(function),
+        }
+    }
+
+    fn translate(
+        &mut self,
+        start: u16,
+        instructions: &[(u16, Instruction)],
+        next: u16,
+    ) -> Result<Compiled, String> {
+        let config = self.module.target_config();
+        let mut context = self.module.make_context();
+        context
+            .func
+            .signature
+            .params
+            .push(AbiParam::new(config.pointer_type()));
+        let signature = &mut context.func.signature;
+        signature.params.push(AbiParam::new(types::I32));
+        signature.returns.push(AbiParam::new(types::I32));
+
+        let mut builder = FunctionBuilder::new(&mut context.func, &mut self.builder);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let machine = builder.block_params(entry)[0];
+        let left = builder.block_params(entry)[1];
+
+        // The body takes the instructions run so far, so a block that jumps
+        // back to its start can go round without returning
+        let body = builder.create_block();
+        let before = builder.append_block_param(body, types::I32);
+        let zero = builder.ins().iconst(types::I32, 0);
+        builder.ins().jump(body, &[zero.into()]);
+        builder.switch_to_block(body);
+
+        let mut emitter = Emitter {
+            builder,
+            machine,
+            registers: [None; 16],
+            changed: [false; 16],
+            vi: None,
+            vi_changed: false,
+        };
+
+        let mut exit = None;
+        for (index, &(address, instruction)) in instructions.iter().enumerate() {
+            let ran = index as i64 + 1;
+
+            if let (true, Some(&(_, Instruction::Jump(nnn)))) =
+                (is_skip(instruction), instructions.get(index + 1))
+            {
+                let skip = emitter.condition(instruction);
+                let pc = emitter.select(skip, address + 4, nnn);
+                let count = emitter.select_count(skip, ran, ran + 1);
+                exit = Some((pc, count));
+                break;
+            }
+
+            if let Some(pc) = emitter.emit(address, instruction, &self.quirks) {
+                let count = emitter.builder.ins().iconst(types::I32, ran);
+                exit = Some((pc, count));
+            }
+        }
+
+        let (pc, count) = match exit {
+            Some(exit) => exit,
+            None => {
+                let pc = emitter.address(next);
+                let count = emitter
+                    .builder
+                    .ins()
+                    .iconst(types::I32, instructions.len() as i64);
+                (pc, count)
+            }
+        };
+        emitter.store(pc);
+
+        let mut builder = emitter.builder;
+        let total = builder.ins().iadd(before, count);
+
+        if let Some(&(_, Instruction::Jump(target))) = instructions.last() {
+            if target == start {
+                let start = builder.ins().iconst(types::I16, start as i64);
+                let again = builder.ins().icmp(IntCC::Equal, pc, start);
+                let most = builder.ins().iadd_imm_u(total, instructions.len() as i64);
+                let fits = builder
+                    .ins()
+                    .icmp(IntCC::UnsignedLessThanOrEqual, most, left);
+                let again = builder.ins().band(again, fits);
+
+                let exit = builder.create_block();
+                builder.ins().brif(again, body, &[total.into()], exit, &[]);
+                builder.switch_to_block(exit);
+                builder.seal_block(exit);
+            }
+        }
+
+        builder.seal_block(body);
+        builder.ins().return_(&[total]);
+        builder.finalize(config);
+
+        let id = self
+            .module
+            .declare_anonymous_function(&context.func.signature)
+            .map_err(|e| e.to_string())?;
+        self.module
+            .define_function(id, &mut context)
+            .map_err(|e| e.to_string())?;
+        self.module.clear_context(&mut context);
+        self.module
+            .finalize_definitions()
+            .map_err(|e| e.to_string())?;
+
+        let code = self.module.get_finalized_function(id);
+        Ok(unsafe { std::mem::transmute::<*const u8, Compiled>(code) })
+    }
+}
+
+fn new_module() -> Result<JITModule, String> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").map_err(|e| e.to_string())?;
+
+    let isa = cranelift_native::builder()
+        .map_err(|e| format!("the JIT does not support this machine: {}", e))?
+        .finish(settings::Flags::new(flags))
+        .map_err(|e| e.to_string())?;
+
+    Ok(JITModule::new(JITBuilder::with_isa(
+        isa,
+        cranelift_module::default_libcall_names(),
+    )))
+}
+
+fn is_compiled(instruction: Instruction) -> bool {
+    use Instruction::*;
+
+    matches!(
+        instruction,
+        Jump(_)
+            | SkipEqual(..)
+            | SkipNotEqual(..)
+            | SkipEqualRegisters(..)
+            | Load(..)
+            | Add(..)
+            | Move(..)
+            | Or(..)
+            | And(..)
+            | Xor(..)
+            | AddRegisters(..)
+            | Sub(..)
+            | ShiftRight(..)
+            | SubReverse(..)
+            | ShiftLeft(..)
+            | SkipNotEqualRegisters(..)
+            | LoadIndex(_)
+            | JumpOffset(..)
+            | ReadDelay(_)
+            | SetDelay(_)
+            | SetSound(_)
+            | AddIndex(_)
+            | LoadFont(_)
+            | Unknown(_)
+    )
+}
+
+fn is_skip(instruction: Instruction) -> bool {
+    use Instruction::*;
+
+    matches!(
+        instruction,
+        SkipEqual(..) | SkipNotEqual(..) | SkipEqualRegisters(..) | SkipNotEqualRegisters(..)
+    )
+}
+
+fn is_branch(instruction: Instruction) -> bool {
+    use Instruction::*;
+
+    matches!(instruction, Jump(_) | JumpOffset(..)) || is_skip(instruction)
+}
+
+// Keeps registers in SSA values for the whole block, loading each on first
+// use and storing the changed ones once at the end
+struct Emitter<'a> {
+    builder: FunctionBuilder<'a>,
+    machine: Value,
+    registers: [Option<Value>; 16],
+    changed: [bool; 16],
+    vi: Option<Value>,
+    vi_changed: bool,
+}
+
+impl Emitter<'_> {
+    fn register(&mut self, x: u8) -> Value {
+        let x = x as usize;
+        if let Some(value) = self.registers[x] {
+            return value;
+        }
+
+        let value =
+            self.builder
+                .ins()
+                .load(types::I8, MemFlagsData::trusted(), self.machine, x as i32);
+        self.registers[x] = Some(value);
+        value
+    }
+
+    fn set_register(&mut self, x: u8, value: Value) {
+        self.registers[x as usize] = Some(value);
+        self.changed[x as usize] = true;
+    }
+
+    fn vi(&mut self) -> Value {
+        if let Some(value) = self.vi {
+            return value;
+        }
+
+        let value =
+            self.builder
+                .ins()
+                .load(types::I16, MemFlagsData::trusted(), self.machine, VI_OFFSET);
+        self.vi = Some(value);
+        value
+    }
+
+    fn set_vi(&mut self, value: Value) {
+        self.vi = Some(value);
+        self.vi_changed = true;
+    }
+
+    fn byte(&mut self, value: u8) -> Value {
+        self.builder.ins().iconst(types::I8, value as i64)
+    }
+
+    fn address(&mut self, value: u16) -> Value {
+        self.builder.ins().iconst(types::I16, value as i64)
+    }
+
+    // Same results as the handlers, returns the new PC for branches
+    fn emit(&mut self, address: u16, instruction: Instruction, quirks: &Quirks) -> Option<Value> {
+        use Instruction::*;
+
+        match instruction {
+            Jump(nnn) => return Some(self.address(nnn)),
+            JumpOffset(x, nnn) => {
+                let x = if quirks.has_jumping() { 0 } else { x };
+                let vx = self.register(x);
+                let offset = self.builder.ins().uextend(types::I16, vx);
+                let address = self.builder.ins().iadd_imm_u(offset, nnn as i64);
+                return Some(self.builder.ins().band_imm_u(address, ADDRESS_MASK as i64));
+            }
+            SkipEqual(..)
+            | SkipNotEqual(..)
+            | SkipEqualRegisters(..)
+            | SkipNotEqualRegisters(..) => {
+                let skip = self.condition(instruction);
+                return Some(self.select(skip, address + 4, address + 2));
+            }
+            Load(x, nn) => {
+                let nn = self.byte(nn);
+                self.set_register(x, nn);
+            }
+            Add(x, nn) => {
+                let vx = self.register(x);
+                let nn = self.byte(nn);
+                let sum = self.builder.ins().iadd(vx, nn);
+                self.set_register(x, sum);
+            }
+            Move(x, y) => {
+                let vy = self.register(y);
+                self.set_register(x, vy);
+            }
+            Or(x, y) | And(x, y) | Xor(x, y) => {
+                let vx = self.register(x);
+                let vy = self.register(y);
+                let result = match instruction {
+                    Or(..) => self.builder.ins().bor(vx, vy),
+                    And(..) => self.builder.ins().band(vx, vy),
+                    _ => self.builder.ins().bxor(vx, vy),
+                };
+                self.set_register(x, result);
+
+                if quirks.has_vf_reset() {
+                    let zero = self.byte(0);
+                    self.set_register(15, zero);
+                }
+            }
+            AddRegisters(x, y) => {
+                let vx = self.register(x);
+                let vy = self.register(y);
+                let sum = self.builder.ins().iadd(vx, vy);
+                let carry = self.builder.ins().icmp(IntCC::UnsignedLessThan, sum, vx);
+                self.set_register(x, sum);
+                self.set_register(15, carry);
+            }
+            Sub(x, y) | SubReverse(x, y) => {
+                let vx = self.register(x);
+                let vy = self.register(y);
+                let (a, b) = if matches!(instruction, Sub(..)) {
+                    (vx, vy)
+                } else {
+                    (vy, vx)
+                };
+                let difference = self.builder.ins().isub(a, b);
+                let no_borrow = self
+                    .builder
+                    .ins()
+                    .icmp(IntCC::UnsignedGreaterThanOrEqual, a, b);
+                self.set_register(x, difference);
+                self.set_register(15, no_borrow);
+            }
+            ShiftRight(x, y) | ShiftLeft(x, y) => {
+                let source = if quirks.has_shifting() { y } else { x };
+                let value = self.register(source);
+                let (result, carry) = if matches!(instruction, ShiftRight(..)) {
+                    (
+                        self.builder.ins().ushr_imm_u(value, 1),
+                        self.builder.ins().band_imm_u(value, 1),
+                    )
+                } else {
+                    (
+                        self.builder.ins().ishl_imm_u(value, 1),
+                        self.builder.ins().ushr_imm_u(value, 7),
+                    )
+                };
+                self.set_register(x, result);
+                self.set_register(15, carry);
+            }
+            ReadDelay(x) => {
+                let delay = self.builder.ins().load(
+                    types::I8,
+                    MemFlagsData::trusted(),
+                    self.machine,
+                    DELAY_OFFSET,
+                );
+                self.set_register(x, delay);
+            }
+            SetDelay(x) | SetSound(x) => {
+                let vx = self.register(x);
+                let offset = if matches!(instruction, SetDelay(_)) {
+                    DELAY_OFFSET
+                } else {
+                    SOUND_OFFSET
+                };
+                self.builder
+                    .ins()
+                    .store(MemFlagsData::trusted(), vx, self.machine, offset);
+            }
+            LoadIndex(nnn) => {
+                let nnn = self.address(nnn);
+                self.set_vi(nnn);
+            }
+            AddIndex(x) => {
+                let vx = self.register(x);
+                let vx = self.builder.ins().uextend(types::I16, vx);
+                let vi = self.vi();
+                let sum = self.builder.ins().iadd(vi, vx);
+                let sum = self.builder.ins().band_imm_u(sum, ADDRESS_MASK as i64);
+                self.set_vi(sum);
+            }
+            LoadFont(x) => {
+                let vx = self.register(x);
+                let digit = self.builder.ins().band_imm_u(vx, 0xF);
+                let digit = self.builder.ins().uextend(types::I16, digit);
+                let offset = self.builder.ins().imul_imm_u(digit, 5);
+                let sprite = self.builder.ins().iadd_imm_u(offset, 0x50);
+                self.set_vi(sprite);
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    // Whether a skip instruction skips
+    fn condition(&mut self, instruction: Instruction) -> Value {
+        use Instruction::*;
+
+        let (cc, x, other) = match instruction {
+            SkipEqual(x, nn) => (IntCC::Equal, x, self.byte(nn)),
+            SkipNotEqual(x, nn) => (IntCC::NotEqual, x, self.byte(nn)),
+            SkipEqualRegisters(x, y) => (IntCC::Equal, x, self.register(y)),
+            SkipNotEqualRegisters(x, y) => (IntCC::NotEqual, x, self.register(y)),
+            _ => unreachable!("{:?} is not a skip", instruction),
+        };
+
+        let vx = self.register(x);
+        self.builder.ins().icmp(cc, vx, other)
+    }
+
+    fn select(&mut self, condition: Value, taken: u16, not_taken: u16) -> Value {
+        let taken = self.address(taken);
+        let not_taken = self.address(not_taken);
+        self.builder.ins().select(condition, taken, not_taken)
+    }
+
+    fn select_count(&mut self, condition: Value, taken: i64, not_taken: i64) -> Value {
+        let taken = self.builder.ins().iconst(types::I32, taken);
+        let not_taken = self.builder.ins().iconst(types::I32, not_taken);
+        self.builder.ins().select(condition, taken, not_taken)
+    }
+
+    // Writes back what the block changed, values can't be kept past this
+    fn store(&mut self, pc: Value) {
+        let flags = MemFlagsData::trusted();
+
+        for x in 0..16 {
+            if self.changed[x] {
+                let value = self.registers[x].unwrap();
+                self.builder
+                    .ins()
+                    .store(flags, value, self.machine, x as i32);
+            }
+        }
+
+        if self.vi_changed {
+            let vi = self.vi.unwrap();
+            self.builder.ins().store(flags, vi, self.machine, VI_OFFSET);
+        }
+
+        self.builder.ins().store(flags, pc, self.machine, PC_OFFSET);
+    }
+}
+
+// Runs a ROM with and without the JIT from the same seed and compares the
+// machines after every frame
+pub fn compare(data: &[u8], quirks: Quirks, frames: u32, ipf: u32) -> Result<(), String> {
+    let mut interpreter = Chip8::with_quirks(quirks);
+    let mut compiled = Chip8::with_quirks(quirks);
+    compiled.set_jit(true)?;
+
+    for chip in [&mut interpreter, &mut compiled] {
+        chip.set_seed(SEED);
+        chip.load_rom(data)?;
+    }
+
+    for frame in 0..frames {
+        interpreter.step(ipf);
+        compiled.step(ipf);
+
+        if interpreter.state() != compiled.state()
+            || interpreter.display().screen_memory != compiled.display().screen_memory
+            || interpreter.cycles() != compiled.cycles()
+        {
+            return Err(format!(
+                "frame {}: the interpreter has {}, the JIT {}",
+                frame,
+                describe(&interpreter),
+                describe(&compiled)
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn describe(chip: &Chip8) -> String {
+    let state = chip.state();
+
+    format!(
+        "PC {:03X} I {:03X} V {:02X?} after {} instructions",
+        state.pc,
+        state.vi,
+        state.registers,
+        chip.cycles()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every compiled opcode, looping through the values of V0 and adding up
+    // the flags in VA to VE
+    const ARITHMETIC: [u8; 58] = [
+        0x61, 0x07, // 200: LD V1, 7
+        0x80, 0x14, // 202: ADD V0, V1
+        0x8A, 0xF4, // 204: ADD VA, VF
+        0x82, 0x00, // 206: LD V2, V0
+        0x82, 0x15, // 208: SUB V2, V1
+        0x8B, 0xF4, // 20A: ADD VB, VF
+        0x83, 0x07, // 20C: SUBN V3, V0
+        0x8C, 0xF4, // 20E: ADD VC, VF
+        0x84, 0x06, // 210: SHR V4, V0
+        0x8D, 0xF4, // 212: ADD VD, VF
+        0x85, 0x0E, // 214: SHL V5, V0
+        0x8E, 0xF4, // 216: ADD VE, VF
+        0x6F, 0x01, // 218: LD VF, 1
+        0x86, 0x31, // 21A: OR V6, V3
+        0x87, 0x42, // 21C: AND V7, V4
+        0x88, 0x53, // 21E: XOR V8, V5
+        0x8E, 0xF4, // 220: ADD VE, VF
+        0xF0, 0x29, // 222: LD F, V0
+        0xF0, 0x1E, // 224: ADD I, V0
+        0xF0, 0x15, // 226: LD DT, V0
+        0xF1, 0x18, // 228: LD ST, V1
+        0xF2, 0x07, // 22A: LD V2, DT
+        0x8E, 0x24, // 22C: ADD VE, V2
+        0x30, 0xF5, // 22E: SE V0, F5
+        0x12, 0x02, // 230: JP 202
+        0x59, 0x00, // 232: SE V9, V0
+        0x79, 0x01, // 234: ADD V9, 1
+        0x60, 0x00, // 236: LD V0, 0
+        0xB0, 0x00, // 238: JP V0, 200
+    ];
+
+    #[test]
+    fn matches_the_interpreter_under_every_preset() {
+        for preset in ["chip8", "schip", "xochip"] {
+            let quirks = Quirks::from_name(preset).unwrap();
+            compare(&ARITHMETIC, quirks, 30, 1000).unwrap();
+        }
+    }
+
+    #[test]
+    fn wraps_around_memory_like_the_interpreter() {
+        // 200: JP FF6  FF6: ADD V0, 1  FF8: ADD V1, 2  FFA: ADD I, V0
+        // FFC: JP V0, FFE, landing in the low memory before 200
+        let mut rom = vec![0u8; 0x1000 - 0x200];
+        rom[0x000..0x002].copy_from_slice(&[0x1F, 0xF6]);
+        rom[0xDF6..].copy_from_slice(&[0x70, 0x01, 0x71, 0x02, 0xF0, 0x1E, 0xBF, 0xFE, 0x00, 0x00]);
+
+        compare(&rom, Quirks::for_chip8(), 60, 1000).unwrap();
+    }
+
+    #[test]
+    fn runs_blocks_up_to_the_next_branch() {
+        let mut state = ChipState::init();
+        state.memory[0x200..0x200 + ARITHMETIC.len()].copy_from_slice(&ARITHMETIC);
+        let quirks = Quirks::for_chip8();
+        let mut jit = Jit::new(quirks).unwrap();
+
+        // 200 to the skip at 22E, which does not skip, and the jump after it
+        assert_eq!(jit.run(&mut state, &quirks, 100), Some(25));
+        assert_eq!(state.pc, 0x202);
+        assert_eq!(state.registers[0], 7);
+        assert_eq!(state.delay_timer, 7);
+        assert_eq!(state.sound_timer, 7);
+
+        // Not enough instructions left in the frame for the loop body
+        state.pc = 0x202;
+        assert_eq!(jit.run(&mut state, &quirks, 5), None);
+    }
+
+    #[test]
+    fn loops_until_the_frame_runs_out() {
+        // 200: LD V0, 5  202: LD DT, V0  204: LD V1, DT  206: SE V1, 0
+        // 208: JP 204  20A: JP 200
+        let rom = [
+            0x60, 0x05, 0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x04, 0x12, 0x00,
+        ];
+
+        // The loop is 3 instructions and the frame doesn't divide by it
+        compare(&rom, Quirks::for_chip8(), 12, 1001).unwrap();
+
+        let mut chip = Chip8::new();
+        chip.set_jit(true).unwrap();
+        chip.load_rom(&rom).unwrap();
+        chip.step(1001);
+        assert_eq!(chip.cycles(), 1001);
+        assert_eq!(chip.state().delay_timer, 4);
+    }
+
+    #[test]
+    fn recompiles_code_written_after_it_ran() {
+        // 200: CALL 20C  202: LD V0, 60  204: LD V1, 05  206: LD I, 20C
+        // 208: LD [I], V1  20A: CALL 20C  20C: LD V2, 3  20E: RET
+        let rom = [
+            0x22, 0x0C, 0x60, 0x60, 0x61, 0x05, 0xA2, 0x0C, 0xF1, 0x55, 0x22, 0x0C, 0x62, 0x03,
+            0x00, 0xEE,
+        ];
+        let mut chip = Chip8::new();
+        chip.set_jit(true).unwrap();
+        chip.load_rom(&rom).unwrap();
+        chip.step(10);
+
+        assert_eq!(chip.state().registers[0], 5);
+        assert_eq!(chip.state().registers[2], 3);
+    }
+}
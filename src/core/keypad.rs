@@ -0,0 +1,50 @@
+pub struct Keypad {
+    keys: [bool; 16],
+    released: Option<u8>,
+}
+
+impl Keypad {
+    pub fn new() -> Keypad {
+        Keypad {
+            keys: [false; 16],
+            released: None,
+        }
+    }
+
+    pub fn press(&mut self, key: u8) {
+        self.keys[(key & 0xF) as usize] = true;
+    }
+
+    pub fn release(&mut self, key: u8) {
+        let key = key & 0xF;
+
+        if self.keys[key as usize] {
+            self.released = Some(key);
+        }
+
+        self.keys[key as usize] = false;
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.keys[(key & 0xF) as usize]
+    }
+
+    // FX0A only accepts a key released while it is waiting
+    pub fn take_released(&mut self) -> Option<u8> {
+        self.released.take()
+    }
+
+    pub fn released(&self) -> Option<u8> {
+        self.released
+    }
+
+    pub fn clear_released(&mut self) {
+        self.released = None;
+    }
+}
+
+impl Default for Keypad {
+    fn default() -> Self {
+        Keypad::new()
+    }
+}
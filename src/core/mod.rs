@@ -1,5 +1,13 @@
+pub mod access;
 pub mod chip;
-mod opcode;
-mod quirks;
-mod state;
-mod handlers;
\ No newline at end of file
+pub mod display;
+// Only public for the benches
+#[doc(hidden)]
+pub mod handlers;
+pub mod instruction;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod keypad;
+pub mod quirks;
+pub mod snapshot;
+pub mod state;
@@ -1,7 +1,21 @@
+// Names of the individual flags, for toggling them one at a time
+pub const FLAGS: [&str; 6] = [
+    "increment_index",
+    "shifting",
+    "jumping",
+    "vf_reset",
+    "display_wait",
+    "clipping",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Quirks {
     increment_index: bool,
     shifting: bool,
     jumping: bool,
+    vf_reset: bool,
+    display_wait: bool,
+    clipping: bool,
 }
 
 impl Quirks {
@@ -10,7 +24,67 @@ impl Quirks {
             increment_index: true,
             shifting: true,
             jumping: true,
+            vf_reset: true,
+            display_wait: true,
+            clipping: true,
+        }
+    }
+
+    pub fn for_schip() -> Quirks {
+        Quirks {
+            increment_index: false,
+            shifting: false,
+            jumping: false,
+            vf_reset: false,
+            display_wait: false,
+            clipping: true,
+        }
+    }
+
+    pub fn for_xochip() -> Quirks {
+        Quirks {
+            increment_index: true,
+            shifting: true,
+            jumping: true,
+            vf_reset: false,
+            display_wait: false,
+            clipping: false,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "chip8" => Some(Quirks::for_chip8()),
+            "schip" => Some(Quirks::for_schip()),
+            "xochip" => Some(Quirks::for_xochip()),
+            _ => None,
+        }
+    }
+
+    pub fn flag(&self, name: &str) -> Option<bool> {
+        match name {
+            "increment_index" => Some(self.increment_index),
+            "shifting" => Some(self.shifting),
+            "jumping" => Some(self.jumping),
+            "vf_reset" => Some(self.vf_reset),
+            "display_wait" => Some(self.display_wait),
+            "clipping" => Some(self.clipping),
+            _ => None,
+        }
+    }
+
+    pub fn set_flag(&mut self, name: &str, on: bool) -> Result<(), String> {
+        match name {
+            "increment_index" => self.increment_index = on,
+            "shifting" => self.shifting = on,
+            "jumping" => self.jumping = on,
+            "vf_reset" => self.vf_reset = on,
+            "display_wait" => self.display_wait = on,
+            "clipping" => self.clipping = on,
+            _ => return Err(format!("unknown quirk '{}'", name)),
         }
+
+        Ok(())
     }
 
     pub fn has_increment_index(&self) -> bool {
@@ -24,4 +98,40 @@ impl Quirks {
     pub fn has_shifting(&self) -> bool {
         self.shifting
     }
+
+    pub fn has_vf_reset(&self) -> bool {
+        self.vf_reset
+    }
+
+    pub fn has_display_wait(&self) -> bool {
+        self.display_wait
+    }
+
+    pub fn has_clipping(&self) -> bool {
+        self.clipping
+    }
+
+    pub fn set_display_wait(&mut self, display_wait: bool) {
+        self.display_wait = display_wait;
+    }
+
+    pub fn set_clipping(&mut self, clipping: bool) {
+        self.clipping = clipping;
+    }
+
+    pub fn set_increment_index(&mut self, increment_index: bool) {
+        self.increment_index = increment_index;
+    }
+
+    pub fn set_shifting(&mut self, shifting: bool) {
+        self.shifting = shifting;
+    }
+
+    pub fn set_jumping(&mut self, jumping: bool) {
+        self.jumping = jumping;
+    }
+
+    pub fn set_vf_reset(&mut self, vf_reset: bool) {
+        self.vf_reset = vf_reset;
+    }
 }
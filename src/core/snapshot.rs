@@ -0,0 +1,305 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+
+use crate::core::display::{Display, SCREEN_HEIGHT};
+use crate::core::keypad::Keypad;
+use crate::core::state::{ChipState, ADDRESS_MASK, STACK_SIZE};
+
+const MAGIC: &[u8; 4] = b"C8S2";
+const NO_KEY: u8 = 0xFF;
+
+// Every snapshot has the same size so frontends can allocate it up front
+pub const SNAPSHOT_SIZE: usize = 4
+    + 4096
+    + 16
+    + 2
+    + 2
+    + 1
+    + 1
+    + 5
+    + 1
+    + STACK_SIZE * 2
+    + SCREEN_HEIGHT * 8
+    + 2
+    + 1
+    + 8
+    + 32
+    + 8
+    + 16;
+
+// Everything that decides how the machine runs on, so a restored snapshot
+// draws the same random numbers and sees the same keys as the original
+pub struct Snapshot {
+    pub state: ChipState,
+    pub display: Display,
+    pub keypad: Keypad,
+    pub rng: ChaCha12Rng,
+    pub sound_playing: bool,
+    pub cycles: u64,
+}
+
+pub fn encode(
+    state: &ChipState,
+    display: &Display,
+    keypad: &Keypad,
+    rng: &ChaCha12Rng,
+    sound_playing: bool,
+    cycles: u64,
+) -> Result<Vec<u8>, String> {
+    if state.stack.len() > STACK_SIZE {
+        return Err(format!(
+            "the stack is {} calls deep, snapshots hold {}",
+            state.stack.len(),
+            STACK_SIZE
+        ));
+    }
+
+    let mut data = Vec::with_capacity(SNAPSHOT_SIZE);
+
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&state.memory);
+    data.extend_from_slice(&state.registers);
+    data.extend_from_slice(&state.vi.to_be_bytes());
+    data.extend_from_slice(&state.pc.to_be_bytes());
+    data.push(state.delay_timer);
+    data.push(state.sound_timer);
+
+    for flag in [
+        state.should_draw,
+        state.running,
+        state.did_jump,
+        state.should_wait,
+        sound_playing,
+    ] {
+        data.push(flag as u8);
+    }
+
+    data.push(state.stack.len() as u8);
+    for slot in 0..STACK_SIZE {
+        let address = state.stack.get(slot).copied().unwrap_or(0);
+        data.extend_from_slice(&address.to_be_bytes());
+    }
+
+    for row in display.screen_memory {
+        data.extend_from_slice(&row.to_be_bytes());
+    }
+
+    let keys = (0..16)
+        .filter(|&key| keypad.is_pressed(key))
+        .fold(0u16, |keys, key| keys | 1 << key);
+    data.extend_from_slice(&keys.to_be_bytes());
+    data.push(keypad.released().unwrap_or(NO_KEY));
+
+    data.extend_from_slice(&cycles.to_be_bytes());
+
+    data.extend_from_slice(&rng.get_seed());
+    data.extend_from_slice(&rng.get_stream().to_be_bytes());
+    data.extend_from_slice(&rng.get_word_pos().to_be_bytes());
+
+    Ok(data)
+}
+
+pub fn decode(data: &[u8]) -> Result<Snapshot, String> {
+    if data.len() < SNAPSHOT_SIZE || &data[0..4] != MAGIC {
+        return Err("not a CHIP-8 snapshot".to_string());
+    }
+
+    let mut reader = Reader { data, position: 4 };
+    let mut state = ChipState::init();
+    let mut display = Display::new();
+    let mut keypad = Keypad::new();
+
+    state.memory.copy_from_slice(reader.take(4096));
+    state.registers.copy_from_slice(reader.take(16));
+    state.vi = reader.u16();
+    state.pc = reader.u16();
+    state.delay_timer = reader.u8();
+    state.sound_timer = reader.u8();
+    state.should_draw = reader.u8() != 0;
+    state.running = reader.u8() != 0;
+    state.did_jump = reader.u8() != 0;
+    state.should_wait = reader.u8() != 0;
+    let sound_playing = reader.u8() != 0;
+
+    let depth = reader.u8() as usize;
+    for slot in 0..STACK_SIZE {
+        let address = reader.u16();
+        if slot < depth {
+            state.stack.push(address);
+        }
+    }
+
+    // The machine never leaves these in memory, so the data is corrupt
+    let mut addresses = [state.pc, state.vi]
+        .into_iter()
+        .chain(state.stack.iter().copied());
+    if addresses.any(|address| address > ADDRESS_MASK) {
+        return Err("the snapshot points outside memory".to_string());
+    }
+
+    for row in display.screen_memory.iter_mut() {
+        *row = u64::from_be_bytes(reader.take(8).try_into().unwrap());
+    }
+
+    let keys = reader.u16();
+    let released = reader.u8();
+    // Releasing marks the key for FX0A, so do that before holding the rest
+    if released != NO_KEY {
+        keypad.press(released);
+        keypad.release(released);
+    }
+    for key in (0..16).filter(|key| keys & 1 << key != 0) {
+        keypad.press(key);
+    }
+
+    let cycles = u64::from_be_bytes(reader.take(8).try_into().unwrap());
+
+    let mut rng = ChaCha12Rng::from_seed(reader.take(32).try_into().unwrap());
+    rng.set_stream(u64::from_be_bytes(reader.take(8).try_into().unwrap()));
+    rng.set_word_pos(u128::from_be_bytes(reader.take(16).try_into().unwrap()));
+
+    Ok(Snapshot {
+        state,
+        display,
+        keypad,
+        rng,
+        sound_playing,
+        cycles,
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> &'a [u8] {
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += length;
+        bytes
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_be_bytes(self.take(2).try_into().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    fn machine() -> (ChipState, Display, Keypad, ChaCha12Rng) {
+        let mut state = ChipState::init();
+        state.memory[0x300] = 0xAB;
+        state.registers[0xF] = 1;
+        state.vi = 0x123;
+        state.pc = 0x246;
+        state.delay_timer = 7;
+        state.sound_timer = 3;
+        state.should_wait = true;
+        state.stack = vec![0x202, 0x30A];
+
+        let mut display = Display::new();
+        display.screen_memory[5] = 0xF0F0;
+
+        let mut keypad = Keypad::new();
+        keypad.press(0x4);
+        keypad.release(0x4);
+        keypad.press(0xA);
+        keypad.press(0xF);
+
+        let mut rng = ChaCha12Rng::seed_from_u64(0x8);
+        for _ in 0..5 {
+            rng.gen::<u8>();
+        }
+
+        (state, display, keypad, rng)
+    }
+
+    #[test]
+    fn round_trips_the_machine() {
+        let (state, display, keypad, rng) = machine();
+        let data = encode(&state, &display, &keypad, &rng, true, 1234).unwrap();
+        assert_eq!(data.len(), SNAPSHOT_SIZE);
+
+        let mut snapshot = decode(&data).unwrap();
+        assert_eq!(snapshot.state.memory, state.memory);
+        assert_eq!(snapshot.state.registers, state.registers);
+        assert_eq!(snapshot.state.vi, 0x123);
+        assert_eq!(snapshot.state.pc, 0x246);
+        assert_eq!(snapshot.state.stack, [0x202, 0x30A]);
+        assert!(snapshot.state.should_wait);
+        assert_eq!(snapshot.display.screen_memory, display.screen_memory);
+        assert!(snapshot.sound_playing);
+        assert_eq!(snapshot.cycles, 1234);
+
+        let pressed: Vec<u8> = (0..16).filter(|&k| snapshot.keypad.is_pressed(k)).collect();
+        assert_eq!(pressed, [0xA, 0xF]);
+        assert_eq!(snapshot.keypad.take_released(), Some(0x4));
+
+        let mut original = rng.clone();
+        let expected: Vec<u8> = (0..32).map(|_| original.gen()).collect();
+        let restored: Vec<u8> = (0..32).map(|_| snapshot.rng.gen()).collect();
+        assert_eq!(restored, expected);
+    }
+
+    #[test]
+    fn encodes_what_it_decodes() {
+        let (state, display, keypad, rng) = machine();
+        let data = encode(&state, &display, &keypad, &rng, false, 99).unwrap();
+        let snapshot = decode(&data).unwrap();
+
+        let again = encode(
+            &snapshot.state,
+            &snapshot.display,
+            &snapshot.keypad,
+            &snapshot.rng,
+            snapshot.sound_playing,
+            snapshot.cycles,
+        )
+        .unwrap();
+        assert_eq!(again, data);
+    }
+
+    #[test]
+    fn rejects_other_data() {
+        let (state, display, keypad, rng) = machine();
+        let mut data = encode(&state, &display, &keypad, &rng, false, 0).unwrap();
+
+        assert!(decode(&data[..SNAPSHOT_SIZE - 1]).is_err());
+        data[3] = b'1';
+        assert!(decode(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_addresses_outside_memory() {
+        for field in ["pc", "vi", "stack"] {
+            let (mut state, display, keypad, rng) = machine();
+            match field {
+                "pc" => state.pc = 0xFFFF,
+                "vi" => state.vi = 0x1000,
+                _ => state.stack.push(0x1000),
+            }
+
+            let data = encode(&state, &display, &keypad, &rng, false, 0).unwrap();
+            let error = decode(&data).err().unwrap();
+            assert_eq!(error, "the snapshot points outside memory");
+        }
+    }
+
+    #[test]
+    fn refuses_stacks_deeper_than_it_holds() {
+        let (mut state, display, keypad, rng) = machine();
+        state.stack = vec![0x200; STACK_SIZE + 1];
+
+        let error = encode(&state, &display, &keypad, &rng, false, 0).unwrap_err();
+        assert!(error.contains("256 calls deep"));
+    }
+}
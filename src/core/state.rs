@@ -1,4 +1,9 @@
-#[derive(Debug)]
+// Addresses wrap around the 4K of memory
+pub const ADDRESS_MASK: u16 = 0xFFF;
+// Calls nested deeper than this halt the machine
+pub const STACK_SIZE: usize = 255;
+
+#[derive(Debug, PartialEq, Hash)]
 pub struct ChipState {
     pub memory: [u8; 4096],
     pub stack: Vec<u16>,
@@ -39,7 +44,7 @@ impl ChipState {
         ];
 
         for (index, byte) in font_data.iter().enumerate() {
-            let address = index + 0x50 as usize;
+            let address = index + 0x50;
             memory[address] = *byte;
         }
 
@@ -59,6 +64,11 @@ impl ChipState {
     }
 
     pub fn skip(&mut self) {
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2) & ADDRESS_MASK;
+    }
+
+    // Where the byte `offset` past I is, wrapping around memory
+    pub fn index_address(&self, offset: u16) -> usize {
+        (self.vi.wrapping_add(offset) & ADDRESS_MASK) as usize
     }
 }
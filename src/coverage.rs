@@ -0,0 +1,238 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+use crate::core::access::{memory_access, Access};
+use crate::screenshot::write_png;
+
+const MEMORY_SIZE: usize = 4096;
+const BYTES_PER_ROW: usize = 64;
+const IMAGE_SCALE: usize = 8;
+
+pub const EXECUTED: u8 = 1;
+pub const READ: u8 = 2;
+pub const WRITTEN: u8 = 4;
+
+// How every byte of memory was touched during a run
+pub struct Coverage {
+    flags: Vec<u8>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            flags: vec![0; MEMORY_SIZE],
+        }
+    }
+
+    // Called before the instruction runs, while `vi` still points at its operands
+    pub fn record(&mut self, pc: u16, code: u16, vi: u16) {
+        self.mark(pc, 2, EXECUTED);
+
+        match memory_access(code, vi) {
+            Some((Access::Read, start, length)) => self.mark(start, length, READ),
+            Some((Access::Write, start, length)) => self.mark(start, length, WRITTEN),
+            None => {}
+        }
+    }
+
+    fn mark(&mut self, start: u16, length: u16, flag: u8) {
+        for offset in 0..length as usize {
+            self.flags[(start as usize + offset) % MEMORY_SIZE] |= flag;
+        }
+    }
+
+    pub fn flags(&self, address: u16) -> u8 {
+        self.flags[address as usize % MEMORY_SIZE]
+    }
+
+    pub fn is_executed(&self, address: u16) -> bool {
+        self.flags(address) & EXECUTED != 0
+    }
+
+    pub fn is_read(&self, address: u16) -> bool {
+        self.flags(address) & READ != 0
+    }
+
+    pub fn is_written(&self, address: u16) -> bool {
+        self.flags(address) & WRITTEN != 0
+    }
+
+    pub fn count(&self, flag: u8) -> usize {
+        self.flags.iter().filter(|f| *f & flag != 0).count()
+    }
+
+    pub fn save_text(&self, path: &str) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        self.write_text(&mut BufWriter::new(file))
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn load(path: &str) -> Result<Coverage, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Coverage::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // Reads back the map written by `write_text`, up to the legend. Bytes
+    // shown as executed lose whether they were also read.
+    pub fn parse(text: &str) -> Result<Coverage, String> {
+        let mut coverage = Coverage::new();
+
+        for (number, line) in text.lines().take_while(|l| !l.is_empty()).enumerate() {
+            let error = || format!("line {}: not a coverage row", number + 1);
+            let (address, symbols) = line.split_once("  ").ok_or_else(error)?;
+            let address = usize::from_str_radix(address, 16).map_err(|_| error())?;
+
+            for (offset, symbol) in symbols.chars().enumerate() {
+                let flags = flags(symbol).ok_or_else(error)?;
+                *coverage.flags.get_mut(address + offset).ok_or_else(error)? = flags;
+            }
+        }
+
+        Ok(coverage)
+    }
+
+    // A map with one character per byte followed by a legend, e.g.
+    // 0200  XXXXXXXXXXXXXXXXXXXX......RRRRRRRRRR.....
+    pub fn write_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        for (row, flags) in self.flags.chunks(BYTES_PER_ROW).enumerate() {
+            let line: String = flags.iter().map(|f| symbol(*f)).collect();
+            writeln!(out, "{:04X}  {}", row * BYTES_PER_ROW, line)?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, ". untouched  X executed  R read  W written")?;
+        writeln!(out, "B read and written  S executed and written")?;
+        writeln!(out)?;
+        writeln!(out, "executed: {} bytes", self.count(EXECUTED))?;
+        writeln!(out, "read:     {} bytes", self.count(READ))?;
+        writeln!(out, "written:  {} bytes", self.count(WRITTEN))?;
+
+        out.flush()
+    }
+
+    // One square per byte, rows of 64 bytes
+    pub fn save_image(&self, path: &str) -> Result<(), String> {
+        let width = BYTES_PER_ROW * IMAGE_SCALE;
+        let height = MEMORY_SIZE / BYTES_PER_ROW * IMAGE_SCALE;
+        let mut pixels = Vec::with_capacity(width * height * 3);
+
+        for y in 0..height {
+            for x in 0..width {
+                let address = y / IMAGE_SCALE * BYTES_PER_ROW + x / IMAGE_SCALE;
+                let (r, g, b) = color(self.flags[address]);
+                pixels.extend_from_slice(&[r, g, b]);
+            }
+        }
+
+        write_png(
+            path,
+            width as u32,
+            height as u32,
+            png::ColorType::Rgb,
+            &pixels,
+        )
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage::new()
+    }
+}
+
+fn symbol(flags: u8) -> char {
+    match flags {
+        0 => '.',
+        f if f & EXECUTED != 0 && f & WRITTEN != 0 => 'S',
+        f if f & EXECUTED != 0 => 'X',
+        f if f & READ != 0 && f & WRITTEN != 0 => 'B',
+        f if f & READ != 0 => 'R',
+        _ => 'W',
+    }
+}
+
+fn flags(symbol: char) -> Option<u8> {
+    match symbol {
+        '.' => Some(0),
+        'S' => Some(EXECUTED | WRITTEN),
+        'X' => Some(EXECUTED),
+        'B' => Some(READ | WRITTEN),
+        'R' => Some(READ),
+        'W' => Some(WRITTEN),
+        _ => None,
+    }
+}
+
+fn color(flags: u8) -> (u8, u8, u8) {
+    match symbol(flags) {
+        'S' => (230, 60, 230),
+        'X' => (60, 200, 80),
+        'B' => (240, 200, 60),
+        'R' => (70, 130, 240),
+        'W' => (230, 70, 60),
+        _ => (24, 24, 24),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_both_bytes_of_an_opcode() {
+        let mut coverage = Coverage::new();
+        coverage.record(0x200, 0x6001, 0);
+
+        assert!(coverage.is_executed(0x200));
+        assert!(coverage.is_executed(0x201));
+        assert!(!coverage.is_executed(0x202));
+    }
+
+    #[test]
+    fn marks_sprite_and_register_reads() {
+        let mut coverage = Coverage::new();
+        coverage.record(0x200, 0xD015, 0x300);
+        coverage.record(0x202, 0xF265, 0x400);
+
+        assert!(coverage.is_read(0x304));
+        assert!(!coverage.is_read(0x305));
+        assert!(coverage.is_read(0x402));
+        assert!(!coverage.is_read(0x403));
+    }
+
+    #[test]
+    fn marks_stores() {
+        let mut coverage = Coverage::new();
+        coverage.record(0x200, 0xF033, 0x300);
+        coverage.record(0x202, 0xF155, 0x310);
+
+        assert_eq!(coverage.count(WRITTEN), 5);
+        assert!(coverage.is_written(0x302));
+        assert!(coverage.is_written(0x311));
+    }
+
+    #[test]
+    fn marks_stores_around_the_end_of_memory() {
+        let mut coverage = Coverage::new();
+        coverage.record(0x200, 0xF355, 0xFFE);
+
+        assert!(coverage.is_written(0xFFF));
+        assert!(coverage.is_written(0x001));
+        assert!(!coverage.is_written(0x002));
+    }
+
+    #[test]
+    fn reads_back_the_text_map() {
+        let mut coverage = Coverage::new();
+        coverage.record(0x200, 0xD015, 0x300);
+        coverage.record(0x202, 0xF155, 0x310);
+
+        let mut text = Vec::new();
+        coverage.write_text(&mut text).unwrap();
+        let loaded = Coverage::parse(&String::from_utf8(text).unwrap()).unwrap();
+
+        assert_eq!(loaded.flags, coverage.flags);
+        assert!(Coverage::parse("0FC0  XX?").is_err());
+        assert!(Coverage::parse("0FFF  XX").is_err());
+    }
+}
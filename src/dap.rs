@@ -0,0 +1,595 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use crate::core::chip::Chip8;
+use crate::debugger::{Debugger, Stop};
+use crate::disasm::disassemble;
+use crate::source_map::SourceMap;
+
+const MEMORY_SIZE: usize = 4096;
+const MEMORY_ROW: usize = 16;
+const THREAD_ID: i64 = 1;
+
+const REGISTERS_REFERENCE: i64 = 1;
+const STACK_REFERENCE: i64 = 2;
+const MEMORY_REFERENCE: i64 = 3;
+
+// What the client asked to run, turned into command line options by the caller
+pub struct Launch {
+    pub program: String,
+    pub ipf: u32,
+    pub args: Vec<String>,
+}
+
+// A Debug Adapter Protocol server. Messages are read on a separate thread and
+// handled once per frame from the emulator loop, which runs the ROM through
+// the same Debugger as the gdb server.
+pub struct DapServer {
+    requests: Receiver<Value>,
+    out: Box<dyn Write + Send>,
+    seq: i64,
+    events: Vec<Value>,
+    debugger: Debugger,
+    launch: Option<Launch>,
+    configured: bool,
+    disconnected: bool,
+    stop_on_entry: bool,
+    source_map: Option<SourceMap>,
+    source_path: Option<String>,
+    source_breakpoints: Vec<u16>,
+    instruction_breakpoints: Vec<u16>,
+}
+
+impl DapServer {
+    pub fn new(
+        input: impl Read + Send + 'static,
+        output: impl Write + Send + 'static,
+    ) -> DapServer {
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || read_messages(input, sender));
+
+        DapServer {
+            requests,
+            out: Box::new(output),
+            seq: 1,
+            events: Vec::new(),
+            debugger: Debugger::new(1),
+            launch: None,
+            configured: false,
+            disconnected: false,
+            stop_on_entry: false,
+            source_map: None,
+            source_path: None,
+            source_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+        }
+    }
+
+    pub fn stdio() -> DapServer {
+        DapServer::new(io::stdin(), io::stdout())
+    }
+
+    // Answers requests until the client launched a ROM and finished configuring
+    pub fn wait_for_launch(&mut self) -> Result<Launch, String> {
+        loop {
+            let request = self
+                .requests
+                .recv()
+                .map_err(|_| "debug client disconnected".to_string())?;
+
+            self.handle(&request, None)?;
+
+            if self.disconnected {
+                return Err("debug session ended before launch".to_string());
+            }
+
+            if self.configured {
+                if let Some(launch) = self.launch.take() {
+                    if self.stop_on_entry {
+                        self.debugger.pause();
+                        self.event("stopped", stopped_body("entry"));
+                        self.flush_events()?;
+                    }
+
+                    return Ok(launch);
+                }
+            }
+        }
+    }
+
+    // Takes the place of `Chip8::step` in the frame loop
+    pub fn run_frame(&mut self, chip: &mut Chip8) -> Result<(), String> {
+        loop {
+            match self.requests.try_recv() {
+                Ok(request) => self.handle(&request, Some(chip))?,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    chip.stop();
+                    return Ok(());
+                }
+            }
+        }
+
+        if let Some(stop) = self.debugger.run_frame(chip) {
+            self.send_stop(stop)?;
+        }
+
+        Ok(())
+    }
+
+    // Tells the client the ROM is gone, once the frame loop ended
+    pub fn finish(&mut self) -> Result<(), String> {
+        self.event("terminated", json!({}));
+        self.flush_events()
+    }
+
+    fn handle(&mut self, request: &Value, chip: Option<&mut Chip8>) -> Result<(), String> {
+        let command = request["command"].as_str().unwrap_or("");
+        let args = &request["arguments"];
+
+        let result = match (command, chip) {
+            ("initialize", _) => Ok(capabilities()),
+            ("launch", _) => self.launch(args),
+            ("setBreakpoints", _) => Ok(self.set_breakpoints(args)),
+            ("setInstructionBreakpoints", _) => Ok(self.set_instruction_breakpoints(args)),
+            ("setExceptionBreakpoints", _) => Ok(json!({ "breakpoints": [] })),
+            ("configurationDone", _) => {
+                self.configured = true;
+                Ok(json!({}))
+            }
+            ("threads", _) => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            ("disconnect" | "terminate", chip) => {
+                if let Some(chip) = chip {
+                    chip.stop();
+                }
+                self.disconnected = true;
+                Ok(json!({}))
+            }
+            (_, None) => Err(format!("{} is only available once a ROM runs", command)),
+            ("continue", Some(_)) => {
+                self.debugger.resume();
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            ("next", Some(chip)) => {
+                // Calls run to completion, everything else is a single instruction
+                if chip.peek_opcode() >> 12 == 0x2 {
+                    self.debugger.resume_until_depth(chip.state().stack.len());
+                } else {
+                    let stop = self.debugger.step(chip);
+                    self.queue_stop(stop);
+                }
+                Ok(json!({}))
+            }
+            ("stepIn", Some(chip)) => {
+                let stop = self.debugger.step(chip);
+                self.queue_stop(stop);
+                Ok(json!({}))
+            }
+            ("stepOut", Some(chip)) => {
+                match chip.state().stack.len() {
+                    0 => {
+                        let stop = self.debugger.step(chip);
+                        self.queue_stop(stop);
+                    }
+                    depth => self.debugger.resume_until_depth(depth - 1),
+                }
+                Ok(json!({}))
+            }
+            ("pause", Some(_)) => {
+                self.debugger.pause();
+                self.event("stopped", stopped_body("pause"));
+                Ok(json!({}))
+            }
+            ("stackTrace", Some(chip)) => Ok(self.stack_trace(chip)),
+            ("scopes", Some(_)) => Ok(scopes()),
+            ("variables", Some(chip)) => Ok(variables(chip, args)),
+            ("disassemble", Some(chip)) => Ok(self.disassemble(chip, args)),
+            ("readMemory", Some(chip)) => read_memory(chip, args),
+            _ => Err(format!("unsupported request {}", command)),
+        };
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+
+        self.send(response)?;
+        self.flush_events()
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"]
+            .as_str()
+            .ok_or("launch needs a program")?
+            .to_string();
+
+        fs::metadata(&program).map_err(|e| format!("{}: {}", program, e))?;
+
+        if let Some(path) = args["sourceMap"].as_str() {
+            self.source_map = Some(SourceMap::load(path)?);
+        }
+
+        self.source_path = args["source"].as_str().map(str::to_string);
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+
+        let ipf = args["ipf"].as_u64().unwrap_or(10) as u32;
+        self.debugger = Debugger::new(ipf);
+
+        let extra = match args["args"].as_array() {
+            Some(values) => values
+                .iter()
+                .filter_map(|value| value.as_str().map(str::to_string))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        self.launch = Some(Launch {
+            program,
+            ipf,
+            args: extra,
+        });
+
+        // Breakpoints can only be resolved once the source map is known
+        self.event("initialized", json!({}));
+
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Value {
+        if self.source_path.is_none() {
+            self.source_path = args["source"]["path"].as_str().map(str::to_string);
+        }
+
+        let mut addresses = Vec::new();
+        let mut breakpoints = Vec::new();
+
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let line = requested["line"].as_u64().unwrap_or(0) as u32;
+
+            match self.source_map.as_ref().and_then(|map| map.resolve(line)) {
+                Some((line, address)) => {
+                    addresses.push(address);
+                    breakpoints.push(json!({
+                        "verified": true,
+                        "line": line,
+                        "instructionReference": reference(address),
+                    }));
+                }
+                None => breakpoints.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "no code is mapped to this line, launch with a sourceMap",
+                })),
+            }
+        }
+
+        self.source_breakpoints = addresses;
+        self.update_breakpoints();
+
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Value {
+        let mut addresses = Vec::new();
+        let mut breakpoints = Vec::new();
+
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let address = parse_reference(&requested["instructionReference"])
+                .map(|address| address + requested["offset"].as_i64().unwrap_or(0));
+
+            match address {
+                Some(address) if (0..MEMORY_SIZE as i64).contains(&address) => {
+                    addresses.push(address as u16);
+                    breakpoints.push(json!({ "verified": true }));
+                }
+                _ => breakpoints.push(json!({ "verified": false })),
+            }
+        }
+
+        self.instruction_breakpoints = addresses;
+        self.update_breakpoints();
+
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn update_breakpoints(&mut self) {
+        self.debugger.clear_breakpoints();
+
+        for address in self
+            .source_breakpoints
+            .iter()
+            .chain(&self.instruction_breakpoints)
+        {
+            self.debugger.add_breakpoint(*address);
+        }
+    }
+
+    // The innermost frame is PC, the others are the 2NNN call sites on the stack
+    fn stack_trace(&self, chip: &Chip8) -> Value {
+        let state = chip.state();
+        let addresses = std::iter::once(state.pc).chain(state.stack.iter().rev().copied());
+
+        let frames: Vec<Value> = addresses
+            .enumerate()
+            .map(|(id, address)| {
+                let mut frame = json!({
+                    "id": id,
+                    "name": format!("{:03X}  {}", address, disassemble(opcode_at(chip, address))),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": reference(address),
+                });
+
+                if let Some(line) = self.line_of(address) {
+                    frame["line"] = json!(line);
+                    frame["column"] = json!(1);
+                    frame["source"] = json!({ "path": self.source_path });
+                }
+
+                frame
+            })
+            .collect();
+
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn line_of(&self, address: u16) -> Option<u32> {
+        self.source_path.as_ref()?;
+        self.source_map.as_ref()?.line_of(address)
+    }
+
+    fn disassemble(&self, chip: &Chip8, args: &Value) -> Value {
+        let start = parse_reference(&args["memoryReference"]).unwrap_or(0)
+            + args["offset"].as_i64().unwrap_or(0)
+            + args["instructionOffset"].as_i64().unwrap_or(0) * 2;
+        let count = args["instructionCount"].as_i64().unwrap_or(0);
+
+        let instructions: Vec<Value> = (0..count)
+            .map(|index| {
+                let address = start + index * 2;
+
+                if !(0..MEMORY_SIZE as i64 - 1).contains(&address) {
+                    return json!({
+                        "address": format!("0x{:X}", address),
+                        "instruction": "",
+                        "presentationHint": "invalid",
+                    });
+                }
+
+                let code = opcode_at(chip, address as u16);
+                let mut instruction = json!({
+                    "address": reference(address as u16),
+                    "instructionBytes": format!("{:04X}", code),
+                    "instruction": disassemble(code),
+                });
+
+                if let Some(line) = self.line_of(address as u16) {
+                    instruction["line"] = json!(line);
+                    instruction["location"] = json!({ "path": self.source_path });
+                }
+
+                instruction
+            })
+            .collect();
+
+        json!({ "instructions": instructions })
+    }
+
+    fn queue_stop(&mut self, stop: Stop) {
+        match stop {
+            Stop::Step => self.event("stopped", stopped_body("step")),
+            Stop::Breakpoint(_) => self.event("stopped", stopped_body("breakpoint")),
+            Stop::Watchpoint(_, _) => self.event("stopped", stopped_body("data breakpoint")),
+            Stop::Halted => self.event("terminated", json!({})),
+        }
+    }
+
+    fn send_stop(&mut self, stop: Stop) -> Result<(), String> {
+        self.queue_stop(stop);
+        self.flush_events()
+    }
+
+    fn event(&mut self, name: &str, body: Value) {
+        self.events
+            .push(json!({ "type": "event", "event": name, "body": body }));
+    }
+
+    // Events go out after the response of the request that caused them
+    fn flush_events(&mut self) -> Result<(), String> {
+        for event in std::mem::take(&mut self.events) {
+            self.send(event)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, mut message: Value) -> Result<(), String> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+
+        let body = message.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+            .and_then(|_| self.out.flush())
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn read_messages(input: impl Read, sender: Sender<Value>) {
+    let mut reader = BufReader::new(input);
+
+    loop {
+        let mut length = None;
+
+        // Headers end with an empty line
+        loop {
+            let mut line = String::new();
+            match reader.read_line(&mut line) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+
+        let mut body = vec![0; length.unwrap_or(0)];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+
+        if let Ok(message) = serde_json::from_slice::<Value>(&body) {
+            if sender.send(message).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsDisassembleRequest": true,
+        "supportsInstructionBreakpoints": true,
+        "supportsReadMemoryRequest": true,
+        "supportsTerminateRequest": true,
+    })
+}
+
+fn stopped_body(reason: &str) -> Value {
+    json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true })
+}
+
+fn scopes() -> Value {
+    json!({
+        "scopes": [
+            { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+            { "name": "Stack", "variablesReference": STACK_REFERENCE, "expensive": false },
+            { "name": "Memory", "variablesReference": MEMORY_REFERENCE, "expensive": true },
+        ]
+    })
+}
+
+fn variable(name: &str, value: String) -> Value {
+    json!({ "name": name, "value": value, "variablesReference": 0 })
+}
+
+fn pointer(name: &str, address: u16) -> Value {
+    let mut pointer = variable(name, reference(address));
+    pointer["memoryReference"] = json!(reference(address));
+    pointer
+}
+
+fn variables(chip: &Chip8, args: &Value) -> Value {
+    let state = chip.state();
+
+    let variables: Vec<Value> = match args["variablesReference"].as_i64() {
+        Some(REGISTERS_REFERENCE) => {
+            let mut registers: Vec<Value> = state
+                .registers
+                .iter()
+                .enumerate()
+                .map(|(index, value)| {
+                    variable(&format!("V{:X}", index), format!("0x{:02X}", value))
+                })
+                .collect();
+
+            registers.push(pointer("I", state.vi));
+            registers.push(pointer("PC", state.pc));
+            registers.push(variable("SP", state.stack.len().to_string()));
+            registers.push(variable("DT", state.delay_timer.to_string()));
+            registers.push(variable("ST", state.sound_timer.to_string()));
+            registers
+        }
+        Some(STACK_REFERENCE) => state
+            .stack
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(index, address)| pointer(&format!("#{}", index), *address))
+            .collect(),
+        Some(MEMORY_REFERENCE) => state
+            .memory
+            .chunks(MEMORY_ROW)
+            .enumerate()
+            .map(|(row, bytes)| {
+                let address = (row * MEMORY_ROW) as u16;
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+
+                let mut line = variable(&reference(address), hex.join(" "));
+                line["memoryReference"] = json!(reference(address));
+                line
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    json!({ "variables": variables })
+}
+
+fn read_memory(chip: &Chip8, args: &Value) -> Result<Value, String> {
+    let start = parse_reference(&args["memoryReference"]).ok_or("invalid memory reference")?
+        + args["offset"].as_i64().unwrap_or(0);
+    let count = args["count"].as_i64().unwrap_or(0).max(0);
+
+    let first = start.clamp(0, MEMORY_SIZE as i64) as usize;
+    let last = (start + count).clamp(0, MEMORY_SIZE as i64) as usize;
+    let bytes = &chip.state().memory[first..last.max(first)];
+
+    Ok(json!({
+        "address": format!("0x{:X}", start),
+        "data": base64(bytes),
+        "unreadableBytes": count - bytes.len() as i64,
+    }))
+}
+
+fn opcode_at(chip: &Chip8, address: u16) -> u16 {
+    let memory = &chip.state().memory;
+    let address = address as usize % MEMORY_SIZE;
+    u16::from_be_bytes([memory[address], memory[(address + 1) % MEMORY_SIZE]])
+}
+
+fn reference(address: u16) -> String {
+    format!("0x{:03X}", address)
+}
+
+fn parse_reference(value: &Value) -> Option<i64> {
+    let text = value.as_str()?;
+    i64::from_str_radix(text.trim_start_matches("0x"), 16).ok()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+
+    for chunk in bytes.chunks(3) {
+        let word = chunk.iter().enumerate().fold(0u32, |word, (index, byte)| {
+            word | (*byte as u32) << (16 - index * 8)
+        });
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(ALPHABET[(word >> (18 - index * 6)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
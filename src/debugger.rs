@@ -0,0 +1,296 @@
+use std::collections::BTreeSet;
+
+use crate::core::access::{memory_access, Access};
+use crate::core::chip::Chip8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub kind: WatchKind,
+    pub address: u16,
+    pub length: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+    Step,
+    Breakpoint(u16),
+    // Kind of the watchpoint and the first watched address that was touched
+    Watchpoint(WatchKind, u16),
+    Halted,
+}
+
+// Runs a Chip8 frame by frame like `Chip8::step`, but can pause between
+// any two instructions. Timers only tick once all of a frame's instructions
+// ran, so pausing mid-frame freezes them too.
+pub struct Debugger {
+    ipf: u32,
+    executed: u32,
+    paused: bool,
+    resuming: bool,
+    target_depth: Option<usize>,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl Debugger {
+    pub fn new(ipf: u32) -> Debugger {
+        Debugger {
+            ipf,
+            executed: 0,
+            paused: false,
+            resuming: false,
+            target_depth: None,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.target_depth = None;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        // Don't stop again on the breakpoint we are sitting on
+        self.resuming = true;
+        self.target_depth = None;
+    }
+
+    // Runs until the call stack is at most `depth` deep, which steps over a
+    // 2NNN with the current depth or out of the current subroutine with one less
+    pub fn resume_until_depth(&mut self, depth: usize) {
+        self.resume();
+        self.target_depth = Some(depth);
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &u16> {
+        self.breakpoints.iter()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| *w != watchpoint);
+        self.watchpoints.len() != count
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    // Runs what is left of the current frame unless paused, returns why it stopped early
+    pub fn run_frame(&mut self, chip: &mut Chip8) -> Option<Stop> {
+        while !self.paused {
+            if !chip.is_running() {
+                self.pause();
+                return Some(Stop::Halted);
+            }
+
+            let pc = chip.state().pc;
+            let resuming = std::mem::take(&mut self.resuming);
+
+            // FX0A runs again every cycle until a key comes, only stop there once
+            if self.breakpoints.contains(&pc) && !resuming && !chip.state().should_wait {
+                self.pause();
+                return Some(Stop::Breakpoint(pc));
+            }
+
+            let (frame_ended, hit) = self.execute(chip);
+
+            if let Some(stop) = hit {
+                self.pause();
+                return Some(stop);
+            }
+
+            if let Some(depth) = self.target_depth {
+                if chip.state().stack.len() <= depth {
+                    self.pause();
+                    return Some(Stop::Step);
+                }
+            }
+
+            if frame_ended {
+                break;
+            }
+        }
+
+        None
+    }
+
+    // Runs a single instruction and stays paused
+    pub fn step(&mut self, chip: &mut Chip8) -> Stop {
+        self.pause();
+        self.resuming = false;
+
+        if !chip.is_running() {
+            return Stop::Halted;
+        }
+
+        let (_, hit) = self.execute(chip);
+        hit.unwrap_or(Stop::Step)
+    }
+
+    fn execute(&mut self, chip: &mut Chip8) -> (bool, Option<Stop>) {
+        let access = memory_access(chip.peek_opcode(), chip.state().vi);
+
+        let skip_rest = chip.step_instruction();
+        self.executed += 1;
+
+        let frame_ended = skip_rest || self.executed >= self.ipf;
+        if frame_ended {
+            chip.end_frame();
+            self.executed = 0;
+        }
+
+        (
+            frame_ended,
+            access.and_then(|access| self.watch_hit(access)),
+        )
+    }
+
+    fn watch_hit(&self, (access, start, length): (Access, u16, u16)) -> Option<Stop> {
+        self.watchpoints.iter().find_map(|watch| {
+            let matches = match watch.kind {
+                WatchKind::Write => access == Access::Write,
+                WatchKind::Read => access == Access::Read,
+                WatchKind::Access => true,
+            };
+
+            // In u32, since I can point anywhere up to FFFF
+            let first = start.max(watch.address) as u32;
+            let end =
+                (start as u32 + length as u32).min(watch.address as u32 + watch.length as u32);
+
+            if matches && first < end {
+                Some(Stop::Watchpoint(watch.kind, first as u16))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chip(program: &[u8]) -> Chip8 {
+        let mut chip = Chip8::new();
+        chip.load_rom(program).unwrap();
+        chip
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_resumes_past_them() {
+        // 200: LD V0, 1  202: ADD V0, 1  204: JP 202
+        let mut chip = chip(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02]);
+        let mut debugger = Debugger::new(10);
+
+        debugger.add_breakpoint(0x202);
+
+        assert_eq!(debugger.run_frame(&mut chip), Some(Stop::Breakpoint(0x202)));
+        assert_eq!(chip.state().registers[0], 1);
+
+        debugger.resume();
+
+        assert_eq!(debugger.run_frame(&mut chip), Some(Stop::Breakpoint(0x202)));
+        assert_eq!(chip.state().registers[0], 2);
+    }
+
+    #[test]
+    fn steps_over_and_out_of_subroutines() {
+        // 200: CALL 206  202: LD V1, 1  204: JP 204  206: LD V0, 1  208: RET
+        let mut chip = chip(&[0x22, 0x06, 0x61, 0x01, 0x12, 0x04, 0x60, 0x01, 0x00, 0xEE]);
+        let mut debugger = Debugger::new(10);
+
+        debugger.resume_until_depth(0);
+        assert_eq!(debugger.run_frame(&mut chip), Some(Stop::Step));
+        assert_eq!(chip.state().pc, 0x202);
+        assert_eq!(chip.state().registers[0], 1);
+
+        chip.state_mut().pc = 0x200;
+        debugger.step(&mut chip);
+        debugger.resume_until_depth(0);
+        assert_eq!(debugger.run_frame(&mut chip), Some(Stop::Step));
+        assert_eq!(chip.state().pc, 0x202);
+    }
+
+    #[test]
+    fn ticks_timers_only_after_a_whole_frame() {
+        // 200: LD V0, 5  202: LD DT, V0  204: JP 204
+        let mut chip = chip(&[0x60, 0x05, 0xF0, 0x15, 0x12, 0x04]);
+        let mut debugger = Debugger::new(4);
+
+        debugger.pause();
+        debugger.step(&mut chip);
+        debugger.step(&mut chip);
+        debugger.step(&mut chip);
+        assert_eq!(chip.state().delay_timer, 5);
+
+        debugger.step(&mut chip);
+        assert_eq!(chip.state().delay_timer, 4);
+    }
+
+    #[test]
+    fn reports_watchpoints_after_the_access() {
+        // 200: LD I, 0x300  202: LD V0, 7  204: LD [I], V1
+        let mut chip = chip(&[0xA3, 0x00, 0x60, 0x07, 0xF1, 0x55]);
+        let mut debugger = Debugger::new(10);
+
+        debugger.add_watchpoint(Watchpoint {
+            kind: WatchKind::Write,
+            address: 0x301,
+            length: 1,
+        });
+
+        assert_eq!(
+            debugger.run_frame(&mut chip),
+            Some(Stop::Watchpoint(WatchKind::Write, 0x301))
+        );
+        assert_eq!(chip.state().memory[0x300], 7);
+        assert_eq!(chip.state().pc, 0x206);
+    }
+
+    #[test]
+    fn matches_accesses_at_the_top_of_the_address_space() {
+        let mut debugger = Debugger::new(10);
+        debugger.add_watchpoint(Watchpoint {
+            kind: WatchKind::Access,
+            address: 0xFFFE,
+            length: 4,
+        });
+
+        assert_eq!(
+            debugger.watch_hit((Access::Read, 0xFFF0, 0xF)),
+            Some(Stop::Watchpoint(WatchKind::Access, 0xFFFE))
+        );
+        assert_eq!(debugger.watch_hit((Access::Write, 0xFFF0, 0xE)), None);
+    }
+}
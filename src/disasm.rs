@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+
+// Mnemonics follow Cowgod's CHIP-8 technical reference
+pub fn disassemble(code: u16) -> String {
+    let x = (code >> 8) & 0xF;
+    let y = (code >> 4) & 0xF;
+    let n = code & 0xF;
+    let nn = code & 0xFF;
+    let nnn = code & 0xFFF;
+
+    match (code >> 12, x, y, n) {
+        (0, 0, 0xE, 0) => "CLS".to_string(),
+        (0, 0, 0xE, 0xE) => "RET".to_string(),
+        (0, _, _, _) => format!("SYS 0x{:03X}", nnn),
+        (1, _, _, _) => format!("JP 0x{:03X}", nnn),
+        (2, _, _, _) => format!("CALL 0x{:03X}", nnn),
+        (3, _, _, _) => format!("SE V{:X}, 0x{:02X}", x, nn),
+        (4, _, _, _) => format!("SNE V{:X}, 0x{:02X}", x, nn),
+        (5, _, _, 0) => format!("SE V{:X}, V{:X}", x, y),
+        (6, _, _, _) => format!("LD V{:X}, 0x{:02X}", x, nn),
+        (7, _, _, _) => format!("ADD V{:X}, 0x{:02X}", x, nn),
+        (8, _, _, 0) => format!("LD V{:X}, V{:X}", x, y),
+        (8, _, _, 1) => format!("OR V{:X}, V{:X}", x, y),
+        (8, _, _, 2) => format!("AND V{:X}, V{:X}", x, y),
+        (8, _, _, 3) => format!("XOR V{:X}, V{:X}", x, y),
+        (8, _, _, 4) => format!("ADD V{:X}, V{:X}", x, y),
+        (8, _, _, 5) => format!("SUB V{:X}, V{:X}", x, y),
+        (8, _, _, 6) => format!("SHR V{:X}, V{:X}", x, y),
+        (8, _, _, 7) => format!("SUBN V{:X}, V{:X}", x, y),
+        (8, _, _, 0xE) => format!("SHL V{:X}, V{:X}", x, y),
+        (9, _, _, 0) => format!("SNE V{:X}, V{:X}", x, y),
+        (0xA, _, _, _) => format!("LD I, 0x{:03X}", nnn),
+        (0xB, _, _, _) => format!("JP V0, 0x{:03X}", nnn),
+        (0xC, _, _, _) => format!("RND V{:X}, 0x{:02X}", x, nn),
+        (0xD, _, _, _) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        (0xE, _, 9, 0xE) => format!("SKP V{:X}", x),
+        (0xE, _, 0xA, 1) => format!("SKNP V{:X}", x),
+        (0xF, _, 0, 7) => format!("LD V{:X}, DT", x),
+        (0xF, _, 0, 0xA) => format!("LD V{:X}, K", x),
+        (0xF, _, 1, 5) => format!("LD DT, V{:X}", x),
+        (0xF, _, 1, 8) => format!("LD ST, V{:X}", x),
+        (0xF, _, 1, 0xE) => format!("ADD I, V{:X}", x),
+        (0xF, _, 2, 9) => format!("LD F, V{:X}", x),
+        (0xF, _, 3, 3) => format!("LD B, V{:X}", x),
+        (0xF, _, 5, 5) => format!("LD [I], V{:X}", x),
+        (0xF, _, 6, 5) => format!("LD V{:X}, [I]", x),
+        _ => format!("DW 0x{:04X}", code),
+    }
+}
+
+// Jump and call targets use the label given for them, if any
+pub fn disassemble_labeled(code: u16, labels: &BTreeMap<u16, String>) -> String {
+    let label = labels.get(&(code & 0xFFF));
+
+    match (code >> 12, label) {
+        (1, Some(label)) => format!("JP {}", label),
+        (2, Some(label)) => format!("CALL {}", label),
+        _ => disassemble(code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassembles_fixed_opcodes() {
+        assert_eq!(disassemble(0x00E0), "CLS");
+        assert_eq!(disassemble(0x00EE), "RET");
+    }
+
+    #[test]
+    fn disassembles_operands() {
+        assert_eq!(disassemble(0x2ABC), "CALL 0xABC");
+        assert_eq!(disassemble(0x8AB4), "ADD VA, VB");
+        assert_eq!(disassemble(0xD01F), "DRW V0, V1, 15");
+        assert_eq!(disassemble(0xF355), "LD [I], V3");
+    }
+
+    #[test]
+    fn uses_labels_for_targets() {
+        let labels = BTreeMap::from([(0x2A4, "sub_2A4".to_string())]);
+
+        assert_eq!(disassemble_labeled(0x22A4, &labels), "CALL sub_2A4");
+        assert_eq!(disassemble_labeled(0x12A4, &labels), "JP sub_2A4");
+        assert_eq!(disassemble_labeled(0xA2A4, &labels), "LD I, 0x2A4");
+    }
+
+    #[test]
+    fn falls_back_to_data_words() {
+        assert_eq!(disassemble(0x5121), "DW 0x5121");
+        assert_eq!(disassemble(0xFFFF), "DW 0xFFFF");
+    }
+}
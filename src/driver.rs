@@ -0,0 +1,71 @@
+use chip_8::core::chip::Chip8;
+use chip_8::dap::DapServer;
+use chip_8::gdb::GdbServer;
+#[cfg(feature = "scripting")]
+use chip_8::script::Script;
+
+use crate::cli::Options;
+
+// Whatever advances the ROM by one frame in the main loop
+pub enum Driver {
+    Plain(u32),
+    Gdb(Box<GdbServer>),
+    Dap(Box<DapServer>),
+    #[cfg(feature = "scripting")]
+    Script(Box<Script>),
+}
+
+impl Driver {
+    // The chip is only needed to load a script
+    #[cfg_attr(not(feature = "scripting"), allow(unused_variables))]
+    pub fn new(
+        options: &Options,
+        chip: &mut Chip8,
+        dap: Option<DapServer>,
+    ) -> Result<Driver, String> {
+        #[cfg(feature = "scripting")]
+        if options.script.is_some() && (options.gdb.is_some() || dap.is_some()) {
+            return Err("scripts can't run under a debugger".to_string());
+        }
+
+        if let Some(dap) = dap {
+            return Ok(Driver::Dap(Box::new(dap)));
+        }
+
+        if let Some(port) = options.gdb {
+            let mut server = GdbServer::bind(&format!("127.0.0.1:{}", port), options.ipf)?;
+            eprintln!("waiting for gdb on {}", server.local_addr()?);
+            server.wait_for_client()?;
+
+            return Ok(Driver::Gdb(Box::new(server)));
+        }
+
+        #[cfg(feature = "scripting")]
+        if let Some(path) = &options.script {
+            let script = Script::load(path, options.ipf, chip)?;
+            return Ok(Driver::Script(Box::new(script)));
+        }
+
+        Ok(Driver::Plain(options.ipf))
+    }
+
+    pub fn run_frame(&mut self, chip: &mut Chip8) -> Result<(), String> {
+        match self {
+            Driver::Plain(ipf) => {
+                chip.step(*ipf);
+                Ok(())
+            }
+            Driver::Gdb(server) => server.run_frame(chip),
+            Driver::Dap(server) => server.run_frame(chip),
+            #[cfg(feature = "scripting")]
+            Driver::Script(script) => script.run_frame(chip),
+        }
+    }
+
+    pub fn finish(&mut self) -> Result<(), String> {
+        match self {
+            Driver::Dap(server) => server.finish(),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,10 @@
+use chip_8::core::chip::Chip8;
+
+pub trait Frontend {
+    fn handle_events(&mut self, chip: &mut Chip8);
+
+    // Called once per frame after the chip stepped, with the buzzer samples for that frame
+    fn update(&mut self, chip: &mut Chip8, samples: &[f32]);
+
+    fn finish(&mut self) {}
+}
@@ -0,0 +1,498 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+use crate::core::chip::Chip8;
+use crate::core::state::ADDRESS_MASK;
+use crate::debugger::{Debugger, Stop, WatchKind, Watchpoint};
+
+const MEMORY_SIZE: usize = 4096;
+const INTERRUPT: u8 = 0x03;
+
+// Registers in the order of `g` packets: V0-VF, I, PC, SP, DT, ST
+const REGISTER_COUNT: usize = 21;
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0" type="uint8"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+// A gdb remote serial protocol server. It is polled once per frame from the
+// emulator loop, so the game keeps rendering while gdb holds it paused.
+pub struct GdbServer {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+    buffer: Vec<u8>,
+    no_ack: bool,
+    debugger: Debugger,
+}
+
+impl GdbServer {
+    pub fn bind(address: &str, ipf: u32) -> Result<GdbServer, String> {
+        let listener = TcpListener::bind(address).map_err(|e| e.to_string())?;
+
+        Ok(GdbServer {
+            listener,
+            stream: None,
+            buffer: Vec::new(),
+            no_ack: false,
+            debugger: Debugger::new(ipf),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.listener.local_addr().map_err(|e| e.to_string())
+    }
+
+    // Blocks until gdb connects, the ROM stays paused on its first instruction
+    pub fn wait_for_client(&mut self) -> Result<(), String> {
+        let (stream, _) = self.listener.accept().map_err(|e| e.to_string())?;
+        self.attach(stream)
+    }
+
+    fn attach(&mut self, stream: TcpStream) -> Result<(), String> {
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        stream.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+        self.stream = Some(stream);
+        self.buffer.clear();
+        self.no_ack = false;
+        self.debugger.pause();
+
+        Ok(())
+    }
+
+    fn detach(&mut self) {
+        self.stream = None;
+        self.debugger.clear_breakpoints();
+        self.debugger.clear_watchpoints();
+        self.debugger.resume();
+    }
+
+    // Takes the place of `Chip8::step` in the frame loop
+    pub fn run_frame(&mut self, chip: &mut Chip8) -> Result<(), String> {
+        if self.stream.is_none() {
+            self.accept()?;
+        }
+
+        self.poll(chip)?;
+
+        if let Some(stop) = self.debugger.run_frame(chip) {
+            self.send_stop(stop)?;
+        }
+
+        Ok(())
+    }
+
+    fn accept(&mut self) -> Result<(), String> {
+        self.listener
+            .set_nonblocking(true)
+            .map_err(|e| e.to_string())?;
+
+        match self.listener.accept() {
+            Ok((stream, _)) => self.attach(stream),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn poll(&mut self, chip: &mut Chip8) -> Result<(), String> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => return Ok(()),
+        };
+
+        let mut chunk = [0u8; 4096];
+        loop {
+            match stream.read(&mut chunk) {
+                Ok(0) => {
+                    self.detach();
+                    return Ok(());
+                }
+                Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+
+        while let Some(packet) = self.next_packet()? {
+            self.handle(&packet, chip)?;
+
+            if self.stream.is_none() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Pops the next complete packet from the buffer, acknowledging it
+    fn next_packet(&mut self) -> Result<Option<String>, String> {
+        loop {
+            match self.buffer.first() {
+                None => return Ok(None),
+                Some(b'$') => break,
+                Some(&INTERRUPT) => {
+                    self.buffer.remove(0);
+                    if !self.debugger.is_paused() {
+                        self.debugger.pause();
+                        self.send("S02")?;
+                    }
+                }
+                // Acks, nacks and noise between packets
+                Some(_) => {
+                    self.buffer.remove(0);
+                }
+            }
+        }
+
+        let end = match self.buffer.iter().position(|b| *b == b'#') {
+            Some(end) if self.buffer.len() >= end + 3 => end,
+            _ => return Ok(None),
+        };
+
+        let data = String::from_utf8_lossy(&self.buffer[1..end]).into_owned();
+        let checksum = String::from_utf8_lossy(&self.buffer[end + 1..end + 3]).into_owned();
+        self.buffer.drain(..end + 3);
+
+        if !self.no_ack {
+            let valid = u8::from_str_radix(&checksum, 16) == Ok(checksum_of(data.as_bytes()));
+            self.write_raw(if valid { b"+" } else { b"-" })?;
+
+            if !valid {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(data))
+    }
+
+    fn handle(&mut self, packet: &str, chip: &mut Chip8) -> Result<(), String> {
+        let (command, args) = packet.split_at(1.min(packet.len()));
+
+        let reply = match command {
+            "?" => "S05".to_string(),
+            "g" => read_registers(chip),
+            "G" => {
+                write_registers(chip, args);
+                "OK".to_string()
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(index) if index < REGISTER_COUNT => encode_register(chip, index),
+                _ => "E01".to_string(),
+            },
+            "P" => write_register(chip, args).unwrap_or("E01").to_string(),
+            "m" => read_memory(chip, args).unwrap_or_else(|| "E01".to_string()),
+            "M" => write_memory(chip, args).unwrap_or("E01").to_string(),
+            "c" => {
+                set_resume_address(chip, args);
+                self.debugger.resume();
+                return Ok(());
+            }
+            "s" => {
+                set_resume_address(chip, args);
+                let stop = self.debugger.step(chip);
+                return self.send_stop(stop);
+            }
+            "Z" | "z" => self.set_point(command == "Z", args),
+            "k" => {
+                chip.stop();
+                self.stream = None;
+                return Ok(());
+            }
+            "D" => {
+                self.send("OK")?;
+                self.detach();
+                return Ok(());
+            }
+            "H" | "T" => "OK".to_string(),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+
+        self.send(&reply)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=4000;QStartNoAckMode+;swbreak+;hwbreak+;qXfer:features:read+".to_string()
+        } else if packet == "QStartNoAckMode" {
+            // Takes effect after this reply, which still gets acknowledged
+            self.no_ack = true;
+            "OK".to_string()
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            xfer(TARGET_XML, range).unwrap_or_else(|| "E01".to_string())
+        } else {
+            match packet {
+                "qAttached" => "1".to_string(),
+                "qC" => "QC1".to_string(),
+                "qfThreadInfo" => "m1".to_string(),
+                "qsThreadInfo" => "l".to_string(),
+                "qSymbol::" => "OK".to_string(),
+                _ => String::new(),
+            }
+        }
+    }
+
+    // Z<type>,<address>,<kind> adds a breakpoint or watchpoint, z removes it
+    fn set_point(&mut self, insert: bool, args: &str) -> String {
+        let fields: Vec<&str> = args.split(',').collect();
+        let (kind, address, length) = match fields[..] {
+            [kind, address, length, ..] => (kind, address, length),
+            _ => return "E01".to_string(),
+        };
+
+        let (address, length) = match (
+            u16::from_str_radix(address, 16),
+            u16::from_str_radix(length.split(';').next().unwrap_or(""), 16),
+        ) {
+            (Ok(address), Ok(length)) => (address, length),
+            _ => return "E01".to_string(),
+        };
+
+        // Watched bytes must lie in memory
+        if matches!(kind, "2" | "3" | "4")
+            && address as usize + length.max(1) as usize > MEMORY_SIZE
+        {
+            return "E01".to_string();
+        }
+
+        let watch = |kind| Watchpoint {
+            kind,
+            address,
+            length: length.max(1),
+        };
+
+        match (kind, insert) {
+            ("0" | "1", true) => self.debugger.add_breakpoint(address),
+            ("0" | "1", false) => {
+                self.debugger.remove_breakpoint(address);
+            }
+            ("2", true) => self.debugger.add_watchpoint(watch(WatchKind::Write)),
+            ("3", true) => self.debugger.add_watchpoint(watch(WatchKind::Read)),
+            ("4", true) => self.debugger.add_watchpoint(watch(WatchKind::Access)),
+            ("2", false) => {
+                self.debugger.remove_watchpoint(watch(WatchKind::Write));
+            }
+            ("3", false) => {
+                self.debugger.remove_watchpoint(watch(WatchKind::Read));
+            }
+            ("4", false) => {
+                self.debugger.remove_watchpoint(watch(WatchKind::Access));
+            }
+            _ => return String::new(),
+        }
+
+        "OK".to_string()
+    }
+
+    fn send_stop(&mut self, stop: Stop) -> Result<(), String> {
+        let reply = match stop {
+            Stop::Step => "S05".to_string(),
+            Stop::Breakpoint(_) => "T05swbreak:;".to_string(),
+            Stop::Watchpoint(kind, address) => {
+                let name = match kind {
+                    WatchKind::Write => "watch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Access => "awatch",
+                };
+                format!("T05{}:{:x};", name, address)
+            }
+            Stop::Halted => "W00".to_string(),
+        };
+
+        self.send(&reply)
+    }
+
+    fn send(&mut self, data: &str) -> Result<(), String> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.write_raw(packet.as_bytes())
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => return Ok(()),
+        };
+
+        // Replies are small, just block until they are out
+        stream.set_nonblocking(false).map_err(|e| e.to_string())?;
+        let result = stream.write_all(bytes).map_err(|e| e.to_string());
+        stream.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+        result
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn register_size(index: usize) -> usize {
+    match index {
+        REG_I | REG_PC => 2,
+        _ => 1,
+    }
+}
+
+// 16 bit registers go out little endian, like on most gdb targets
+fn encode_register(chip: &Chip8, index: usize) -> String {
+    let state = chip.state();
+
+    match index {
+        0..=15 => hex(&[state.registers[index]]),
+        REG_I => hex(&state.vi.to_le_bytes()),
+        REG_PC => hex(&state.pc.to_le_bytes()),
+        REG_SP => hex(&[state.stack.len() as u8]),
+        REG_DT => hex(&[state.delay_timer]),
+        _ => hex(&[state.sound_timer]),
+    }
+}
+
+fn decode_register(chip: &mut Chip8, index: usize, bytes: &[u8]) {
+    let state = chip.state_mut();
+
+    match (index, bytes) {
+        (0..=15, [value]) => state.registers[index] = *value,
+        (REG_I, [low, high]) => state.vi = u16::from_le_bytes([*low, *high]) & ADDRESS_MASK,
+        (REG_PC, [low, high]) => state.pc = u16::from_le_bytes([*low, *high]) & ADDRESS_MASK,
+        (REG_DT, [value]) => state.delay_timer = *value,
+        (REG_ST, [value]) => state.sound_timer = *value,
+        // The stack pointer follows the stack and can't be moved on its own
+        _ => {}
+    }
+}
+
+fn read_registers(chip: &Chip8) -> String {
+    (0..REGISTER_COUNT)
+        .map(|index| encode_register(chip, index))
+        .collect()
+}
+
+fn write_registers(chip: &mut Chip8, args: &str) {
+    let bytes = match unhex(args) {
+        Some(bytes) => bytes,
+        None => return,
+    };
+
+    let mut offset = 0;
+    for index in 0..REGISTER_COUNT {
+        let size = register_size(index);
+        if let Some(value) = bytes.get(offset..offset + size) {
+            decode_register(chip, index, value);
+        }
+        offset += size;
+    }
+}
+
+fn write_register(chip: &mut Chip8, args: &str) -> Option<&'static str> {
+    let (index, value) = args.split_once('=')?;
+    let index = usize::from_str_radix(index, 16).ok()?;
+    let bytes = unhex(value)?;
+
+    if index >= REGISTER_COUNT || bytes.len() != register_size(index) {
+        return None;
+    }
+
+    decode_register(chip, index, &bytes);
+    Some("OK")
+}
+
+fn parse_range(args: &str) -> Option<(usize, usize)> {
+    let (address, length) = args.split_once(',')?;
+    let address = usize::from_str_radix(address, 16).ok()?;
+    let length = usize::from_str_radix(length, 16).ok()?;
+
+    if address >= MEMORY_SIZE {
+        return None;
+    }
+
+    Some((address, length.min(MEMORY_SIZE - address)))
+}
+
+fn read_memory(chip: &Chip8, args: &str) -> Option<String> {
+    let (address, length) = parse_range(args)?;
+    Some(hex(&chip.state().memory[address..address + length]))
+}
+
+fn write_memory(chip: &mut Chip8, args: &str) -> Option<&'static str> {
+    let (range, data) = args.split_once(':')?;
+    let (address, length) = parse_range(range)?;
+    let bytes = unhex(data)?;
+
+    let memory = &mut chip.state_mut().memory[address..address + length];
+    memory.copy_from_slice(bytes.get(..length)?);
+
+    Some("OK")
+}
+
+// `c` and `s` may carry the address to resume from
+fn set_resume_address(chip: &mut Chip8, args: &str) {
+    if let Ok(address) = u16::from_str_radix(args, 16) {
+        chip.state_mut().pc = address & ADDRESS_MASK;
+    }
+}
+
+// qXfer reads come in <offset>,<length> chunks
+fn xfer(document: &str, range: &str) -> Option<String> {
+    let (offset, length) = range.split_once(',')?;
+    let offset = usize::from_str_radix(offset, 16).ok()?;
+    let length = usize::from_str_radix(length, 16).ok()?;
+
+    let bytes = document.as_bytes();
+    if offset >= bytes.len() {
+        return Some("l".to_string());
+    }
+
+    let end = (offset + length).min(bytes.len());
+    let marker = if end == bytes.len() { "l" } else { "m" };
+
+    Some(format!(
+        "{}{}",
+        marker,
+        String::from_utf8_lossy(&bytes[offset..end])
+    ))
+}
@@ -0,0 +1,26 @@
+use std::collections::HashMap;
+
+use sdl2::keyboard::Scancode;
+
+pub fn scancode_to_u8(key: Scancode) -> Option<u8> {
+    let key_mapping: HashMap<Scancode, u8> = HashMap::from([
+        (Scancode::Num1, 0x1),
+        (Scancode::Num2, 0x2),
+        (Scancode::Num3, 0x3),
+        (Scancode::Num4, 0xC),
+        (Scancode::Q, 0x4),
+        (Scancode::W, 0x5),
+        (Scancode::E, 0x6),
+        (Scancode::R, 0xD),
+        (Scancode::A, 0x7),
+        (Scancode::S, 0x8),
+        (Scancode::D, 0x9),
+        (Scancode::F, 0xE),
+        (Scancode::Z, 0xA),
+        (Scancode::X, 0x0),
+        (Scancode::C, 0xB),
+        (Scancode::V, 0xF),
+    ]);
+
+    key_mapping.get(&key).copied()
+}
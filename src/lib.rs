@@ -0,0 +1,23 @@
+pub mod analysis;
+pub mod cartridge;
+pub mod cheat;
+pub mod core;
+pub mod coverage;
+pub mod dap;
+pub mod debugger;
+pub mod disasm;
+pub mod gdb;
+pub mod lint;
+pub mod octo;
+pub mod palette;
+pub mod patch;
+pub mod profile;
+pub mod recorder;
+pub mod rom;
+pub mod screenshot;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod source_map;
+pub mod synth;
+pub mod trace;
+pub mod wav;
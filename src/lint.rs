@@ -0,0 +1,362 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::analysis::Analysis;
+use crate::core::chip::Chip8;
+use crate::core::display::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::core::quirks::{Quirks, FLAGS};
+use crate::rom;
+
+// Every run draws the same random numbers so only the quirks differ
+const SEED: u64 = 0x8;
+const PRESETS: [&str; 3] = ["chip8", "schip", "xochip"];
+
+// How a run went compared with the run under the base quirks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Same,
+    // First frame where the machine state differs
+    Differs(u32),
+    // Frame the core panicked in
+    Crashes(u32),
+}
+
+pub struct Report {
+    pub base: Quirks,
+    pub frames: u32,
+    // Quirk to the reachable instructions it affects
+    pub uses: BTreeMap<&'static str, Vec<u16>>,
+    // Quirk to how often those instructions ran under the base quirks
+    pub executed: BTreeMap<&'static str, u64>,
+    // Sprites drawn partly off screen, where clipping decides what shows
+    pub edge_draws: Vec<u16>,
+    // Frame the run under the base quirks crashed in
+    pub crashed: Option<u32>,
+    // Flag to how the run went when only it was toggled
+    pub flags: Vec<(&'static str, Outcome)>,
+    // Preset to how the run went under it
+    pub presets: Vec<(&'static str, Outcome)>,
+}
+
+struct Run {
+    // Hash of the machine state after each frame
+    frames: Vec<u64>,
+    executed: BTreeMap<&'static str, u64>,
+    edge_draws: Vec<u16>,
+    crashed: Option<u32>,
+}
+
+// Finds the quirk-sensitive instructions a ROM has, then runs it without
+// input under the base quirks, with each flag toggled and under each preset,
+// and compares the machine state frame by frame
+pub fn lint(data: &[u8], base: Quirks, frames: u32, ipf: u32) -> Result<Report, String> {
+    rom::validate(data)?;
+
+    let analysis = Analysis::run(data);
+    let mut uses: BTreeMap<&'static str, Vec<u16>> = BTreeMap::new();
+    for address in analysis.instructions() {
+        for quirk in rom::quirks_for(analysis.fetch(address).unwrap_or(0)) {
+            uses.entry(quirk).or_default().push(address);
+        }
+    }
+
+    let reference = run(data, base, frames, ipf)?;
+
+    let mut flags = Vec::new();
+    for flag in FLAGS {
+        let mut quirks = base;
+        quirks.set_flag(flag, !base.flag(flag).unwrap())?;
+        flags.push((flag, compare(&reference, &run(data, quirks, frames, ipf)?)));
+    }
+
+    let mut presets = Vec::new();
+    for preset in PRESETS {
+        let quirks = Quirks::from_name(preset).unwrap();
+        presets.push((
+            preset,
+            compare(&reference, &run(data, quirks, frames, ipf)?),
+        ));
+    }
+
+    Ok(Report {
+        base,
+        frames,
+        uses,
+        executed: reference.executed,
+        edge_draws: reference.edge_draws,
+        crashed: reference.crashed,
+        flags,
+        presets,
+    })
+}
+
+fn run(data: &[u8], quirks: Quirks, frames: u32, ipf: u32) -> Result<Run, String> {
+    let mut chip = Chip8::with_quirks(quirks);
+    chip.set_seed(SEED);
+    chip.load_rom(data)?;
+
+    let mut result = Run {
+        frames: Vec::new(),
+        executed: BTreeMap::new(),
+        edge_draws: Vec::new(),
+        crashed: None,
+    };
+
+    for frame in 0..frames {
+        // Toggled quirks send ROMs down paths nobody tested, a panic there
+        // is a difference to report rather than the end of the report
+        let ran = panic::catch_unwind(AssertUnwindSafe(|| {
+            run_frame(&mut chip, &mut result, ipf);
+        }));
+        if ran.is_err() {
+            result.crashed = Some(frame);
+            break;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        chip.state().hash(&mut hasher);
+        chip.display().screen_memory.hash(&mut hasher);
+        result.frames.push(hasher.finish());
+    }
+
+    result.edge_draws.sort();
+    Ok(result)
+}
+
+fn run_frame(chip: &mut Chip8, result: &mut Run, ipf: u32) {
+    for _ in 0..ipf {
+        if !chip.is_running() {
+            break;
+        }
+
+        let code = chip.peek_opcode();
+        for quirk in rom::quirks_for(code) {
+            *result.executed.entry(quirk).or_default() += 1;
+        }
+
+        if code >> 12 == 0xD && crosses_edge(chip, code) {
+            let pc = chip.state().pc;
+            if !result.edge_draws.contains(&pc) {
+                result.edge_draws.push(pc);
+            }
+        }
+
+        if chip.step_instruction() {
+            break;
+        }
+    }
+    chip.end_frame();
+}
+
+fn crosses_edge(chip: &Chip8, code: u16) -> bool {
+    let registers = &chip.state().registers;
+    let x = registers[(code as usize >> 8) & 0xF] as usize % SCREEN_WIDTH as usize;
+    let y = registers[(code as usize >> 4) & 0xF] as usize % SCREEN_HEIGHT;
+    let rows = (code & 0xF) as usize;
+
+    x + 8 > SCREEN_WIDTH as usize || y + rows > SCREEN_HEIGHT
+}
+
+fn compare(reference: &Run, other: &Run) -> Outcome {
+    let differs = reference
+        .frames
+        .iter()
+        .zip(&other.frames)
+        .position(|(a, b)| a != b);
+    if let Some(frame) = differs {
+        return Outcome::Differs(frame as u32);
+    }
+
+    // Both ran the same up to where the first one crashed
+    match (reference.crashed, other.crashed) {
+        (Some(a), Some(b)) if a == b => Outcome::Same,
+        (_, Some(frame)) => Outcome::Crashes(frame),
+        (Some(frame), None) => Outcome::Differs(frame),
+        (None, None) => Outcome::Same,
+    }
+}
+
+impl Report {
+    // The flags the ROM behaved differently without
+    pub fn sensitive_flags(&self) -> Vec<&'static str> {
+        let mut flags: Vec<&'static str> = self
+            .flags
+            .iter()
+            .filter(|(_, outcome)| *outcome != Outcome::Same)
+            .map(|(flag, _)| *flag)
+            .collect();
+
+        // Clipping only shows on screen, toggling it may not change a frame
+        // before the game is played, so drawing across an edge is enough
+        if !self.edge_draws.is_empty() && !flags.contains(&"clipping") {
+            flags.push("clipping");
+        }
+
+        flags
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "quirk-sensitive instructions reachable from 200:").unwrap();
+        for flag in FLAGS {
+            let addresses = match self.uses.get(flag) {
+                Some(addresses) => addresses,
+                None => continue,
+            };
+
+            let listed: Vec<String> = addresses
+                .iter()
+                .take(8)
+                .map(|a| format!("{:03X}", a))
+                .collect();
+            let more = if addresses.len() > 8 { ", ..." } else { "" };
+            writeln!(
+                out,
+                "  {:<16} {:<10} at {}{} (ran {} times)",
+                flag,
+                instructions_for(flag),
+                listed.join(", "),
+                more,
+                self.executed.get(flag).unwrap_or(&0)
+            )
+            .unwrap();
+        }
+
+        if !self.edge_draws.is_empty() {
+            let listed: Vec<String> = self
+                .edge_draws
+                .iter()
+                .map(|a| format!("{:03X}", a))
+                .collect();
+            writeln!(
+                out,
+                "\nsprites drawn across the screen edge at {}",
+                listed.join(", ")
+            )
+            .unwrap();
+        }
+
+        if let Some(frame) = self.crashed {
+            writeln!(
+                out,
+                "\nthe run under the base quirks crashes at frame {}",
+                frame
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\ntoggling each flag for {} frames:", self.frames).unwrap();
+        for (flag, outcome) in &self.flags {
+            writeln!(out, "  {:<16} {}", flag, describe(*outcome)).unwrap();
+        }
+
+        writeln!(out, "\nunder each preset:").unwrap();
+        for (preset, outcome) in &self.presets {
+            writeln!(out, "  {:<16} {}", preset, describe(*outcome)).unwrap();
+        }
+
+        let settings: Vec<String> = self
+            .sensitive_flags()
+            .iter()
+            .map(|flag| {
+                let on = self.base.flag(flag).unwrap();
+                format!("{}={}", flag, if on { "on" } else { "off" })
+            })
+            .collect();
+
+        if settings.is_empty() {
+            writeln!(out, "\nno flag changed the run, any preset will do").unwrap();
+        } else {
+            writeln!(out, "\nship with {}", settings.join(" ")).unwrap();
+        }
+
+        out
+    }
+}
+
+fn instructions_for(flag: &str) -> &'static str {
+    match flag {
+        "increment_index" => "FX55/FX65",
+        "shifting" => "8XY6/8XYE",
+        "jumping" => "BNNN",
+        "vf_reset" => "8XY1-8XY3",
+        _ => "DXYN",
+    }
+}
+
+fn describe(outcome: Outcome) -> String {
+    match outcome {
+        Outcome::Same => "no effect".to_string(),
+        Outcome::Differs(frame) => format!("changes the run from frame {}", frame),
+        Outcome::Crashes(frame) => format!("crashes at frame {}", frame),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_flags_that_change_the_run() {
+        // 200: LD V0, 3  202: LD V1, 1  204: SHR V0, V1  206: JP 206
+        let rom = [0x60, 0x03, 0x61, 0x01, 0x80, 0x16, 0x12, 0x06];
+        let report = lint(&rom, Quirks::for_chip8(), 2, 10).unwrap();
+
+        assert_eq!(report.uses["shifting"], [0x204]);
+        assert_eq!(report.executed["shifting"], 1);
+        assert_eq!(report.sensitive_flags(), ["shifting"]);
+        assert!(report.to_text().contains("ship with shifting=on"));
+    }
+
+    #[test]
+    fn reports_sprites_drawn_across_edges() {
+        // 200: LD V0, 60  202: DRW V0, V0, 1  204: JP 204
+        let rom = [0x60, 0x3C, 0xD0, 0x01, 0x12, 0x04];
+        let report = lint(&rom, Quirks::for_chip8(), 1, 10).unwrap();
+
+        assert_eq!(report.edge_draws, [0x202]);
+        assert!(report.sensitive_flags().contains(&"clipping"));
+    }
+
+    #[test]
+    fn reports_no_effect_for_plain_roms() {
+        // 200: LD V0, 1  202: JP 202
+        let report = lint(&[0x60, 0x01, 0x12, 0x02], Quirks::for_chip8(), 2, 10).unwrap();
+
+        assert!(report.sensitive_flags().is_empty());
+        assert!(report
+            .presets
+            .iter()
+            .all(|(_, outcome)| *outcome == Outcome::Same));
+    }
+
+    fn crashed_after(frames: &[u64], crashed: Option<u32>) -> Run {
+        Run {
+            frames: frames.to_vec(),
+            executed: BTreeMap::new(),
+            edge_draws: Vec::new(),
+            crashed,
+        }
+    }
+
+    #[test]
+    fn reports_crashes_as_differences() {
+        let reference = crashed_after(&[1, 2, 3], None);
+
+        let crashes = crashed_after(&[1, 2], Some(2));
+        assert_eq!(compare(&reference, &crashes), Outcome::Crashes(2));
+        assert_eq!(describe(Outcome::Crashes(2)), "crashes at frame 2");
+
+        // Differing before the crash is reported as the difference
+        let differs = crashed_after(&[1, 5], Some(2));
+        assert_eq!(compare(&reference, &differs), Outcome::Differs(1));
+
+        let base_crashes = crashed_after(&[1], Some(1));
+        assert_eq!(compare(&base_crashes, &reference), Outcome::Differs(1));
+        assert_eq!(compare(&base_crashes, &base_crashes), Outcome::Same);
+    }
+}
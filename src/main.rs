@@ -1,32 +1,379 @@
 extern crate sdl2;
 
 use std::env;
+use std::fs;
 use std::time::{Duration, SystemTime};
 
-use crate::core::chip::Chip8;
+use chip_8::analysis::Analysis;
+use chip_8::core::chip::Chip8;
+#[cfg(feature = "jit")]
+use chip_8::core::jit;
+use chip_8::core::quirks::Quirks;
+use chip_8::coverage::Coverage;
+use chip_8::dap::DapServer;
+use chip_8::disasm;
+use chip_8::lint;
+use chip_8::patch;
+use chip_8::profile::Profiler;
+use chip_8::recorder::Recorder;
+use chip_8::rom::{self, Platform};
+use chip_8::screenshot;
+use chip_8::wav::WavWriter;
+
+use crate::cli::{Options, USAGE};
+use crate::console::CheatConsole;
+use crate::driver::Driver;
+use crate::frontend::Frontend;
+use crate::terminal::Terminal;
+use crate::window::Window;
 
 mod audio;
-mod core;
+mod cli;
+mod console;
+mod driver;
+mod frontend;
+mod input;
 mod screen;
+mod terminal;
+mod window;
 
 const FPS: u128 = 60;
 
 fn main() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect(); // check for argparser
-    let rom_path = &args[1];
-    let ipf: u32 = args[2].parse::<u32>().unwrap();
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() == 2 && args[1] == "dap" {
+        return run_dap();
+    }
+
+    if args.len() > 2 && args[1] == "analyze" {
+        return run_analyze(&args[2..]);
+    }
+
+    if args.len() > 2 && args[1] == "lint" {
+        return run_lint(&args[2..]);
+    }
+
+    if args.len() > 2 && args[1] == "jit-check" {
+        #[cfg(feature = "jit")]
+        return run_jit_check(&args[2..]);
+        #[cfg(not(feature = "jit"))]
+        return Err(cli::NO_JIT.to_string());
+    }
+
+    let options = Options::parse(&args[1..])?;
+    run(&options, None)
+}
+
+// The editor runs `chip_8 dap` and sends the ROM and options over stdin
+fn run_dap() -> Result<(), String> {
+    let mut dap = DapServer::stdio();
+    let launch = dap.wait_for_launch()?;
+
+    let mut args = vec![launch.program, launch.ipf.to_string()];
+    args.extend(launch.args);
+    let options = Options::parse(&args)?;
+
+    // Stdout carries the protocol, nothing else may write to it. The window's
+    // own messages go to stderr.
+    let uses_stdout =
+        options.trace.as_deref() == Some("-") || options.profile.as_deref() == Some("-");
+    let uses_stdin = options.cheats;
+    if options.tui || options.gdb.is_some() || uses_stdout || uses_stdin {
+        return Err("the debug adapter needs stdin and stdout to itself".to_string());
+    }
+
+    run(&options, Some(dap))
+}
+
+// Prints a labeled listing of the code reachable from 0x200, or executed in
+// the run that wrote --coverage <file>, and with --dot <file> writes the
+// control-flow graph
+fn run_analyze(args: &[String]) -> Result<(), String> {
+    let mut dot = None;
+    let mut coverage = None;
+    for pair in args[1..].chunks(2) {
+        match pair {
+            [flag, path] if flag == "--dot" => dot = Some(path),
+            [flag, path] if flag == "--coverage" => coverage = Some(Coverage::load(path)?),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let rom = rom::read(&args[0], None)?;
+    for warning in rom::validate(&rom)? {
+        eprintln!("warning: {}", warning);
+    }
+
+    let analysis = match &coverage {
+        Some(coverage) => Analysis::with_coverage(&rom, coverage),
+        None => Analysis::run(&rom),
+    };
+    print!("{}", analysis.listing());
+
+    if let Some(path) = dot {
+        fs::write(path, analysis.to_dot()).map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    Ok(())
+}
+
+// Reports the quirk-sensitive instructions and which flags change the run,
+// under --quirks or the preset the ROM looks like it needs
+fn run_lint(args: &[String]) -> Result<(), String> {
+    let (rom, quirks, frames, ipf) = parse_headless(args)?;
+    print!("{}", lint::lint(&rom, quirks, frames, ipf)?.to_text());
+
+    Ok(())
+}
+
+// Runs the ROM with and without the JIT and fails at the first frame where
+// the machines differ
+#[cfg(feature = "jit")]
+fn run_jit_check(args: &[String]) -> Result<(), String> {
+    let (rom, quirks, frames, ipf) = parse_headless(args)?;
+    jit::compare(&rom, quirks, frames, ipf)?;
+    println!("the JIT matched the interpreter for {} frames", frames);
+
+    Ok(())
+}
+
+// <path> [--frames <n>] [--ipf <n>] [--quirks <preset>] for runs without
+// input, returning the ROM, quirks, frames and ipf
+fn parse_headless(args: &[String]) -> Result<(Vec<u8>, Quirks, u32, u32), String> {
+    let mut frames = 600;
+    let mut ipf = 10;
+    let mut quirks = None;
+
+    let mut rest = args[1..].iter();
+    while let Some(flag) = rest.next() {
+        let value = rest.next().ok_or(USAGE)?;
+        match flag.as_str() {
+            "--frames" => frames = value.parse().map_err(|_| USAGE)?,
+            "--ipf" => ipf = value.parse().map_err(|_| USAGE)?,
+            "--quirks" => {
+                quirks = Some(
+                    Quirks::from_name(value).ok_or(format!("unknown quirks preset '{}'", value))?,
+                )
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    // Detection analyses the ROM, which must fit in memory
+    let rom = rom::read(&args[0], None)?;
+    rom::validate(&rom)?;
+    let quirks = quirks.unwrap_or_else(|| rom::detect(&rom).platform.quirks());
+
+    Ok((rom, quirks, frames, ipf))
+}
+
+// Warns about suspicious ROMs and picks or suggests the quirks preset
+fn check_rom(chip: &mut Chip8, options: &Options, rom: &[u8]) -> Result<(), String> {
+    for warning in rom::validate(rom)? {
+        eprintln!("warning: {}", warning);
+    }
+
+    let detection = rom::detect(rom);
+    let platform = detection.platform.name();
+    let evidence: Vec<String> = detection
+        .evidence
+        .iter()
+        .take(3)
+        .map(|(address, code)| format!("{} at {:03X}", disasm::disassemble(*code), address))
+        .collect();
+
+    match options.preset.as_deref() {
+        Some("auto") => {
+            *chip.quirks() = detection.platform.quirks();
+            eprintln!("using {} quirks", platform);
+        }
+        None if detection.platform != Platform::Chip8 => {
+            eprintln!(
+                "this looks like a {} ROM ({}), try --quirks {}",
+                platform,
+                evidence.join(", "),
+                platform
+            );
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn run(options: &Options, dap: Option<DapServer>) -> Result<(), String> {
+    let mut chip = Chip8::with_quirks(options.quirks);
+    let patch = options
+        .patch
+        .clone()
+        .or_else(|| patch::find_for(&options.rom_path));
+    let rom = chip.open_rom(
+        &options.rom_path,
+        options.entry.as_deref(),
+        patch.as_deref(),
+    )?;
+    check_rom(&mut chip, options, &rom)?;
+    chip.set_tracer(options.tracer()?);
+
+    #[cfg(feature = "jit")]
+    chip.set_jit(options.jit)?;
+
+    if options.profile.is_some() {
+        chip.set_profiler(Some(Profiler::new()));
+    }
+
+    if options.coverage.is_some() || options.coverage_image.is_some() {
+        chip.set_coverage(Some(Coverage::new()));
+    }
+
+    let mut driver = Driver::new(options, &mut chip, dap)?;
+    let mut cheats = CheatConsole::new(&options.rom_path, options.cheats)?;
+
+    let result = match options.headless {
+        Some(frames) => run_headless(&mut chip, options, &mut driver, &mut cheats, frames),
+        None if options.tui => {
+            let mut terminal = Terminal::new(options.palette.clone())?;
+            run_frontend(&mut chip, options, &mut terminal, &mut driver, &mut cheats)
+        }
+        None => {
+            let mut window = Window::new(options.palette.clone(), options.scale.unwrap_or(1));
+            run_frontend(&mut chip, options, &mut window, &mut driver, &mut cheats)
+        }
+    };
+
+    // Written after the frontend is gone so the terminal is back to normal
+    if let (Some(path), Some(profiler)) = (&options.profile, chip.profiler()) {
+        profiler.save(path, options.profile_top)?;
+    }
+
+    if let Some(coverage) = chip.coverage() {
+        if let Some(path) = &options.coverage {
+            coverage.save_text(path)?;
+        }
+
+        if let Some(path) = &options.coverage_image {
+            coverage.save_image(path)?;
+        }
+    }
+
+    result
+}
+
+fn recorder(options: &Options) -> Result<Option<Recorder>, String> {
+    match &options.record {
+        Some(path) => Ok(Some(Recorder::new(
+            path,
+            options.palette.clone(),
+            options.scale.unwrap_or(1),
+        )?)),
+        None => Ok(None),
+    }
+}
+
+fn wav_writer(options: &Options) -> Result<Option<WavWriter>, String> {
+    match &options.wav {
+        Some(path) => Ok(Some(WavWriter::create(path)?)),
+        None => Ok(None),
+    }
+}
+
+fn run_headless(
+    chip: &mut Chip8,
+    options: &Options,
+    driver: &mut Driver,
+    cheats: &mut CheatConsole,
+    frames: u32,
+) -> Result<(), String> {
+    let mut recorder = recorder(options)?;
+    let mut wav = wav_writer(options)?;
+    let mut synth = options.synth();
+
+    // Counts the frames the chip ended, since a paused debugger runs none
+    let last = chip.frames() + frames as u64;
+    while chip.is_running() && chip.frames() < last {
+        let before = chip.frames();
+        driver.run_frame(chip)?;
+        cheats.update(chip);
 
-    let mut chip = Chip8::new();
-    chip.read_rom(rom_path);
+        if chip.frames() == before {
+            continue;
+        }
+
+        if let Some(recorder) = &mut recorder {
+            recorder.capture(chip.display());
+        }
+
+        if let Some(wav) = &mut wav {
+            wav.write(&synth.frame(chip.is_sound_playing()))?;
+        }
+    }
+
+    driver.finish()?;
+
+    if let Some(recorder) = &recorder {
+        recorder.save()?;
+    }
+
+    if let Some(wav) = wav {
+        wav.finish()?;
+    }
+
+    if let Some(path) = &options.screenshot {
+        match options.scale {
+            Some(scale) => screenshot::save_scaled(path, chip.display(), &options.palette, scale)?,
+            None => screenshot::save_native(path, chip.display())?,
+        }
+    }
+
+    Ok(())
+}
+
+fn run_frontend(
+    chip: &mut Chip8,
+    options: &Options,
+    frontend: &mut dyn Frontend,
+    driver: &mut Driver,
+    cheats: &mut CheatConsole,
+) -> Result<(), String> {
+    let mut recorder = recorder(options)?;
+    let mut wav = wav_writer(options)?;
+    let mut synth = options.synth();
 
     while chip.is_running() {
         let start = SystemTime::now();
 
-        chip.step(ipf);
+        frontend.handle_events(chip);
+
+        driver.run_frame(chip)?;
+        cheats.update(chip);
+
+        let samples = synth.frame(chip.is_sound_playing());
+        frontend.update(chip, &samples);
+
+        if let Some(recorder) = &mut recorder {
+            recorder.capture(chip.display());
+        }
+
+        if let Some(wav) = &mut wav {
+            wav.write(&samples)?;
+        }
 
         let sleep_for = start.elapsed().unwrap().as_nanos() + 1_000_000_000 / FPS;
         ::std::thread::sleep(Duration::new(0, sleep_for as u32));
     }
 
+    frontend.finish();
+
+    driver.finish()?;
+
+    if let Some(recorder) = &recorder {
+        recorder.save()?;
+    }
+
+    if let Some(wav) = wav {
+        wav.finish()?;
+    }
+
     Ok(())
 }
@@ -0,0 +1,1230 @@
+use std::collections::HashMap;
+use std::f64::consts::{E, PI};
+
+use crate::core::chip::MEM_OFFSET;
+
+const MEMORY_SIZE: usize = 4096;
+// Stops macros that expand into themselves
+const MAX_EXPANSIONS: usize = 10_000;
+
+// Assembles Octo source, which is what cartridges carry, into a ROM loaded
+// at 0x200. Covers the CHIP-8, SCHIP and XO-CHIP statements, if/else and
+// loop/while blocks, labels, aliases, constants, macros and :calc. Like Octo,
+// 0x200 holds a jump to `main`.
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let mut assembler = Assembler::new(source);
+    assembler
+        .run()
+        .map_err(|e| format!("line {}: {}", assembler.line, e))?;
+    assembler.finish()
+}
+
+#[derive(Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+#[derive(Clone)]
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    // The low 12 bits of an instruction
+    Address,
+    // Two bytes, for :pointer and i := long
+    Wide,
+    // The two loads :unpack emits, with the high nibble given
+    Unpack,
+    UnpackLong,
+}
+
+// A name used before it was defined, filled in at the end
+struct Fixup {
+    name: String,
+    line: usize,
+    address: usize,
+    kind: Kind,
+}
+
+enum Block {
+    // Address of the jump past the block
+    If(usize),
+    Else(usize),
+    // Start of the loop and the jumps out of it
+    Loop(usize, Vec<usize>),
+}
+
+// The instructions that test a condition, ending with one that skips the
+// next instruction when it is false
+struct Condition {
+    setup: Vec<u16>,
+    skip: u16,
+}
+
+struct Assembler {
+    // Tokens left with the next one last, so macros can push their body
+    tokens: Vec<Token>,
+    line: usize,
+    memory: Vec<u8>,
+    here: usize,
+    end: usize,
+    // Labels, constants and :calc results
+    values: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    expansions: usize,
+}
+
+impl Assembler {
+    fn new(source: &str) -> Assembler {
+        let mut tokens = Vec::new();
+        for (number, line) in source.lines().enumerate() {
+            let code = line.split('#').next().unwrap_or("");
+            for text in code.split_whitespace() {
+                tokens.push(Token {
+                    text: text.to_string(),
+                    line: number + 1,
+                });
+            }
+        }
+        tokens.reverse();
+
+        Assembler {
+            tokens,
+            line: 0,
+            memory: vec![0; MEMORY_SIZE],
+            here: MEM_OFFSET as usize,
+            end: MEM_OFFSET as usize,
+            values: HashMap::new(),
+            aliases: HashMap::from([("unpack-hi".to_string(), 0), ("unpack-lo".to_string(), 1)]),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            expansions: 0,
+        }
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        self.refer("main", 0x1000, Kind::Address)?;
+
+        while let Some(token) = self.next() {
+            self.statement(&token)?;
+        }
+
+        match self.blocks.last() {
+            Some(Block::Loop(..)) => Err("loop without again".to_string()),
+            Some(_) => Err("begin without end".to_string()),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, String> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let value = match self.values.get(&fixup.name) {
+                Some(value) => integer(*value),
+                None if fixup.name == "main" => {
+                    return Err("the program has no main label".to_string())
+                }
+                None => {
+                    return Err(format!(
+                        "line {}: undefined name {}",
+                        fixup.line, fixup.name
+                    ))
+                }
+            };
+
+            self.patch(fixup.address, value, fixup.kind)
+                .map_err(|e| format!("line {}: {}", fixup.line, e))?;
+        }
+
+        Ok(self.memory[MEM_OFFSET as usize..self.end].to_vec())
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.pop()?;
+        self.line = token.line;
+        Some(token.text)
+    }
+
+    fn expect(&mut self) -> Result<String, String> {
+        self.next()
+            .ok_or_else(|| "unexpected end of program".to_string())
+    }
+
+    fn expect_token(&mut self, expected: &str) -> Result<(), String> {
+        match self.expect()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("expected {}, found {}", expected, token)),
+        }
+    }
+
+    fn statement(&mut self, token: &str) -> Result<(), String> {
+        match token {
+            ":" => {
+                let name = self.name()?;
+                self.define(name, self.here as f64)?;
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.value()?;
+                self.define(name, value)?;
+            }
+            ":calc" => {
+                let name = self.name()?;
+                self.expect_token("{")?;
+                let value = self.calc()?;
+                self.define(name, value)?;
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":next" => {
+                let name = self.name()?;
+                self.define(name, (self.here + 1) as f64)?;
+            }
+            ":unpack" => self.unpack()?,
+            ":org" => {
+                let address = integer(self.value()?);
+                if !(MEM_OFFSET as i64..MEMORY_SIZE as i64).contains(&address) {
+                    return Err(format!("can't place code at {:#X}", address));
+                }
+                self.here = address as usize;
+            }
+            ":byte" => {
+                let value = self.byte()?;
+                self.emit_byte(value)?;
+            }
+            ":pointer" => {
+                let target = self.expect()?;
+                self.refer(&target, 0, Kind::Wide)?;
+            }
+            ":call" => {
+                let target = self.expect()?;
+                self.refer(&target, 0x2000, Kind::Address)?;
+            }
+            ":macro" => self.define_macro()?,
+            ":breakpoint" | ":proto" => {
+                self.expect()?;
+            }
+            ":monitor" => {
+                self.expect()?;
+                self.expect()?;
+            }
+            "return" | ";" => self.emit(0x00EE)?,
+            "clear" => self.emit(0x00E0)?,
+            "exit" => self.emit(0x00FD)?,
+            "lores" => self.emit(0x00FE)?,
+            "hires" => self.emit(0x00FF)?,
+            "scroll-right" => self.emit(0x00FB)?,
+            "scroll-left" => self.emit(0x00FC)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n)?;
+            }
+            "audio" => self.emit(0xF002)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | n << 8)?;
+            }
+            "bcd" => self.register_op(0xF033)?,
+            "saveflags" => self.register_op(0xF075)?,
+            "loadflags" => self.register_op(0xF085)?,
+            "save" | "load" => {
+                let x = self.register()? as u16;
+                if self.tokens.last().is_some_and(|next| next.text == "-") {
+                    self.next();
+                    let y = self.register()? as u16;
+                    let op = if token == "save" { 0x5002 } else { 0x5003 };
+                    self.emit(op | x << 8 | y << 4)?;
+                } else {
+                    let op = if token == "save" { 0xF055 } else { 0xF065 };
+                    self.emit(op | x << 8)?;
+                }
+            }
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let n = self.nibble()?;
+                self.emit(0xD000 | x << 8 | y << 4 | n)?;
+            }
+            "jump" | "jump0" | "native" => {
+                let op = match token {
+                    "jump" => 0x1000,
+                    "jump0" => 0xB000,
+                    _ => 0x0000,
+                };
+                let target = self.expect()?;
+                self.refer(&target, op, Kind::Address)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect_token(":=")?;
+                let op = match token {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.register_op(op)?;
+            }
+            "i" => self.index()?,
+            "if" => {
+                let condition = self.condition()?;
+                match self.expect()?.as_str() {
+                    "then" => self.test(&condition, false)?,
+                    "begin" => {
+                        self.test(&condition, true)?;
+                        self.blocks.push(Block::If(self.here));
+                        self.emit(0x1000)?;
+                    }
+                    other => return Err(format!("expected then or begin, found {}", other)),
+                }
+            }
+            "else" => {
+                let Some(Block::If(start)) = self.blocks.pop() else {
+                    return Err("else without begin".to_string());
+                };
+                let jump = self.here;
+                self.emit(0x1000)?;
+                self.patch(start, self.here as i64, Kind::Address)?;
+                self.blocks.push(Block::Else(jump));
+            }
+            "end" => match self.blocks.pop() {
+                Some(Block::If(jump) | Block::Else(jump)) => {
+                    self.patch(jump, self.here as i64, Kind::Address)?
+                }
+                _ => return Err("end without begin".to_string()),
+            },
+            "loop" => self.blocks.push(Block::Loop(self.here, Vec::new())),
+            "while" => {
+                let condition = self.condition()?;
+                self.test(&condition, true)?;
+
+                let jump = self.here;
+                let Some(Block::Loop(_, breaks)) = self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|block| matches!(block, Block::Loop(..)))
+                else {
+                    return Err("while outside a loop".to_string());
+                };
+                breaks.push(jump);
+                self.emit(0x1000)?;
+            }
+            "again" => {
+                let Some(Block::Loop(start, breaks)) = self.blocks.pop() else {
+                    return Err("again without loop".to_string());
+                };
+                self.emit(0x1000 | start as u16)?;
+                for jump in breaks {
+                    self.patch(jump, self.here as i64, Kind::Address)?;
+                }
+            }
+            _ if self.register_of(token).is_some() => {
+                let x = self.register_of(token).unwrap() as u16;
+                self.assignment(x)?;
+            }
+            _ if self.macros.contains_key(token) => self.expand(token)?,
+            _ if token.starts_with(':') => return Err(format!("unsupported directive {}", token)),
+            _ => match literal(token) {
+                // Bare numbers are data
+                Some(value) => {
+                    let value = fit_byte(integer(value))?;
+                    self.emit_byte(value)?;
+                }
+                // and bare names call the subroutine
+                None => self.refer(token, 0x2000, Kind::Address)?,
+            },
+        }
+
+        Ok(())
+    }
+
+    // vx := ..., vx += ... and the other register operators
+    fn assignment(&mut self, x: u16) -> Result<(), String> {
+        let op = self.expect()?;
+        let rhs = self.expect()?;
+
+        let code = match (op.as_str(), self.register_of(&rhs).map(u16::from)) {
+            (":=", Some(y)) => 0x8000 | x << 8 | y << 4,
+            ("|=", Some(y)) => 0x8001 | x << 8 | y << 4,
+            ("&=", Some(y)) => 0x8002 | x << 8 | y << 4,
+            ("^=", Some(y)) => 0x8003 | x << 8 | y << 4,
+            ("+=", Some(y)) => 0x8004 | x << 8 | y << 4,
+            ("-=", Some(y)) => 0x8005 | x << 8 | y << 4,
+            (">>=", Some(y)) => 0x8006 | x << 8 | y << 4,
+            ("=-", Some(y)) => 0x8007 | x << 8 | y << 4,
+            ("<<=", Some(y)) => 0x800E | x << 8 | y << 4,
+            (":=", None) => match rhs.as_str() {
+                "random" => 0xC000 | x << 8 | self.byte()? as u16,
+                "delay" => 0xF007 | x << 8,
+                "key" => 0xF00A | x << 8,
+                _ => 0x6000 | x << 8 | self.byte_of(&rhs)? as u16,
+            },
+            ("+=", None) => 0x7000 | x << 8 | self.byte_of(&rhs)? as u16,
+            ("-=", None) => 0x7000 | x << 8 | self.byte_of(&rhs)?.wrapping_neg() as u16,
+            _ => return Err(format!("can't assemble v{:x} {} {}", x, op, rhs)),
+        };
+
+        self.emit(code)
+    }
+
+    fn index(&mut self) -> Result<(), String> {
+        match self.expect()?.as_str() {
+            ":=" => {
+                let target = self.expect()?;
+                match target.as_str() {
+                    "hex" => self.register_op(0xF029),
+                    "bighex" => self.register_op(0xF030),
+                    "long" => {
+                        self.emit(0xF000)?;
+                        let target = self.expect()?;
+                        self.refer(&target, 0, Kind::Wide)
+                    }
+                    _ => self.refer(&target, 0xA000, Kind::Address),
+                }
+            }
+            "+=" => self.register_op(0xF01E),
+            other => Err(format!("can't assemble i {}", other)),
+        }
+    }
+
+    // :unpack <nibble> <address> loads 0xN000 | address into unpack-hi and unpack-lo
+    fn unpack(&mut self) -> Result<(), String> {
+        let high = self.expect()?;
+        let kind = if high == "long" {
+            Kind::UnpackLong
+        } else {
+            Kind::Unpack
+        };
+        let nibble = match kind {
+            Kind::UnpackLong => 0,
+            _ => self.nibble_of(&high)?,
+        };
+
+        let hi = self.aliases["unpack-hi"] as u16;
+        let lo = self.aliases["unpack-lo"] as u16;
+        let start = self.here;
+        self.emit(0x6000 | hi << 8 | nibble << 4)?;
+        self.emit(0x6000 | lo << 8)?;
+
+        let target = self.expect()?;
+        if self.resolves(&target) {
+            let value = integer(self.value_of(&target)?);
+            self.patch(start, value, kind)
+        } else {
+            self.add_fixup(target, start, kind)
+        }
+    }
+
+    // Emits `op`, or two bytes for wide references, with the target's address
+    fn refer(&mut self, target: &str, op: u16, kind: Kind) -> Result<(), String> {
+        let start = self.here;
+        match kind {
+            Kind::Wide => {
+                self.emit(0)?;
+            }
+            _ => self.emit(op)?,
+        }
+
+        if self.resolves(target) {
+            let value = integer(self.value_of(target)?);
+            self.patch(start, value, kind)
+        } else {
+            self.add_fixup(target.to_string(), start, kind)
+        }
+    }
+
+    fn add_fixup(&mut self, name: String, address: usize, kind: Kind) -> Result<(), String> {
+        if self.register_of(&name).is_some() || literal(&name).is_some() {
+            return Err(format!("expected an address, found {}", name));
+        }
+
+        self.fixups.push(Fixup {
+            name,
+            line: self.line,
+            address,
+            kind,
+        });
+        Ok(())
+    }
+
+    fn patch(&mut self, address: usize, value: i64, kind: Kind) -> Result<(), String> {
+        let limit = match kind {
+            Kind::Wide | Kind::UnpackLong => 0xFFFF,
+            _ => 0xFFF,
+        };
+        if !(0..=limit).contains(&value) {
+            return Err(format!("address {:#X} is out of range", value));
+        }
+
+        let memory = &mut self.memory;
+        match kind {
+            Kind::Address => {
+                memory[address] = memory[address] & 0xF0 | (value >> 8) as u8;
+                memory[address + 1] = value as u8;
+            }
+            Kind::Wide => {
+                memory[address] = (value >> 8) as u8;
+                memory[address + 1] = value as u8;
+            }
+            Kind::Unpack => {
+                memory[address + 1] = memory[address + 1] & 0xF0 | (value >> 8) as u8;
+                memory[address + 3] = value as u8;
+            }
+            Kind::UnpackLong => {
+                memory[address + 1] = (value >> 8) as u8;
+                memory[address + 3] = value as u8;
+            }
+        }
+
+        Ok(())
+    }
+
+    // The skip `then` needs, or for begin and while the one that skips the
+    // jump past the block when the condition holds
+    fn test(&mut self, condition: &Condition, inverted: bool) -> Result<(), String> {
+        for op in &condition.setup {
+            self.emit(*op)?;
+        }
+
+        let skip = condition.skip;
+        let skip = match (inverted, skip >> 12) {
+            (false, _) => skip,
+            (true, 0x3) => skip + 0x1000,
+            (true, 0x4) => skip - 0x1000,
+            (true, 0x5) => skip & 0x0FFF | 0x9000,
+            (true, 0x9) => skip & 0x0FFF | 0x5000,
+            (true, _) if skip & 0xFF == 0x9E => skip & 0xFF00 | 0xA1,
+            (true, _) => skip & 0xFF00 | 0x9E,
+        };
+        self.emit(skip)
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let x = self.register()? as u16;
+        let op = self.expect()?;
+
+        let skip = |skip| Condition {
+            setup: Vec::new(),
+            skip,
+        };
+        match op.as_str() {
+            "key" => return Ok(skip(0xE0A1 | x << 8)),
+            "-key" => return Ok(skip(0xE09E | x << 8)),
+            _ => {}
+        }
+
+        let rhs = self.expect()?;
+        let y = self.register_of(&rhs).map(u16::from);
+        let nn = match y {
+            Some(_) => 0,
+            None => self.byte_of(&rhs)? as u16,
+        };
+
+        // The ordering tests subtract in VF and look at the borrow
+        let load_vf = match y {
+            Some(y) => 0x8F00 | y << 4,
+            None => 0x6F00 | nn,
+        };
+        let compare = |subtract: u16, skip: u16| Condition {
+            setup: vec![load_vf, subtract | x << 4],
+            skip,
+        };
+
+        Ok(match (op.as_str(), y) {
+            ("==", Some(y)) => skip(0x9000 | x << 8 | y << 4),
+            ("!=", Some(y)) => skip(0x5000 | x << 8 | y << 4),
+            ("==", None) => skip(0x4000 | x << 8 | nn),
+            ("!=", None) => skip(0x3000 | x << 8 | nn),
+            // VF = vx - rhs without a borrow
+            ("<", _) => compare(0x8F07, 0x3F01),
+            (">=", _) => compare(0x8F07, 0x3F00),
+            // VF = rhs - vx without a borrow
+            (">", _) => compare(0x8F05, 0x3F01),
+            ("<=", _) => compare(0x8F05, 0x3F00),
+            _ => return Err(format!("unknown comparison {}", op)),
+        })
+    }
+
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.name()?;
+        let mut args = Vec::new();
+        loop {
+            match self.expect()? {
+                token if token == "{" => break,
+                token => args.push(token),
+            }
+        }
+        let body = self.braced()?;
+
+        self.macros.insert(name, Macro { args, body });
+        Ok(())
+    }
+
+    fn expand(&mut self, name: &str) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(format!("{} keeps expanding into itself", name));
+        }
+
+        let definition = self.macros[name].clone();
+        let mut values = HashMap::new();
+        for arg in &definition.args {
+            values.insert(arg.clone(), self.expect()?);
+        }
+
+        for token in definition.body.iter().rev() {
+            let text = values.get(&token.text).unwrap_or(&token.text);
+            self.tokens.push(Token {
+                text: text.clone(),
+                line: self.line,
+            });
+        }
+
+        Ok(())
+    }
+
+    // The tokens up to the } closing one that was just read
+    fn braced(&mut self) -> Result<Vec<Token>, String> {
+        let mut depth = 1;
+        let mut tokens = Vec::new();
+
+        loop {
+            let token = self.tokens.pop().ok_or("missing }")?;
+            depth += match token.text.as_str() {
+                "{" => 1,
+                "}" => -1,
+                _ => 0,
+            };
+            if depth == 0 {
+                return Ok(tokens);
+            }
+            tokens.push(token);
+        }
+    }
+
+    fn calc(&mut self) -> Result<f64, String> {
+        let tokens: Vec<String> = self.braced()?.into_iter().map(|t| t.text).collect();
+        let mut position = 0;
+        let value = self.expression(&tokens, &mut position)?;
+
+        match tokens.get(position) {
+            Some(token) => Err(format!("unexpected {} in expression", token)),
+            None => Ok(value),
+        }
+    }
+
+    // Like Octo, operators have no precedence and group from the right,
+    // so 2 * 3 + 1 is 8
+    fn expression(&self, tokens: &[String], position: &mut usize) -> Result<f64, String> {
+        let left = self.term(tokens, position)?;
+
+        match tokens.get(*position) {
+            Some(op) if op != ")" => {
+                *position += 1;
+                let right = self.expression(tokens, position)?;
+                binary(op, left, right)
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn term(&self, tokens: &[String], position: &mut usize) -> Result<f64, String> {
+        let token = tokens.get(*position).ok_or("incomplete expression")?;
+        *position += 1;
+
+        match token.as_str() {
+            "(" => {
+                let value = self.expression(tokens, position)?;
+                match tokens.get(*position) {
+                    Some(close) if close == ")" => {
+                        *position += 1;
+                        Ok(value)
+                    }
+                    _ => Err("missing )".to_string()),
+                }
+            }
+            "-" | "~" | "!" | "abs" | "sqrt" | "sin" | "cos" | "tan" | "exp" | "log" | "sign"
+            | "ceil" | "floor" => unary(token, self.term(tokens, position)?),
+            // The byte assembled at an address so far
+            "@" => {
+                let address = integer(self.term(tokens, position)?);
+                match usize::try_from(address) {
+                    Ok(address) if address < MEMORY_SIZE => Ok(self.memory[address] as f64),
+                    _ => Err(format!("address {:#X} is out of range", address)),
+                }
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(PI),
+            "E" => Ok(E),
+            _ => self
+                .known(token)
+                .ok_or_else(|| format!("undefined name {}", token)),
+        }
+    }
+
+    fn value(&mut self) -> Result<f64, String> {
+        let token = self.expect()?;
+        self.value_of(&token)
+    }
+
+    fn value_of(&mut self, token: &str) -> Result<f64, String> {
+        if token == "{" {
+            return self.calc();
+        }
+
+        self.known(token)
+            .ok_or_else(|| format!("undefined name {}", token))
+    }
+
+    // Whether the token has a value now, rather than a label defined later
+    fn resolves(&self, token: &str) -> bool {
+        token == "{" || self.known(token).is_some()
+    }
+
+    fn known(&self, token: &str) -> Option<f64> {
+        literal(token).or_else(|| self.values.get(token).copied())
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let token = self.expect()?;
+        self.byte_of(&token)
+    }
+
+    fn byte_of(&mut self, token: &str) -> Result<u8, String> {
+        fit_byte(integer(self.value_of(token)?))
+    }
+
+    fn nibble(&mut self) -> Result<u16, String> {
+        let token = self.expect()?;
+        self.nibble_of(&token)
+    }
+
+    fn nibble_of(&mut self, token: &str) -> Result<u16, String> {
+        match integer(self.value_of(token)?) {
+            value @ 0..=15 => Ok(value as u16),
+            value => Err(format!("{} doesn't fit in a nibble", value)),
+        }
+    }
+
+    fn register(&mut self) -> Result<u8, String> {
+        let token = self.expect()?;
+        self.register_of(&token)
+            .ok_or_else(|| format!("expected a register, found {}", token))
+    }
+
+    fn register_of(&self, token: &str) -> Option<u8> {
+        if let Some(register) = self.aliases.get(token) {
+            return Some(*register);
+        }
+
+        match token.as_bytes() {
+            [b'v' | b'V', digit] => (*digit as char).to_digit(16).map(|x| x as u8),
+            _ => None,
+        }
+    }
+
+    // FX.. and other opcodes taking a single register in X
+    fn register_op(&mut self, op: u16) -> Result<(), String> {
+        let x = self.register()? as u16;
+        self.emit(op | x << 8)
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let name = self.expect()?;
+        if literal(&name).is_some() || self.register_of(&name).is_some() {
+            return Err(format!("{} can't be used as a name", name));
+        }
+        Ok(name)
+    }
+
+    fn define(&mut self, name: String, value: f64) -> Result<(), String> {
+        if self.values.contains_key(&name) {
+            return Err(format!("{} is already defined", name));
+        }
+        self.values.insert(name, value);
+        Ok(())
+    }
+
+    fn emit(&mut self, op: u16) -> Result<(), String> {
+        self.emit_byte((op >> 8) as u8)?;
+        self.emit_byte(op as u8)
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), String> {
+        if self.here >= MEMORY_SIZE {
+            return Err("the program doesn't fit in memory".to_string());
+        }
+
+        self.memory[self.here] = byte;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+}
+
+// Decimal, 0x hex and 0b binary, optionally negative
+fn literal(token: &str) -> Option<f64> {
+    let (sign, digits) = match token.strip_prefix('-') {
+        Some(digits) => (-1.0, digits),
+        None => (1.0, token),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(sign * value)
+}
+
+fn integer(value: f64) -> i64 {
+    value.floor() as i64
+}
+
+// Negative bytes wrap, so -1 is 0xFF
+fn fit_byte(value: i64) -> Result<u8, String> {
+    match value {
+        -128..=255 => Ok(value as u8),
+        _ => Err(format!("{} doesn't fit in a byte", value)),
+    }
+}
+
+fn unary(op: &str, value: f64) -> Result<f64, String> {
+    Ok(match op {
+        "-" => -value,
+        "~" => !integer(value) as f64,
+        "!" => (value == 0.0) as i64 as f64,
+        "abs" => value.abs(),
+        "sqrt" => value.sqrt(),
+        "sin" => value.sin(),
+        "cos" => value.cos(),
+        "tan" => value.tan(),
+        "exp" => value.exp(),
+        "log" => value.ln(),
+        "sign" => value.signum(),
+        "ceil" => value.ceil(),
+        "floor" => value.floor(),
+        _ => return Err(format!("unknown operator {}", op)),
+    })
+}
+
+fn binary(op: &str, left: f64, right: f64) -> Result<f64, String> {
+    let (a, b) = (integer(left), integer(right));
+    let truth = |value: bool| value as i64 as f64;
+
+    Ok(match op {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        "/" if right == 0.0 => return Err("division by zero".to_string()),
+        "/" => left / right,
+        "%" if b == 0 => return Err("division by zero".to_string()),
+        // i64::MIN % -1 overflows, the remainder is 0 anyway
+        "%" => a.wrapping_rem_euclid(b) as f64,
+        "&" => (a & b) as f64,
+        "|" => (a | b) as f64,
+        "^" => (a ^ b) as f64,
+        "<<" => a.checked_shl(b as u32).unwrap_or(0) as f64,
+        ">>" => a.checked_shr(b as u32).unwrap_or(0) as f64,
+        "pow" => left.powf(right),
+        "min" => left.min(right),
+        "max" => left.max(right),
+        "<" => truth(left < right),
+        ">" => truth(left > right),
+        "<=" => truth(left <= right),
+        ">=" => truth(left >= right),
+        "==" => truth(left == right),
+        "!=" => truth(left != right),
+        _ => return Err(format!("unknown operator {}", op)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::chip::Chip8;
+
+    fn words(rom: &[u8]) -> Vec<u16> {
+        rom.chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]))
+            .collect()
+    }
+
+    #[test]
+    fn assembles_statements() {
+        let rom = assemble(
+            ": main
+               clear
+               v0 := 5  v1 := v0  v1 += 2  v2 -= 1  v3 =- v1  v4 <<= v4
+               i := shape  sprite v0 v1 4
+               v5 := random 0x0F  delay := v5  v6 := key
+               save v3  bcd v6  draw
+             : loop-forever
+               jump loop-forever
+             : draw
+               i += v2 ;
+             : shape
+               0xF0 0x90 0b11110000 -1",
+        )
+        .unwrap();
+
+        assert_eq!(
+            words(&rom),
+            [
+                0x1202, 0x00E0, 0x6005, 0x8100, 0x7102, 0x72FF, 0x8317, 0x844E, 0xA226, 0xD014,
+                0xC50F, 0xF515, 0xF60A, 0xF355, 0xF633, 0x2222, 0x1220, 0xF21E, 0x00EE, 0xF090,
+                0xF0FF,
+            ]
+        );
+    }
+
+    #[test]
+    fn assembles_blocks() {
+        let rom = assemble(
+            ": main
+               loop
+                 v0 += 1
+                 while v0 != 10
+                 if v0 == v1 then v2 := 1
+                 if v0 key begin
+                   v3 := 1
+                 else
+                   v3 := 2
+                 end
+               again
+               if v0 < 4 then exit",
+        )
+        .unwrap();
+
+        assert_eq!(
+            words(&rom),
+            [
+                0x1202, // jump main
+                0x7001, // 202: v0 += 1
+                0x400A, 0x1218, // 204: while v0 != 10
+                0x9010, 0x6201, // 208: if v0 == v1 then
+                0xE09E, 0x1214, 0x6301, 0x1216, // 20C: if v0 key begin
+                0x6302, // 214: else
+                0x1202, // 216: again
+                0x6F04, 0x8F07, 0x3F01, 0x00FD, // 218: if v0 < 4 then
+            ]
+        );
+    }
+
+    #[test]
+    fn expands_macros_and_constants() {
+        let rom = assemble(
+            ":const SPEED 3
+             :calc DOUBLE { SPEED * 2 }
+             :alias x v4
+             :macro move reg amount { reg += amount }
+             : main
+               move x DOUBLE
+               v5 += { 1 + 2 * 3 }
+               :unpack 0xA data
+               :next target v6 := 0
+               i := long data
+             : data
+               :byte { HERE & 0xFF }
+               :pointer target",
+        )
+        .unwrap();
+
+        assert_eq!(
+            words(&rom),
+            [0x1202, 0x7406, 0x7507, 0x60A2, 0x6110, 0x6600, 0xF000, 0x0210, 0x1002, 0x0B00]
+        );
+    }
+
+    #[test]
+    fn reports_mistakes_with_their_line() {
+        assert_eq!(
+            assemble(": main\n  jump nowhere").unwrap_err(),
+            "line 2: undefined name nowhere"
+        );
+        assert_eq!(
+            assemble(": main\n\n v0 := 300").unwrap_err(),
+            "line 3: 300 doesn't fit in a byte"
+        );
+        assert!(assemble("v0 := 1").unwrap_err().contains("no main"));
+        assert!(assemble(": main loop")
+            .unwrap_err()
+            .contains("without again"));
+        assert!(assemble(":macro m { m } : main m").is_err());
+    }
+
+    #[test]
+    fn runs_what_it_assembles() {
+        let rom = assemble(
+            ": main
+               v0 := 0
+               loop
+                 v0 += 3
+                 while v0 < 20
+               again
+               i := result
+               save v0
+             : halt jump halt
+             : result 0",
+        )
+        .unwrap();
+
+        let mut chip = Chip8::new();
+        chip.load_rom(&rom).unwrap();
+        chip.step(200);
+
+        assert_eq!(chip.state().registers[0], 21);
+        assert_eq!(chip.state().memory[0x200 + rom.len() - 1], 21);
+    }
+
+    // Words assembled after the jump to main
+    fn body(source: &str) -> Vec<u16> {
+        let rom = assemble(&format!(": main {}", source)).unwrap();
+        words(&rom[2..])
+    }
+
+    #[test]
+    fn assembles_every_statement() {
+        let cases: [(&str, &[u16]); 50] = [
+            ("return", &[0x00EE]),
+            (";", &[0x00EE]),
+            ("clear", &[0x00E0]),
+            ("exit", &[0x00FD]),
+            ("lores", &[0x00FE]),
+            ("hires", &[0x00FF]),
+            ("scroll-right", &[0x00FB]),
+            ("scroll-left", &[0x00FC]),
+            ("scroll-down 3", &[0x00C3]),
+            ("scroll-up 3", &[0x00D3]),
+            ("audio", &[0xF002]),
+            ("plane 2", &[0xF201]),
+            ("bcd v3", &[0xF333]),
+            ("saveflags v3", &[0xF375]),
+            ("loadflags v3", &[0xF385]),
+            ("save v3", &[0xF355]),
+            ("load v3", &[0xF365]),
+            ("save v3 - v5", &[0x5352]),
+            ("load v3 - v5", &[0x5353]),
+            ("sprite v1 v2 5", &[0xD125]),
+            ("jump 0x345", &[0x1345]),
+            ("jump0 0x345", &[0xB345]),
+            ("native 0x345", &[0x0345]),
+            ("delay := v5", &[0xF515]),
+            ("buzzer := v5", &[0xF518]),
+            ("pitch := v5", &[0xF53A]),
+            ("i := 0x345", &[0xA345]),
+            ("i := hex v3", &[0xF329]),
+            ("i := bighex v3", &[0xF330]),
+            ("i := long 0x1234", &[0xF000, 0x1234]),
+            ("i += v3", &[0xF31E]),
+            ("v0 := v1", &[0x8010]),
+            ("v0 |= v1", &[0x8011]),
+            ("v0 &= v1", &[0x8012]),
+            ("v0 ^= v1", &[0x8013]),
+            ("v0 += v1", &[0x8014]),
+            ("v0 -= v1", &[0x8015]),
+            ("v0 >>= v1", &[0x8016]),
+            ("v0 =- v1", &[0x8017]),
+            ("v0 <<= v1", &[0x801E]),
+            ("v0 := random 0x0F", &[0xC00F]),
+            ("vA := delay", &[0xFA07]),
+            ("vA := key", &[0xFA0A]),
+            ("vA := 5", &[0x6A05]),
+            ("vA += 5", &[0x7A05]),
+            ("vA -= 1", &[0x7AFF]),
+            (":call 0x345", &[0x2345]),
+            (":pointer 0x1234", &[0x1234]),
+            (":byte 7 :byte { 7 / 2 }", &[0x0703]),
+            ("0x12 -1", &[0x12FF]),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(body(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn assembles_every_condition() {
+        // (condition, the skip for then, the skip for begin)
+        let cases: [(&str, &[u16], &[u16]); 12] = [
+            ("v0 == 5", &[0x4005], &[0x3005]),
+            ("v0 != 5", &[0x3005], &[0x4005]),
+            ("v0 == v1", &[0x9010], &[0x5010]),
+            ("v0 != v1", &[0x5010], &[0x9010]),
+            ("v0 key", &[0xE0A1], &[0xE09E]),
+            ("v0 -key", &[0xE09E], &[0xE0A1]),
+            (
+                "v0 < 4",
+                &[0x6F04, 0x8F07, 0x3F01],
+                &[0x6F04, 0x8F07, 0x4F01],
+            ),
+            (
+                "v0 >= 4",
+                &[0x6F04, 0x8F07, 0x3F00],
+                &[0x6F04, 0x8F07, 0x4F00],
+            ),
+            (
+                "v0 > 4",
+                &[0x6F04, 0x8F05, 0x3F01],
+                &[0x6F04, 0x8F05, 0x4F01],
+            ),
+            (
+                "v0 <= 4",
+                &[0x6F04, 0x8F05, 0x3F00],
+                &[0x6F04, 0x8F05, 0x4F00],
+            ),
+            (
+                "v0 < v1",
+                &[0x8F10, 0x8F07, 0x3F01],
+                &[0x8F10, 0x8F07, 0x4F01],
+            ),
+            (
+                "v0 > v1",
+                &[0x8F10, 0x8F05, 0x3F01],
+                &[0x8F10, 0x8F05, 0x4F01],
+            ),
+        ];
+
+        for (condition, then, begin) in cases {
+            let mut expected = then.to_vec();
+            expected.push(0x00E0);
+            assert_eq!(body(&format!("if {} then clear", condition)), expected);
+
+            // The jump past the block follows the skip
+            let mut expected = begin.to_vec();
+            let end = 0x202 + 2 * (begin.len() as u16 + 2);
+            expected.extend([0x1000 | end, 0x00E0]);
+            assert_eq!(body(&format!("if {} begin clear end", condition)), expected);
+        }
+
+        assert!(assemble(": main if v0 ~ 4 then clear").is_err());
+    }
+
+    #[test]
+    fn assembles_directives() {
+        // :org leaves a gap, :breakpoint, :proto and :monitor emit nothing
+        let rom = assemble(
+            ": main
+               :breakpoint here  :proto thing  :monitor v0 2
+               :org 0x208
+               :unpack 0xA 0x123
+               :unpack long 0x1234
+               helper
+             : helper",
+        )
+        .unwrap();
+        assert_eq!(
+            words(&rom),
+            [0x1202, 0x0000, 0x0000, 0x0000, 0x60A1, 0x6123, 0x6012, 0x6134, 0x2212]
+        );
+
+        assert_eq!(
+            assemble(": main :org 0x100").unwrap_err(),
+            "line 1: can't place code at 0x100"
+        );
+        assert_eq!(
+            assemble(": main :stringmode x \"ab\"").unwrap_err(),
+            "line 1: unsupported directive :stringmode"
+        );
+        assert_eq!(
+            assemble(": main : main").unwrap_err(),
+            "line 1: main is already defined"
+        );
+        assert_eq!(
+            assemble(": main 0x34 :byte { @ 0x202 }").unwrap(),
+            [0x12, 0x02, 0x34, 0x34]
+        );
+    }
+
+    fn calc(expression: &str) -> Result<f64, String> {
+        Assembler::new(&format!("{} }}", expression)).calc()
+    }
+
+    #[test]
+    fn calculates_every_operator() {
+        let cases = [
+            ("1 + 2", 3.0),
+            ("5 - 7", -2.0),
+            ("3 * 4", 12.0),
+            ("7 / 2", 3.5),
+            ("7 % 3", 1.0),
+            ("-7 % 3", 2.0),
+            ("-9223372036854775808 % -1", 0.0),
+            ("12 & 10", 8.0),
+            ("12 | 3", 15.0),
+            ("12 ^ 10", 6.0),
+            ("1 << 4", 16.0),
+            ("256 >> 4", 16.0),
+            ("2 pow 10", 1024.0),
+            ("3 min 5", 3.0),
+            ("3 max 5", 5.0),
+            ("1 < 2", 1.0),
+            ("2 > 1", 1.0),
+            ("2 <= 1", 0.0),
+            ("2 >= 2", 1.0),
+            ("2 == 2", 1.0),
+            ("2 != 2", 0.0),
+            ("- 3", -3.0),
+            ("~ 0", -1.0),
+            ("! 0", 1.0),
+            ("! 5", 0.0),
+            ("abs -4", 4.0),
+            ("sqrt 16", 4.0),
+            ("sin 0", 0.0),
+            ("cos 0", 1.0),
+            ("tan 0", 0.0),
+            ("exp 0", 1.0),
+            ("log 1", 0.0),
+            ("sign -3", -1.0),
+            ("ceil 1.5", 2.0),
+            ("floor 1.5", 1.0),
+            ("HERE", 512.0),
+            ("PI", PI),
+            ("E", E),
+            // No precedence, grouped from the right
+            ("2 * 3 + 1", 8.0),
+            ("( 2 * 3 ) + 1", 7.0),
+        ];
+
+        for (expression, expected) in cases {
+            assert_eq!(calc(expression), Ok(expected), "{}", expression);
+        }
+
+        let errors = [
+            ("1 / 0", "division by zero"),
+            ("1 % 0", "division by zero"),
+            ("1 ? 2", "unknown operator ?"),
+            ("1 +", "incomplete expression"),
+            ("( 1", "missing )"),
+            ("1 )", "unexpected ) in expression"),
+            ("nothing", "undefined name nothing"),
+            ("@ 0x1000", "address 0x1000 is out of range"),
+        ];
+
+        for (expression, error) in errors {
+            assert_eq!(calc(expression), Err(error.to_string()), "{}", expression);
+        }
+        assert_eq!(
+            unary("round", 1.5),
+            Err("unknown operator round".to_string())
+        );
+    }
+}
@@ -0,0 +1,61 @@
+pub type Rgb = (u8, u8, u8);
+
+#[derive(Clone)]
+pub struct Palette {
+    pub background: Rgb,
+    pub foreground: Rgb,
+}
+
+impl Palette {
+    pub fn new(background: Rgb, foreground: Rgb) -> Palette {
+        Palette {
+            background,
+            foreground,
+        }
+    }
+
+    // Accepts "rrggbb:rrggbb" with the background first
+    pub fn parse(value: &str) -> Result<Palette, String> {
+        let (background, foreground) = value
+            .split_once(':')
+            .ok_or(format!("invalid palette '{}', expected <bg>:<fg>", value))?;
+
+        Ok(Palette::new(parse_hex(background)?, parse_hex(foreground)?))
+    }
+
+    // Blends between background (0.0) and foreground (1.0)
+    pub fn mix(&self, level: f32) -> Rgb {
+        let channel = |bg: u8, fg: u8| (bg as f32 + (fg as f32 - bg as f32) * level) as u8;
+
+        (
+            channel(self.background.0, self.foreground.0),
+            channel(self.background.1, self.foreground.1),
+            channel(self.background.2, self.foreground.2),
+        )
+    }
+
+    pub fn color(&self, lit: bool) -> Rgb {
+        if lit {
+            self.foreground
+        } else {
+            self.background
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::new((38, 17, 13), (155, 66, 49))
+    }
+}
+
+fn parse_hex(value: &str) -> Result<Rgb, String> {
+    let value = value.trim_start_matches('#');
+    let rgb = u32::from_str_radix(value, 16).map_err(|e| e.to_string())?;
+
+    if value.len() != 6 {
+        return Err(format!("invalid color '{}', expected rrggbb", value));
+    }
+
+    Ok(((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
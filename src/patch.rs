@@ -0,0 +1,351 @@
+use std::fs;
+use std::path::Path;
+
+use crate::rom;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_END: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+// Source, target and patch checksums
+const BPS_FOOTER: usize = 12;
+
+// A patch kept next to the ROM, game.ch8 uses game.ips or game.bps
+pub fn find_for(rom_path: &str) -> Option<String> {
+    ["ips", "bps"]
+        .iter()
+        .map(|extension| Path::new(rom_path).with_extension(extension))
+        .find(|path| path.exists())
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+pub fn load(path: &str, rom: &[u8]) -> Result<Vec<u8>, String> {
+    let patch = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    apply(&patch, rom).map_err(|e| format!("{}: {}", path, e))
+}
+
+// The format is told apart by its magic rather than the file extension
+pub fn apply(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(patch, rom)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(patch, rom)
+    } else {
+        Err("not an IPS or BPS patch".to_string())
+    }
+}
+
+pub fn apply_ips(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = Reader::new(patch, IPS_MAGIC.len());
+    let mut output = rom.to_vec();
+
+    loop {
+        let offset = reader.bytes(3)?;
+        if offset == IPS_END {
+            break;
+        }
+
+        let offset = read_big_endian(offset);
+        let size = read_big_endian(reader.bytes(2)?);
+
+        // A zero size marks a run of one repeated byte
+        let data = if size == 0 {
+            let count = read_big_endian(reader.bytes(2)?);
+            vec![reader.byte()?; count]
+        } else {
+            reader.bytes(size)?.to_vec()
+        };
+
+        if output.len() < offset + data.len() {
+            output.resize(offset + data.len(), 0);
+        }
+        output[offset..offset + data.len()].copy_from_slice(&data);
+    }
+
+    // Some tools append the size to truncate the output to
+    if let Ok(size) = reader.bytes(3) {
+        output.truncate(read_big_endian(size));
+    }
+
+    Ok(output)
+}
+
+pub fn apply_bps(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < BPS_MAGIC.len() + BPS_FOOTER {
+        return Err("truncated patch".to_string());
+    }
+
+    let body = patch.len() - BPS_FOOTER;
+    let source_crc = read_little_endian(&patch[body..body + 4]);
+    let target_crc = read_little_endian(&patch[body + 4..body + 8]);
+    let patch_crc = read_little_endian(&patch[body + 8..]);
+
+    if crc32fast::hash(&patch[..body + 8]) != patch_crc {
+        return Err("patch checksum mismatch, the file is damaged".to_string());
+    }
+
+    if crc32fast::hash(rom) != source_crc {
+        return Err("ROM checksum mismatch, the patch is for a different ROM".to_string());
+    }
+
+    let mut reader = Reader::new(&patch[..body], BPS_MAGIC.len());
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    if target_size > rom::MAX_SIZE {
+        return Err(format!(
+            "patched ROM would be {} bytes, only {} fit in memory",
+            target_size,
+            rom::MAX_SIZE
+        ));
+    }
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+
+    if source_size != rom.len() {
+        return Err("ROM size mismatch, the patch is for a different ROM".to_string());
+    }
+
+    let mut output = Vec::with_capacity(target_size);
+    let mut source_offset = 0;
+    let mut target_offset = 0;
+
+    while !reader.is_done() {
+        let action = reader.number()?;
+        let length = (action >> 2) + 1;
+        if length > target_size - output.len() {
+            return Err("patch writes past the patched ROM's size".to_string());
+        }
+
+        match action & 3 {
+            // Source read, copy from the ROM at the same position
+            0 => {
+                let start = output.len();
+                let bytes = rom
+                    .get(start..start + length)
+                    .ok_or("source read out of range")?;
+                output.extend_from_slice(bytes);
+            }
+            // Target read, copy from the patch
+            1 => output.extend_from_slice(reader.bytes(length)?),
+            // Source copy, copy from anywhere in the ROM
+            2 => {
+                source_offset = seek(source_offset, reader.number()?, rom.len())?;
+                let bytes = rom
+                    .get(source_offset..source_offset + length)
+                    .ok_or("source copy out of range")?;
+                output.extend_from_slice(bytes);
+                source_offset += length;
+            }
+            // Target copy, copy output already written, which may overlap
+            _ => {
+                target_offset = seek(target_offset, reader.number()?, output.len())?;
+                for _ in 0..length {
+                    let byte = *output
+                        .get(target_offset)
+                        .ok_or("target copy out of range")?;
+                    output.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if output.len() != target_size {
+        return Err("patched ROM has the wrong size".to_string());
+    }
+
+    if crc32fast::hash(&output) != target_crc {
+        return Err("patched ROM checksum mismatch".to_string());
+    }
+
+    Ok(output)
+}
+
+// Relative offsets store the sign in their lowest bit
+fn seek(offset: usize, relative: usize, limit: usize) -> Result<usize, String> {
+    let distance = relative >> 1;
+    let offset = if relative & 1 == 1 {
+        offset.checked_sub(distance)
+    } else {
+        offset.checked_add(distance)
+    };
+
+    offset
+        .filter(|offset| *offset <= limit)
+        .ok_or_else(|| "relative offset out of range".to_string())
+}
+
+fn read_big_endian(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |value, byte| value << 8 | *byte as usize)
+}
+
+fn read_little_endian(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], position: usize) -> Reader<'a> {
+        Reader { data, position }
+    }
+
+    fn is_done(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .position
+            .checked_add(count)
+            .and_then(|end| self.data.get(self.position..end))
+            .ok_or("truncated patch")?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    // BPS variable length numbers, 7 bits at a time with the top bit ending
+    // the number and each continuation adding one to avoid duplicate encodings
+    fn number(&mut self) -> Result<usize, String> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+
+        loop {
+            let byte = self.byte()?;
+            value = (byte as usize & 0x7F)
+                .checked_mul(shift)
+                .and_then(|bits| value.checked_add(bits))
+                .ok_or("number out of range")?;
+
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+
+            shift = shift.checked_mul(0x80).ok_or("number out of range")?;
+            value = value.checked_add(shift).ok_or("number out of range")?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_number(mut value: usize, out: &mut Vec<u8>) {
+        loop {
+            let bits = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(bits | 0x80);
+                return;
+            }
+            out.push(bits);
+            value -= 1;
+        }
+    }
+
+    fn bps(rom: &[u8], target: &[u8], actions: &[u8]) -> Vec<u8> {
+        bps_sized(rom, target.len(), target, actions)
+    }
+
+    fn bps_sized(rom: &[u8], target_size: usize, target: &[u8], actions: &[u8]) -> Vec<u8> {
+        let mut patch = BPS_MAGIC.to_vec();
+        encode_number(rom.len(), &mut patch);
+        encode_number(target_size, &mut patch);
+        encode_number(0, &mut patch);
+        patch.extend_from_slice(actions);
+        patch.extend_from_slice(&crc32fast::hash(rom).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+        let crc = crc32fast::hash(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn applies_ips_records_and_runs() {
+        let rom = [0x00, 0xE0, 0x12, 0x00];
+        let mut patch = IPS_MAGIC.to_vec();
+        // Replace 0x0002 with 13 02, then a run of three 0xAA at 0x0005
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x02, 0x13, 0x02]);
+        patch.extend_from_slice(&[0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x03, 0xAA]);
+        patch.extend_from_slice(IPS_END);
+
+        let output = apply(&patch, &rom).unwrap();
+        assert_eq!(output, [0x00, 0xE0, 0x13, 0x02, 0x00, 0xAA, 0xAA, 0xAA]);
+    }
+
+    #[test]
+    fn applies_bps_actions() {
+        let rom = [0x00, 0xE0, 0x12, 0x00];
+        let target = [0x00, 0xE0, 0x60, 0x01, 0x60, 0x01, 0x12, 0x00];
+
+        let mut actions = Vec::new();
+        // Source read of 2, target read of 2, target copy of 2 from offset 2,
+        // source copy of 2 from offset 2
+        encode_number(1 << 2, &mut actions);
+        encode_number((1 << 2) | 1, &mut actions);
+        actions.extend_from_slice(&[0x60, 0x01]);
+        encode_number((1 << 2) | 3, &mut actions);
+        encode_number(2 << 1, &mut actions);
+        encode_number((1 << 2) | 2, &mut actions);
+        encode_number(2 << 1, &mut actions);
+
+        let output = apply(&bps(&rom, &target, &actions), &rom).unwrap();
+        assert_eq!(output, target);
+    }
+
+    #[test]
+    fn rejects_bps_patches_for_other_roms() {
+        let rom = [0x00, 0xE0];
+        let mut actions = Vec::new();
+        encode_number(1 << 2, &mut actions);
+
+        let patch = bps(&rom, &rom, &actions);
+        assert!(apply(&patch, &[0x00, 0xEE])
+            .unwrap_err()
+            .contains("ROM checksum"));
+
+        let mut damaged = patch.clone();
+        damaged[5] ^= 1;
+        assert!(apply(&damaged, &rom)
+            .unwrap_err()
+            .contains("patch checksum"));
+    }
+
+    #[test]
+    fn rejects_bps_sizes_that_do_not_fit() {
+        let rom = [0x00, 0xE0];
+        let mut actions = Vec::new();
+        encode_number(1 << 2, &mut actions);
+
+        let patch = bps_sized(&rom, usize::MAX >> 1, &rom, &actions);
+        assert!(apply(&patch, &rom).unwrap_err().contains("fit in memory"));
+
+        // A target copy far longer than the declared size
+        encode_number((1 << 40 << 2) | 3, &mut actions);
+        encode_number(0, &mut actions);
+        let patch = bps_sized(&rom, 4, &rom, &actions);
+        assert!(apply(&patch, &rom)
+            .unwrap_err()
+            .contains("past the patched ROM"));
+
+        // Metadata longer than the patch
+        let mut patch = BPS_MAGIC.to_vec();
+        encode_number(rom.len(), &mut patch);
+        encode_number(rom.len(), &mut patch);
+        encode_number(usize::MAX >> 1, &mut patch);
+        patch.extend_from_slice(&crc32fast::hash(&rom).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(&rom).to_le_bytes());
+        let crc = crc32fast::hash(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        assert!(apply(&patch, &rom).unwrap_err().contains("truncated"));
+    }
+}
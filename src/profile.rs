@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::disasm::disassemble;
+
+const MEMORY_SIZE: usize = 4096;
+const ROM_START: u16 = 0x200;
+
+const CLASS_NAMES: [&str; 16] = [
+    "system",
+    "jump",
+    "call",
+    "skip if equal",
+    "skip if not equal",
+    "skip if registers equal",
+    "load",
+    "add",
+    "arithmetic",
+    "skip if registers differ",
+    "load index",
+    "jump with offset",
+    "random",
+    "draw",
+    "keypad",
+    "misc",
+];
+
+#[derive(Clone, Copy, Default)]
+pub struct CallStats {
+    pub calls: u64,
+    pub cycles: u64,
+}
+
+struct Frame {
+    routine: u16,
+    caller: u16,
+    start: u64,
+}
+
+// Counts are in instructions, which is what the original interpreter's
+// timing is roughly proportional to
+pub struct Profiler {
+    hits: Vec<u64>,
+    opcodes: Vec<u16>,
+    classes: [u64; 16],
+    total: u64,
+    frames: Vec<Frame>,
+    routines: HashMap<u16, CallStats>,
+    edges: HashMap<(u16, u16), CallStats>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            hits: vec![0; MEMORY_SIZE],
+            opcodes: vec![0; MEMORY_SIZE],
+            classes: [0; 16],
+            total: 0,
+            frames: Vec::new(),
+            routines: HashMap::new(),
+            edges: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, pc: u16, code: u16) {
+        let address = pc as usize % MEMORY_SIZE;
+
+        self.hits[address] += 1;
+        self.opcodes[address] = code;
+        self.classes[(code >> 12) as usize] += 1;
+        self.total += 1;
+
+        if code >> 12 == 0x2 {
+            self.frames.push(Frame {
+                routine: code & 0xFFF,
+                caller: self.current_routine(),
+                start: self.total,
+            });
+        } else if code == 0x00EE {
+            // Returns without a matching call (e.g. after loading a state) are ignored
+            if let Some(frame) = self.frames.pop() {
+                let cycles = self.total - frame.start;
+
+                let routine = self.routines.entry(frame.routine).or_default();
+                routine.calls += 1;
+                routine.cycles += cycles;
+
+                let edge = self.edges.entry((frame.caller, frame.routine)).or_default();
+                edge.calls += 1;
+                edge.cycles += cycles;
+            }
+        }
+    }
+
+    fn current_routine(&self) -> u16 {
+        self.frames.last().map_or(ROM_START, |frame| frame.routine)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn hits(&self, address: u16) -> u64 {
+        self.hits[address as usize % MEMORY_SIZE]
+    }
+
+    pub fn class_count(&self, nibble: u8) -> u64 {
+        self.classes[(nibble & 0xF) as usize]
+    }
+
+    // Inclusive counts only cover calls that have returned
+    pub fn routine(&self, address: u16) -> CallStats {
+        self.routines.get(&address).copied().unwrap_or_default()
+    }
+
+    pub fn edge(&self, caller: u16, callee: u16) -> CallStats {
+        self.edges
+            .get(&(caller, callee))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &str, top: usize) -> Result<(), String> {
+        if path == "-" {
+            self.report(&mut io::stdout().lock(), top)
+        } else {
+            let file = File::create(path).map_err(|e| e.to_string())?;
+            self.report(&mut BufWriter::new(file), top)
+        }
+        .map_err(|e| e.to_string())
+    }
+
+    pub fn report(&self, out: &mut dyn Write, top: usize) -> io::Result<()> {
+        writeln!(out, "instructions executed: {}", self.total)?;
+
+        writeln!(out, "\nhot addresses:")?;
+        let mut addresses: Vec<usize> = (0..MEMORY_SIZE).filter(|a| self.hits[*a] > 0).collect();
+        addresses.sort_by(|a, b| self.hits[*b].cmp(&self.hits[*a]).then(a.cmp(b)));
+
+        for address in addresses.into_iter().take(top) {
+            writeln!(
+                out,
+                "  {:03X}  {:>10}  {:>6.2}%  {}",
+                address,
+                self.hits[address],
+                self.percent(self.hits[address]),
+                disassemble(self.opcodes[address])
+            )?;
+        }
+
+        writeln!(out, "\nsubroutines (inclusive):")?;
+        let mut routines: Vec<(&u16, &CallStats)> = self.routines.iter().collect();
+        routines.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+
+        for (address, stats) in routines.into_iter().take(top) {
+            writeln!(
+                out,
+                "  {:03X}  {:>8} calls  {:>10} cycles  {:>6.2}%",
+                address,
+                stats.calls,
+                stats.cycles,
+                self.percent(stats.cycles)
+            )?;
+        }
+
+        writeln!(out, "\ncall graph:")?;
+        let mut edges: Vec<(&(u16, u16), &CallStats)> = self.edges.iter().collect();
+        edges.sort_by_key(|(edge, _)| **edge);
+
+        for ((caller, callee), stats) in edges {
+            writeln!(
+                out,
+                "  {:03X} -> {:03X}  {:>8} calls  {:>10} cycles",
+                caller, callee, stats.calls, stats.cycles
+            )?;
+        }
+
+        writeln!(out, "\ninstruction classes:")?;
+        for (nibble, count) in self.classes.iter().enumerate() {
+            if *count > 0 {
+                writeln!(
+                    out,
+                    "  {:X}  {:<24}  {:>10}  {:>6.2}%",
+                    nibble,
+                    CLASS_NAMES[nibble],
+                    count,
+                    self.percent(*count)
+                )?;
+            }
+        }
+
+        out.flush()
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.total as f64
+        }
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(program: &[(u16, u16)]) -> Profiler {
+        let mut profiler = Profiler::new();
+        for (pc, code) in program {
+            profiler.record(*pc, *code);
+        }
+        profiler
+    }
+
+    #[test]
+    fn counts_hits_and_classes() {
+        let profiler = profile(&[(0x200, 0x6001), (0x202, 0x1200), (0x200, 0x6001)]);
+
+        assert_eq!(profiler.total(), 3);
+        assert_eq!(profiler.hits(0x200), 2);
+        assert_eq!(profiler.hits(0x202), 1);
+        assert_eq!(profiler.class_count(0x6), 2);
+        assert_eq!(profiler.class_count(0x1), 1);
+    }
+
+    #[test]
+    fn attributes_inclusive_cycles_to_subroutines() {
+        let profiler = profile(&[
+            (0x200, 0x2300),
+            (0x300, 0x6001),
+            (0x302, 0x2400),
+            (0x400, 0x7001),
+            (0x402, 0x00EE),
+            (0x304, 0x00EE),
+        ]);
+
+        assert_eq!(profiler.routine(0x400).cycles, 2);
+        assert_eq!(profiler.routine(0x300).cycles, 5);
+        assert_eq!(profiler.edge(0x200, 0x300).calls, 1);
+        assert_eq!(profiler.edge(0x300, 0x400).calls, 1);
+    }
+
+    #[test]
+    fn ignores_unmatched_returns() {
+        let profiler = profile(&[(0x200, 0x00EE)]);
+
+        assert_eq!(profiler.routine(0x200).calls, 0);
+        assert_eq!(profiler.total(), 1);
+    }
+}
@@ -0,0 +1,170 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::core::display::{Display, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::palette::Palette;
+use crate::screenshot;
+
+const FPS: u32 = 60;
+
+enum Format {
+    Gif,
+    Png,
+    Ppm,
+}
+
+pub struct Recorder {
+    path: String,
+    format: Format,
+    palette: Palette,
+    scale: usize,
+    frames: Vec<[u64; SCREEN_HEIGHT]>,
+}
+
+impl Recorder {
+    // The extension picks the output: a single .gif or a numbered .png/.ppm sequence
+    pub fn new(path: &str, palette: Palette, scale: usize) -> Result<Recorder, String> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+
+        let format = match extension.as_deref() {
+            Some("gif") => Format::Gif,
+            Some("png") => Format::Png,
+            Some("ppm") => Format::Ppm,
+            _ => {
+                return Err(format!(
+                    "cannot record to '{}', use .gif, .png or .ppm",
+                    path
+                ))
+            }
+        };
+
+        Ok(Recorder {
+            path: path.to_string(),
+            format,
+            palette,
+            scale: scale.max(1),
+            frames: vec![],
+        })
+    }
+
+    pub fn capture(&mut self, display: &Display) {
+        self.frames.push(display.screen_memory);
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        match self.format {
+            Format::Gif => self.save_gif(),
+            Format::Png | Format::Ppm => self.save_sequence(),
+        }
+    }
+
+    fn save_gif(&self) -> Result<(), String> {
+        let width = SCREEN_WIDTH as usize * self.scale;
+        let height = SCREEN_HEIGHT * self.scale;
+
+        let background = self.palette.background;
+        let foreground = self.palette.foreground;
+        let colors = [
+            background.0,
+            background.1,
+            background.2,
+            foreground.0,
+            foreground.1,
+            foreground.2,
+        ];
+
+        let file = File::create(&self.path).map_err(|e| e.to_string())?;
+        let mut encoder =
+            gif::Encoder::new(BufWriter::new(file), width as u16, height as u16, &colors)
+                .map_err(|e| e.to_string())?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(|e| e.to_string())?;
+
+        // Identical frames are merged, delays are in hundredths of a second
+        let mut elapsed_frames = 0u32;
+        let mut written_delay = 0u32;
+        let mut index = 0;
+
+        while index < self.frames.len() {
+            let mut end = index + 1;
+            while end < self.frames.len() && self.frames[end] == self.frames[index] {
+                end += 1;
+            }
+
+            elapsed_frames += (end - index) as u32;
+            let delay = (elapsed_frames * 100 / FPS - written_delay).max(1);
+            written_delay += delay;
+
+            let frame = gif::Frame {
+                width: width as u16,
+                height: height as u16,
+                delay: delay as u16,
+                buffer: Cow::Owned(self.indexed_pixels(&self.frames[index])),
+                ..Default::default()
+            };
+            encoder.write_frame(&frame).map_err(|e| e.to_string())?;
+
+            index = end;
+        }
+
+        Ok(())
+    }
+
+    fn save_sequence(&self) -> Result<(), String> {
+        let path = Path::new(&self.path);
+        let stem = path.with_extension("");
+        let extension = path.extension().unwrap().to_string_lossy();
+
+        for (number, frame) in self.frames.iter().enumerate() {
+            let display = Display {
+                screen_memory: *frame,
+            };
+            let frame_path = format!("{}-{:05}.{}", stem.display(), number, extension);
+
+            match self.format {
+                Format::Png => {
+                    screenshot::save_scaled(&frame_path, &display, &self.palette, self.scale)?
+                }
+                _ => self.write_ppm(&frame_path, &display)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_ppm(&self, path: &str, display: &Display) -> Result<(), String> {
+        let width = SCREEN_WIDTH as usize * self.scale;
+        let height = SCREEN_HEIGHT * self.scale;
+        let pixels = screenshot::scaled_pixels(display, &self.palette, self.scale);
+
+        let mut file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+        write!(file, "P6\n{} {}\n255\n", width, height).map_err(|e| e.to_string())?;
+        file.write_all(&pixels).map_err(|e| e.to_string())
+    }
+
+    fn indexed_pixels(&self, frame: &[u64; SCREEN_HEIGHT]) -> Vec<u8> {
+        let display = Display {
+            screen_memory: *frame,
+        };
+        let width = SCREEN_WIDTH as usize * self.scale;
+        let mut pixels = Vec::with_capacity(width * SCREEN_HEIGHT * self.scale);
+
+        for y in 0..SCREEN_HEIGHT * self.scale {
+            for x in 0..width {
+                pixels.push(display.is_lit(x / self.scale, y / self.scale) as u8);
+            }
+        }
+
+        pixels
+    }
+}
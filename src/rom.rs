@@ -0,0 +1,334 @@
+use std::fs::{self, File};
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use crate::analysis::Analysis;
+use crate::cartridge::Cartridge;
+use crate::core::chip::MEM_OFFSET;
+use crate::core::quirks::Quirks;
+use crate::octo;
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const GIF_MAGIC: &[u8] = b"GIF8";
+const EXTENSIONS: [&str; 5] = ["ch8", "c8", "sc8", "xo8", "rom"];
+const MEMORY_SIZE: usize = 4096;
+pub const MAX_SIZE: usize = MEMORY_SIZE - MEM_OFFSET as usize;
+
+// Reads a raw ROM, the ROM inside a zip archive or the program of an Octo
+// cartridge. `entry` picks a file in the archive, which is otherwise the only
+// ROM it holds.
+pub fn read(path: &str, entry: Option<&str>) -> Result<Vec<u8>, String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+
+    if data.starts_with(ZIP_MAGIC) {
+        read_zip(&data, entry).map_err(|e| format!("{}: {}", path, e))
+    } else if data.starts_with(GIF_MAGIC) {
+        read_cartridge(&data).map_err(|e| format!("{}: {}", path, e))
+    } else if entry.is_some() {
+        Err(format!("{} is not a zip archive", path))
+    } else {
+        Ok(data)
+    }
+}
+
+// Cartridges hold the Octo source, which is assembled into the ROM
+pub fn read_cartridge(data: &[u8]) -> Result<Vec<u8>, String> {
+    let cartridge = Cartridge::read(data)?;
+    octo::assemble(&cartridge.program)
+}
+
+pub fn is_cartridge(path: &str) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && magic == GIF_MAGIC
+}
+
+pub fn read_zip(data: &[u8], entry: Option<&str>) -> Result<Vec<u8>, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).map_err(|e| e.to_string())?;
+
+    let files: Vec<String> = archive
+        .file_names()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|name| !name.ends_with('/'))
+        .map(|name| name.into_owned())
+        .collect();
+
+    let name = match entry {
+        Some(entry) => files
+            .iter()
+            .find(|name| *name == entry || file_name(name) == entry)
+            .ok_or_else(|| format!("no {} in the archive, it holds {}", entry, files.join(", ")))?,
+        None => {
+            let roms: Vec<&String> = files.iter().filter(|name| is_rom(name)).collect();
+            match (roms.as_slice(), files.as_slice()) {
+                ([rom], _) => *rom,
+                ([], [file]) => file,
+                _ => {
+                    return Err(format!(
+                        "pick a ROM with --entry, the archive holds {}",
+                        files.join(", ")
+                    ))
+                }
+            }
+        }
+    };
+
+    let mut file = archive.by_name(name).map_err(|e| e.to_string())?;
+    let mut rom = Vec::new();
+    file.read_to_end(&mut rom).map_err(|e| e.to_string())?;
+    Ok(rom)
+}
+
+// Errors for ROMs that can't be loaded, warnings for ones that look wrong
+pub fn validate(data: &[u8]) -> Result<Vec<String>, String> {
+    if data.is_empty() {
+        return Err("the ROM is empty".to_string());
+    }
+
+    if data.len() > MAX_SIZE {
+        return Err(format!(
+            "the ROM is {} bytes, but only {} fit in memory from 0x{:03X}",
+            data.len(),
+            MAX_SIZE,
+            MEM_OFFSET
+        ));
+    }
+
+    let mut warnings = Vec::new();
+    if data.len() % 2 == 1 {
+        warnings.push(format!(
+            "the ROM is {} bytes, an odd length for 2 byte instructions",
+            data.len()
+        ));
+    }
+
+    Ok(warnings)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Platform {
+    Chip8,
+    Schip,
+    Xochip,
+}
+
+impl Platform {
+    // Matches the quirks preset names
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::Schip => "schip",
+            Platform::Xochip => "xochip",
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        Quirks::from_name(self.name()).unwrap()
+    }
+}
+
+pub struct Detection {
+    pub platform: Platform,
+    // Instructions that gave the platform away, as (address, opcode)
+    pub evidence: Vec<(u16, u16)>,
+    // Quirks the ROM has instructions for, so the preset matters
+    pub quirks_used: Vec<&'static str>,
+}
+
+// Only instructions reachable from the load address are looked at, as
+// sprites are full of bytes like 00FF that read as SCHIP opcodes
+pub fn detect(data: &[u8]) -> Detection {
+    let mut schip = Vec::new();
+    let mut xochip = Vec::new();
+    let mut quirks_used = Vec::new();
+
+    let analysis = Analysis::run(data);
+    for address in analysis.instructions() {
+        let code = analysis.fetch(address).unwrap_or(0);
+
+        if is_schip(code) {
+            schip.push((address, code));
+        }
+        if is_xochip(code) {
+            xochip.push((address, code));
+        }
+
+        for quirk in quirks_for(code) {
+            if !quirks_used.contains(quirk) {
+                quirks_used.push(*quirk);
+            }
+        }
+    }
+
+    let (platform, evidence) = if !xochip.is_empty() {
+        (Platform::Xochip, xochip)
+    } else if !schip.is_empty() {
+        (Platform::Schip, schip)
+    } else {
+        (Platform::Chip8, Vec::new())
+    };
+
+    Detection {
+        platform,
+        evidence,
+        quirks_used,
+    }
+}
+
+fn is_schip(code: u16) -> bool {
+    match code {
+        // Scroll down, right and left, exit, low and high resolution
+        0x00C1..=0x00CF | 0x00FB..=0x00FF => true,
+        // 16x16 sprites, big font, flag registers
+        _ => {
+            code >> 12 == 0xD && code & 0xF == 0
+                || matches!(code & 0xF0FF, 0xF030 | 0xF075 | 0xF085)
+        }
+    }
+}
+
+fn is_xochip(code: u16) -> bool {
+    match code {
+        // Long I load, audio pattern, scroll up
+        0xF000 | 0xF002 | 0x00D1..=0x00DF => true,
+        // Plane select, pitch, register range save and load
+        _ => matches!(code & 0xF0FF, 0xF001 | 0xF03A) || matches!(code & 0xF00F, 0x5002 | 0x5003),
+    }
+}
+
+pub(crate) fn quirks_for(code: u16) -> &'static [&'static str] {
+    match (code >> 12, code & 0xF, code & 0xFF) {
+        (0x8, 0x1..=0x3, _) => &["vf_reset"],
+        (0x8, 0x6 | 0xE, _) => &["shifting"],
+        (0xB, _, _) => &["jumping"],
+        (0xD, _, _) => &["display_wait", "clipping"],
+        (0xF, _, 0x55 | 0x65) => &["increment_index"],
+        _ => &[],
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn is_rom(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer
+                .start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn rejects_roms_that_do_not_fit() {
+        assert!(validate(&[]).is_err());
+        assert!(validate(&[0; MAX_SIZE + 1]).is_err());
+        assert!(validate(&[0; MAX_SIZE]).unwrap().is_empty());
+        assert_eq!(validate(&[0x00, 0xE0, 0x12]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn detects_platforms_from_their_opcodes() {
+        // CLS, LD V0, 1, SHR V0, JP 200
+        let chip8 = detect(&[0x00, 0xE0, 0x60, 0x01, 0x80, 0x06, 0x12, 0x00]);
+        assert_eq!(chip8.platform, Platform::Chip8);
+        assert_eq!(chip8.quirks_used, ["shifting"]);
+
+        // HIGH, CLS
+        let schip = detect(&[0x00, 0xFF, 0x00, 0xE0]);
+        assert_eq!(schip.platform, Platform::Schip);
+        assert_eq!(schip.evidence, [(0x200, 0x00FF)]);
+
+        // HIGH, LD I, long 0x1234
+        let xochip = detect(&[0x00, 0xFF, 0xF0, 0x00, 0x12, 0x34]);
+        assert_eq!(xochip.platform, Platform::Xochip);
+
+        // JP 204, then a sprite row that reads as HIGH
+        let data = detect(&[0x12, 0x04, 0x00, 0xFF, 0x12, 0x04]);
+        assert_eq!(data.platform, Platform::Chip8);
+    }
+
+    // An Octo cartridge with the payload in the low bits of each pixel
+    fn cartridge(program: &str) -> Vec<u8> {
+        let payload = serde_json::json!({ "program": program, "options": {} }).to_string();
+        let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(payload.as_bytes());
+
+        let mut pixels: Vec<u8> = bytes
+            .iter()
+            .flat_map(|byte| [6, 4, 2, 0].map(|shift| (byte >> shift) & 3))
+            .collect();
+        let width = 64;
+        let height = pixels.len().div_ceil(width);
+        pixels.resize(width * height, 0);
+
+        let mut gif = Vec::new();
+        let mut encoder =
+            gif::Encoder::new(&mut gif, width as u16, height as u16, &[0; 12]).unwrap();
+        let frame = gif::Frame::from_indexed_pixels(width as u16, height as u16, pixels, None);
+        encoder.write_frame(&frame).unwrap();
+        drop(encoder);
+        gif
+    }
+
+    #[test]
+    fn assembles_the_program_of_a_cartridge() {
+        let gif = cartridge(": main\n  v0 := 7\n  jump main");
+        assert_eq!(
+            read_cartridge(&gif).unwrap(),
+            [0x12, 0x02, 0x60, 0x07, 0x12, 0x02]
+        );
+
+        let path = std::env::temp_dir().join(format!("chip_8_cart_{}.gif", std::process::id()));
+        fs::write(&path, &gif).unwrap();
+        let path = path.to_str().unwrap();
+        assert!(is_cartridge(path));
+        assert_eq!(
+            read(path, None).unwrap(),
+            [0x12, 0x02, 0x60, 0x07, 0x12, 0x02]
+        );
+        fs::remove_file(path).unwrap();
+
+        let broken = cartridge(": main\n  jump nowhere");
+        assert!(read_cartridge(&broken)
+            .unwrap_err()
+            .contains("undefined name nowhere"));
+    }
+
+    #[test]
+    fn picks_the_only_rom_in_an_archive() {
+        let data = archive(&[("README.txt", b"hello"), ("games/pong.ch8", &[0x00, 0xE0])]);
+
+        assert_eq!(read_zip(&data, None).unwrap(), [0x00, 0xE0]);
+    }
+
+    #[test]
+    fn picks_the_chosen_rom_in_an_archive() {
+        let data = archive(&[("pong.ch8", &[0x00, 0xE0]), ("tetris.ch8", &[0x12, 0x00])]);
+
+        assert!(read_zip(&data, None)
+            .unwrap_err()
+            .contains("pong.ch8, tetris.ch8"));
+        assert_eq!(read_zip(&data, Some("tetris.ch8")).unwrap(), [0x12, 0x00]);
+        assert!(read_zip(&data, Some("breakout.ch8")).is_err());
+    }
+}
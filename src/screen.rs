@@ -4,17 +4,47 @@ use sdl2::render::Canvas;
 use sdl2::video::Window;
 use sdl2::Sdl;
 
-pub const SCREEN_WIDTH: u8 = 64;
-pub const SCREEN_HEIGHT: usize = 32;
+use chip_8::core::chip::Chip8;
+use chip_8::core::display::{Display, SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip_8::palette::Palette;
+
 pub const PIXEL_SCALE: usize = 20;
 
+pub struct Filters {
+    pub phosphor: bool,
+    pub blend: bool,
+    pub vblank: bool,
+    pub decay_frames: u32,
+}
+
+impl Filters {
+    pub fn new() -> Filters {
+        Filters {
+            phosphor: false,
+            blend: false,
+            vblank: false,
+            decay_frames: 6,
+        }
+    }
+
+    // Phosphor decay and blending change the picture even when nothing was drawn
+    pub fn needs_redraw(&self) -> bool {
+        self.phosphor || self.blend
+    }
+}
+
 pub struct Screen {
-    pub screen_memory: [u64; SCREEN_HEIGHT],
     pub canvas: Canvas<Window>,
+    pub filters: Filters,
+    pub palette: Palette,
+    previous_frame: [u64; SCREEN_HEIGHT],
+    intensity: [[f32; SCREEN_WIDTH as usize]; SCREEN_HEIGHT],
+    // Frame count of the chip when the picture was last presented
+    presented_frame: u64,
 }
 
 impl Screen {
-    fn init_canvas(sdl: &Sdl) -> Canvas<Window> {
+    fn init_canvas(sdl: &Sdl, palette: &Palette) -> Canvas<Window> {
         let video_subsystem = sdl.video().unwrap();
 
         let window = video_subsystem
@@ -35,41 +65,89 @@ impl Screen {
             .map_err(|e| e.to_string())
             .unwrap();
 
-        canvas.set_draw_color(Color::RGB(38, 17, 13));
+        canvas.set_draw_color(Color::from(palette.background));
         canvas.clear();
 
         canvas
     }
 
-    pub fn new(sdl: &Sdl) -> Screen {
+    pub fn new(sdl: &Sdl, palette: Palette) -> Screen {
         Screen {
-            screen_memory: [0u64; SCREEN_HEIGHT],
-            canvas: Screen::init_canvas(sdl),
+            canvas: Screen::init_canvas(sdl, &palette),
+            filters: Filters::new(),
+            palette,
+            previous_frame: [0u64; SCREEN_HEIGHT],
+            intensity: [[0f32; SCREEN_WIDTH as usize]; SCREEN_HEIGHT],
+            presented_frame: 0,
+        }
+    }
+
+    fn pixel_level(&mut self, display: &Display, row_index: usize, column: usize) -> f32 {
+        let mask = 1u64 << (SCREEN_WIDTH as usize - 1 - column);
+
+        let mut level: f32 = if display.screen_memory[row_index] & mask != 0 {
+            1.0
+        } else {
+            0.0
+        };
+
+        // Average with the last frame so sprites erased and redrawn stay half lit
+        if self.filters.blend {
+            let previous = if self.previous_frame[row_index] & mask != 0 {
+                1.0
+            } else {
+                0.0
+            };
+            level = (level + previous) / 2.0;
         }
+
+        // Lit pixels fade out over `decay_frames` instead of turning off at once
+        if self.filters.phosphor {
+            let decay = 1.0 / self.filters.decay_frames.max(1) as f32;
+            let faded = self.intensity[row_index][column] - decay;
+            level = level.max(faded).max(0.0);
+        }
+
+        self.intensity[row_index][column] = level;
+
+        level
     }
 
-    pub fn clear(&mut self) {
-        self.screen_memory = [0u64; SCREEN_HEIGHT];
+    // With `vblank` set the picture only changes once the chip ends a frame,
+    // so stepping through a frame in a debugger never shows half-drawn sprites
+    pub fn update(&mut self, chip: &mut Chip8) {
+        if self.filters.vblank && chip.frames() == self.presented_frame {
+            return;
+        }
+        self.presented_frame = chip.frames();
+
+        if chip.take_redraw() || self.filters.needs_redraw() {
+            self.render(chip.display());
+        }
     }
 
-    pub fn render(&mut self) {
-        self.canvas.set_draw_color(Color::RGB(38, 17, 13));
+    fn render(&mut self, display: &Display) {
+        self.canvas
+            .set_draw_color(Color::from(self.palette.background));
         self.canvas.clear();
 
         let mut pixel = Rect::new(0, 0, PIXEL_SCALE as u32, PIXEL_SCALE as u32);
 
-        let width = SCREEN_WIDTH as u64;
+        for row_index in 0..SCREEN_HEIGHT {
+            for column in 0..SCREEN_WIDTH as usize {
+                let level = self.pixel_level(display, row_index, column);
 
-        for (row_index, row) in self.screen_memory.iter().enumerate() {
-            for column in 0..width {
-                if 1u64 << (width - 1 - column) & row != 0 {
+                if level > 0.0 {
                     pixel.x = column as i32 * PIXEL_SCALE as i32;
                     pixel.y = row_index as i32 * PIXEL_SCALE as i32;
-                    self.canvas.set_draw_color(Color::RGB(155, 66, 49));
+                    self.canvas
+                        .set_draw_color(Color::from(self.palette.mix(level)));
                     self.canvas.fill_rect(pixel).unwrap();
                 }
             }
         }
         self.canvas.present();
+
+        self.previous_frame = display.screen_memory;
     }
 }
@@ -0,0 +1,75 @@
+use std::fs::File;
+use std::io::BufWriter;
+
+use crate::core::display::{Display, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::palette::Palette;
+
+// One byte per pixel, black and white at 64x32
+pub fn native_pixels(display: &Display) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(SCREEN_WIDTH as usize * SCREEN_HEIGHT);
+
+    for row in 0..SCREEN_HEIGHT {
+        for column in 0..SCREEN_WIDTH as usize {
+            pixels.push(if display.is_lit(column, row) { 255 } else { 0 });
+        }
+    }
+
+    pixels
+}
+
+// Three bytes per pixel, each CHIP-8 pixel drawn as a `scale` sized square
+pub fn scaled_pixels(display: &Display, palette: &Palette, scale: usize) -> Vec<u8> {
+    let width = SCREEN_WIDTH as usize * scale;
+    let mut pixels = Vec::with_capacity(width * SCREEN_HEIGHT * scale * 3);
+
+    for y in 0..SCREEN_HEIGHT * scale {
+        for x in 0..width {
+            let (r, g, b) = palette.color(display.is_lit(x / scale, y / scale));
+            pixels.extend_from_slice(&[r, g, b]);
+        }
+    }
+
+    pixels
+}
+
+pub fn save_native(path: &str, display: &Display) -> Result<(), String> {
+    write_png(
+        path,
+        SCREEN_WIDTH as u32,
+        SCREEN_HEIGHT as u32,
+        png::ColorType::Grayscale,
+        &native_pixels(display),
+    )
+}
+
+pub fn save_scaled(
+    path: &str,
+    display: &Display,
+    palette: &Palette,
+    scale: usize,
+) -> Result<(), String> {
+    write_png(
+        path,
+        (SCREEN_WIDTH as usize * scale) as u32,
+        (SCREEN_HEIGHT * scale) as u32,
+        png::ColorType::Rgb,
+        &scaled_pixels(display, palette, scale),
+    )
+}
+
+pub(crate) fn write_png(
+    path: &str,
+    width: u32,
+    height: u32,
+    color: png::ColorType,
+    pixels: &[u8],
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(pixels).map_err(|e| e.to_string())
+}
@@ -0,0 +1,355 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::rc::Rc;
+
+use rhai::{Dynamic, Engine, FnPtr, FuncArgs, AST, INT};
+
+use crate::core::access::{memory_access, Access};
+use crate::core::chip::Chip8;
+use crate::core::state::ADDRESS_MASK;
+
+#[derive(Default)]
+struct Hooks {
+    frame: Vec<FnPtr>,
+    exec: HashMap<u16, Vec<FnPtr>>,
+    write: Vec<(u16, u16, FnPtr)>,
+}
+
+// Runs a ROM frame by frame like `Chip8::step`, calling back into a Rhai
+// script at the end of each frame, before instructions at chosen addresses
+// run and after memory is written. While the script runs, the chip lives in
+// `chip` so the functions registered on the engine can reach it.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    chip: Rc<RefCell<Chip8>>,
+    hooks: Rc<RefCell<Hooks>>,
+    frames: Rc<RefCell<INT>>,
+    ipf: u32,
+}
+
+impl Script {
+    // Runs the top level of the script, which registers its hooks
+    pub fn load(path: &str, ipf: u32, chip: &mut Chip8) -> Result<Script, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Script::compile(&source, ipf, chip)
+    }
+
+    pub fn compile(source: &str, ipf: u32, chip: &mut Chip8) -> Result<Script, String> {
+        let chip_cell = Rc::new(RefCell::new(Chip8::new()));
+        let hooks = Rc::new(RefCell::new(Hooks::default()));
+        let frames = Rc::new(RefCell::new(0));
+
+        let mut engine = Engine::new();
+        register_machine(&mut engine, &chip_cell, &frames);
+        register_hooks(&mut engine, &hooks);
+
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+
+        let script = Script {
+            engine,
+            ast,
+            chip: chip_cell,
+            hooks,
+            frames,
+            ipf,
+        };
+
+        script.with_chip(chip, |script| {
+            script
+                .engine
+                .run_ast(&script.ast)
+                .map_err(|e| e.to_string())
+        })?;
+
+        Ok(script)
+    }
+
+    // Takes the place of `Chip8::step` in the frame loop
+    pub fn run_frame(&mut self, chip: &mut Chip8) -> Result<(), String> {
+        self.with_chip(chip, |script| {
+            script.run_instructions()?;
+            script.chip.borrow_mut().end_frame();
+            *script.frames.borrow_mut() += 1;
+
+            let hooks = script.hooks.borrow().frame.clone();
+            for hook in hooks {
+                script.call(&hook, ())?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn run_instructions(&self) -> Result<(), String> {
+        for _ in 0..self.ipf {
+            if !self.chip.borrow().is_running() {
+                break;
+            }
+
+            let pc = self.chip.borrow().state().pc;
+            let hooks = self.hooks.borrow().exec.get(&pc).cloned();
+            for hook in hooks.into_iter().flatten() {
+                self.call(&hook, ())?;
+            }
+
+            // Hooks may have moved PC or I, look at the opcode only now
+            let (access, skip_rest) = {
+                let mut chip = self.chip.borrow_mut();
+                let access = memory_access(chip.peek_opcode(), chip.state().vi);
+                (access, chip.step_instruction())
+            };
+
+            if let Some((Access::Write, start, length)) = access {
+                self.call_write_hooks(start, length)?;
+            }
+
+            if skip_rest {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn call_write_hooks(&self, start: u16, length: u16) -> Result<(), String> {
+        // Writes past the end of memory wrap around to its start, like the core
+        for offset in 0..length as u32 {
+            let address = (start as u32 + offset) as u16 & ADDRESS_MASK;
+            let hooks: Vec<FnPtr> = self
+                .hooks
+                .borrow()
+                .write
+                .iter()
+                .filter(|(first, last, _)| (*first..=*last).contains(&address))
+                .map(|(_, _, hook)| hook.clone())
+                .collect();
+
+            for hook in hooks {
+                let value = self.chip.borrow().state().memory[address as usize];
+                self.call(&hook, (address as INT, value as INT))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn call(&self, hook: &FnPtr, args: impl FuncArgs) -> Result<(), String> {
+        hook.call::<Dynamic>(&self.engine, &self.ast, args)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    // Swaps the caller's chip in for the duration of `f`
+    fn with_chip<T>(
+        &self,
+        chip: &mut Chip8,
+        f: impl FnOnce(&Script) -> Result<T, String>,
+    ) -> Result<T, String> {
+        std::mem::swap(chip, &mut self.chip.borrow_mut());
+        let result = f(self);
+        std::mem::swap(chip, &mut self.chip.borrow_mut());
+        result
+    }
+}
+
+fn register_hooks(engine: &mut Engine, hooks: &Rc<RefCell<Hooks>>) {
+    let frame = hooks.clone();
+    engine.register_fn("on_frame", move |hook: FnPtr| {
+        frame.borrow_mut().frame.push(hook);
+    });
+
+    let exec = hooks.clone();
+    engine.register_fn("on_exec", move |address: INT, hook: FnPtr| {
+        let address = address as u16 & 0xFFF;
+        exec.borrow_mut()
+            .exec
+            .entry(address)
+            .or_default()
+            .push(hook);
+    });
+
+    let write = hooks.clone();
+    engine.register_fn("on_write", move |address: INT, hook: FnPtr| {
+        let address = address as u16 & 0xFFF;
+        write.borrow_mut().write.push((address, address, hook));
+    });
+
+    let write = hooks.clone();
+    engine.register_fn("on_write", move |first: INT, last: INT, hook: FnPtr| {
+        let range = (first as u16 & 0xFFF, last as u16 & 0xFFF);
+        write.borrow_mut().write.push((range.0, range.1, hook));
+    });
+}
+
+// Values are masked to what the hardware can hold rather than rejected
+fn register_machine(engine: &mut Engine, chip: &Rc<RefCell<Chip8>>, frames: &Rc<RefCell<INT>>) {
+    let c = chip.clone();
+    engine.register_fn("reg", move |x: INT| {
+        c.borrow().state().registers[x as usize & 0xF] as INT
+    });
+
+    let c = chip.clone();
+    engine.register_fn("set_reg", move |x: INT, value: INT| {
+        c.borrow_mut().state_mut().registers[x as usize & 0xF] = value as u8;
+    });
+
+    let c = chip.clone();
+    engine.register_fn("index", move || c.borrow().state().vi as INT);
+
+    let c = chip.clone();
+    engine.register_fn("set_index", move |value: INT| {
+        c.borrow_mut().state_mut().vi = value as u16 & ADDRESS_MASK;
+    });
+
+    let c = chip.clone();
+    engine.register_fn("pc", move || c.borrow().state().pc as INT);
+
+    let c = chip.clone();
+    engine.register_fn("set_pc", move |value: INT| {
+        c.borrow_mut().state_mut().pc = value as u16 & ADDRESS_MASK;
+    });
+
+    let c = chip.clone();
+    engine.register_fn("delay_timer", move || c.borrow().state().delay_timer as INT);
+
+    let c = chip.clone();
+    engine.register_fn("set_delay_timer", move |value: INT| {
+        c.borrow_mut().state_mut().delay_timer = value as u8;
+    });
+
+    let c = chip.clone();
+    engine.register_fn("sound_timer", move || c.borrow().state().sound_timer as INT);
+
+    let c = chip.clone();
+    engine.register_fn("set_sound_timer", move |value: INT| {
+        c.borrow_mut().state_mut().sound_timer = value as u8;
+    });
+
+    let c = chip.clone();
+    engine.register_fn("peek", move |address: INT| {
+        c.borrow().state().memory[address as usize & 0xFFF] as INT
+    });
+
+    let c = chip.clone();
+    engine.register_fn("poke", move |address: INT, value: INT| {
+        c.borrow_mut().state_mut().memory[address as usize & 0xFFF] = value as u8;
+    });
+
+    let c = chip.clone();
+    engine.register_fn("press", move |key: INT| {
+        c.borrow_mut().keypad().press(key as u8 & 0xF);
+    });
+
+    let c = chip.clone();
+    engine.register_fn("release", move |key: INT| {
+        c.borrow_mut().keypad().release(key as u8 & 0xF);
+    });
+
+    let c = chip.clone();
+    engine.register_fn("is_pressed", move |key: INT| {
+        c.borrow_mut().keypad().is_pressed(key as u8)
+    });
+
+    let c = chip.clone();
+    engine.register_fn("is_lit", move |x: INT, y: INT| {
+        c.borrow()
+            .display()
+            .is_lit(x as usize % 64, y as usize % 32)
+    });
+
+    let c = chip.clone();
+    engine.register_fn("stop", move || c.borrow_mut().stop());
+
+    let f = frames.clone();
+    engine.register_fn("frame", move || *f.borrow());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chip(program: &[u8]) -> Chip8 {
+        let mut chip = Chip8::new();
+        chip.load_rom(program).unwrap();
+        chip
+    }
+
+    #[test]
+    fn calls_frame_hooks_after_each_frame() {
+        // 200: ADD V0, 1  202: JP 200
+        let mut chip = chip(&[0x70, 0x01, 0x12, 0x00]);
+        let mut script = Script::compile(
+            "on_frame(|| { if frame() == 2 { set_reg(1, reg(0)); } });",
+            4,
+            &mut chip,
+        )
+        .unwrap();
+
+        script.run_frame(&mut chip).unwrap();
+        script.run_frame(&mut chip).unwrap();
+
+        assert_eq!(chip.state().registers[1], 4);
+    }
+
+    #[test]
+    fn calls_exec_hooks_before_the_instruction() {
+        // 200: LD V0, 1  202: LD V1, 2  204: JP 204
+        let mut chip = chip(&[0x60, 0x01, 0x61, 0x02, 0x12, 0x04]);
+        let mut script = Script::compile(
+            "on_exec(0x202, || { set_reg(2, reg(0) + reg(1)); set_pc(0x204); });",
+            4,
+            &mut chip,
+        )
+        .unwrap();
+
+        script.run_frame(&mut chip).unwrap();
+
+        assert_eq!(chip.state().registers[2], 1);
+        assert_eq!(chip.state().registers[1], 0);
+    }
+
+    #[test]
+    fn calls_write_hooks_with_the_new_value() {
+        // 200: LD I, 0x300  202: LD V0, 123  204: LD B, V0  206: JP 206
+        let mut chip = chip(&[0xA3, 0x00, 0x60, 0x7B, 0xF0, 0x33, 0x12, 0x06]);
+        let mut script = Script::compile(
+            "on_write(0x301, 0x302, |address, value| { poke(0x400 + address - 0x301, value); });",
+            4,
+            &mut chip,
+        )
+        .unwrap();
+
+        script.run_frame(&mut chip).unwrap();
+
+        assert_eq!(chip.state().memory[0x400], 2);
+        assert_eq!(chip.state().memory[0x401], 3);
+    }
+
+    #[test]
+    fn calls_write_hooks_around_the_end_of_memory() {
+        // 200: LD I, 0xFFE  202: LD V3, 7  204: LD [I], V3  206: JP 206
+        let mut chip = chip(&[0xAF, 0xFE, 0x63, 0x07, 0xF3, 0x55, 0x12, 0x06]);
+        let mut script = Script::compile(
+            "on_write(0x000, 0xFFF, |address, value| { poke(0x400 + address % 0x10, value); });",
+            4,
+            &mut chip,
+        )
+        .unwrap();
+
+        script.run_frame(&mut chip).unwrap();
+
+        assert_eq!(chip.state().memory[0x401], 7);
+    }
+
+    #[test]
+    fn surfaces_script_errors() {
+        let mut chip = chip(&[0x12, 0x00]);
+        let mut script =
+            Script::compile(r#"on_frame(|| throw "score overflowed");"#, 1, &mut chip).unwrap();
+
+        let error = script.run_frame(&mut chip).unwrap_err();
+        assert!(error.contains("score overflowed"));
+    }
+}
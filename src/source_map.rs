@@ -0,0 +1,76 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+// Maps assembler source lines to the address of the instruction they emit.
+// The file holds one `<line> <address>` pair per line, addresses in hex:
+//
+//     # main.8o
+//     12 200
+//     13 202
+pub struct SourceMap {
+    lines: BTreeMap<u32, u16>,
+    addresses: HashMap<u16, u32>,
+}
+
+impl SourceMap {
+    pub fn load(path: &str) -> Result<SourceMap, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        SourceMap::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<SourceMap, String> {
+        let mut lines = BTreeMap::new();
+        let mut addresses = HashMap::new();
+
+        for (number, entry) in text.lines().enumerate() {
+            let entry = entry.split('#').next().unwrap_or("").trim();
+            if entry.is_empty() {
+                continue;
+            }
+
+            let invalid = || format!("invalid source map entry on line {}", number + 1);
+
+            let (line, address) = entry.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let line: u32 = line.parse().map_err(|_| invalid())?;
+            let address = u16::from_str_radix(address.trim().trim_start_matches("0x"), 16)
+                .map_err(|_| invalid())?;
+
+            lines.insert(line, address);
+            addresses.entry(address).or_insert(line);
+        }
+
+        Ok(SourceMap { lines, addresses })
+    }
+
+    // Lines without code snap to the next line that has some, like most debuggers do
+    pub fn resolve(&self, line: u32) -> Option<(u32, u16)> {
+        self.lines
+            .range(line..)
+            .next()
+            .map(|(line, address)| (*line, *address))
+    }
+
+    pub fn line_of(&self, address: u16) -> Option<u32> {
+        self.addresses.get(&address).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_lines_to_addresses() {
+        let map = SourceMap::parse("# comment\n3 200\n5 0x202\n\n9 204 # loop\n").unwrap();
+
+        assert_eq!(map.resolve(3), Some((3, 0x200)));
+        assert_eq!(map.resolve(4), Some((5, 0x202)));
+        assert_eq!(map.resolve(10), None);
+        assert_eq!(map.line_of(0x204), Some(9));
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        assert!(SourceMap::parse("3 200\nfoo\n").is_err());
+    }
+}
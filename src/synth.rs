@@ -0,0 +1,209 @@
+use std::f32::consts::PI;
+
+pub const SAMPLE_RATE: u32 = 44100;
+const FPS: u32 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Pulse(f32),
+    Triangle,
+    Sine,
+    Noise,
+}
+
+impl Waveform {
+    // Accepts "square", "triangle", "sine", "noise" or "pulse:<duty>" with duty in 0..1
+    pub fn parse(value: &str) -> Result<Waveform, String> {
+        match value.split_once(':') {
+            Some(("pulse", duty)) => {
+                let duty = duty
+                    .parse::<f32>()
+                    .map_err(|_| format!("invalid pulse width '{}'", duty))?;
+
+                if duty <= 0.0 || duty >= 1.0 {
+                    return Err(format!("pulse width {} must be between 0 and 1", duty));
+                }
+
+                Ok(Waveform::Pulse(duty))
+            }
+            None if value == "square" => Ok(Waveform::Square),
+            None if value == "pulse" => Ok(Waveform::Pulse(0.25)),
+            None if value == "triangle" => Ok(Waveform::Triangle),
+            None if value == "sine" => Ok(Waveform::Sine),
+            None if value == "noise" => Ok(Waveform::Noise),
+            _ => Err(format!("unknown waveform '{}'", value)),
+        }
+    }
+}
+
+// Generates the buzzer from the emulated sound timer, one frame of samples at a time.
+// The envelope ramps volume in and out so notes never start or stop mid wave.
+pub struct Synth {
+    pub waveform: Waveform,
+    pub frequency: f32,
+    pub volume: f32,
+    attack: f32,
+    release: f32,
+    phase: f32,
+    envelope: f32,
+    noise: u32,
+    noise_value: f32,
+}
+
+impl Synth {
+    pub fn new() -> Synth {
+        let mut synth = Synth {
+            waveform: Waveform::Square,
+            frequency: 440.0,
+            volume: 0.05,
+            attack: 0.0,
+            release: 0.0,
+            phase: 0.0,
+            envelope: 0.0,
+            noise: 0xACE1,
+            noise_value: 1.0,
+        };
+        synth.set_envelope(2.0, 10.0);
+        synth
+    }
+
+    // Attack and release lengths in milliseconds
+    pub fn set_envelope(&mut self, attack_ms: f32, release_ms: f32) {
+        let step = |ms: f32| 1.0 / (ms / 1000.0 * SAMPLE_RATE as f32).max(1.0);
+
+        self.attack = step(attack_ms);
+        self.release = step(release_ms);
+    }
+
+    pub fn frame(&mut self, gate: bool) -> Vec<f32> {
+        let mut samples = vec![0.0; (SAMPLE_RATE / FPS) as usize];
+        self.fill(gate, &mut samples);
+        samples
+    }
+
+    pub fn fill(&mut self, gate: bool, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            self.envelope = if gate {
+                (self.envelope + self.attack).min(1.0)
+            } else {
+                (self.envelope - self.release).max(0.0)
+            };
+
+            if self.envelope == 0.0 {
+                *sample = 0.0;
+                self.phase = 0.0;
+                continue;
+            }
+
+            *sample = self.oscillator() * self.volume * self.envelope;
+            self.advance();
+        }
+    }
+
+    fn oscillator(&self) -> f32 {
+        match self.waveform {
+            Waveform::Square => pulse(self.phase, 0.5),
+            Waveform::Pulse(duty) => pulse(self.phase, duty),
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Sine => (self.phase * 2.0 * PI).sin(),
+            Waveform::Noise => self.noise_value,
+        }
+    }
+
+    fn advance(&mut self) {
+        self.phase += self.frequency / SAMPLE_RATE as f32;
+
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+
+            // Noise holds one random level per period so the frequency sets its pitch
+            self.noise ^= self.noise << 13;
+            self.noise ^= self.noise >> 17;
+            self.noise ^= self.noise << 5;
+            self.noise_value = if self.noise & 1 == 0 { 1.0 } else { -1.0 };
+        }
+    }
+}
+
+impl Default for Synth {
+    fn default() -> Self {
+        Synth::new()
+    }
+}
+
+fn pulse(phase: f32, duty: f32) -> f32 {
+    if phase < duty {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::chip::Chip8;
+
+    // Without an envelope every sample is at full volume
+    fn synth(waveform: Waveform) -> Synth {
+        let mut synth = Synth::new();
+        synth.waveform = waveform;
+        synth.frequency = 441.0;
+        synth.volume = 0.5;
+        synth.set_envelope(0.0, 0.0);
+        synth
+    }
+
+    fn rising_edges(samples: &[f32]) -> Vec<usize> {
+        (1..samples.len())
+            .filter(|&i| samples[i - 1] < 0.0 && samples[i] >= 0.0)
+            .collect()
+    }
+
+    #[test]
+    fn plays_the_set_pitch() {
+        for waveform in [Waveform::Square, Waveform::Pulse(0.25), Waveform::Sine] {
+            let samples = synth(waveform).frame(true);
+            let edges = rising_edges(&samples);
+
+            // 441 Hz is one period every 100 samples
+            assert_eq!(edges.len(), 7, "{:?}", waveform);
+            for pair in edges.windows(2) {
+                assert!((99..=101).contains(&(pair[1] - pair[0])), "{:?}", waveform);
+            }
+        }
+    }
+
+    #[test]
+    fn scales_by_the_volume() {
+        let mut synth = synth(Waveform::Square);
+        let peak = |samples: Vec<f32>| samples.iter().fold(0.0f32, |max, s| max.max(s.abs()));
+
+        assert_eq!(peak(synth.frame(true)), 0.5);
+
+        synth.volume = 0.1;
+        assert_eq!(peak(synth.frame(true)), 0.1);
+    }
+
+    #[test]
+    fn is_silent_while_the_sound_timer_is_zero() {
+        let mut chip = Chip8::new();
+        chip.load_rom(&[0x12, 0x00]).unwrap();
+        chip.step(10);
+        assert!(!chip.is_sound_playing());
+
+        let mut synth = Synth::new();
+        assert!(synth
+            .frame(chip.is_sound_playing())
+            .iter()
+            .all(|&s| s == 0.0));
+
+        // A note that ends releases back to silence within 10 ms
+        synth.frame(true);
+        let tail = synth.frame(chip.is_sound_playing());
+        let release = SAMPLE_RATE as usize / 100;
+        assert!(tail[0] != 0.0);
+        assert!(tail[release..].iter().all(|&s| s == 0.0));
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::io::{stdout, Stdout, Write};
+use std::time::Duration;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use chip_8::core::chip::Chip8;
+use chip_8::core::display::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip_8::palette::{Palette, Rgb};
+
+use crate::frontend::Frontend;
+
+// Most terminals only report presses, so a key stays down this many frames after its last repeat
+const HOLD_FRAMES: u8 = 8;
+
+pub struct Terminal {
+    out: Stdout,
+    palette: Palette,
+    held: [u8; 16],
+    reports_release: bool,
+    was_beeping: bool,
+    drawn: bool,
+}
+
+impl Terminal {
+    pub fn new(palette: Palette) -> Result<Terminal, String> {
+        let mut out = stdout();
+
+        terminal::enable_raw_mode().map_err(|e| e.to_string())?;
+        execute!(out, EnterAlternateScreen, Hide, Clear(ClearType::All))
+            .map_err(|e| e.to_string())?;
+
+        // Terminals speaking the kitty keyboard protocol tell us when keys go up
+        let reports_release = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if reports_release {
+            execute!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )
+            .map_err(|e| e.to_string())?;
+        }
+
+        Ok(Terminal {
+            out,
+            palette,
+            held: [0; 16],
+            reports_release,
+            was_beeping: false,
+            drawn: false,
+        })
+    }
+
+    fn handle_key(&mut self, key: KeyEvent, chip: &mut Chip8) {
+        let quit = key.code == KeyCode::Esc
+            || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL));
+
+        if quit {
+            chip.stop();
+            return;
+        }
+
+        let pad_key = match key.code {
+            KeyCode::Char(c) => char_to_u8(c.to_ascii_lowercase()),
+            _ => None,
+        };
+
+        if let Some(pad_key) = pad_key {
+            match key.kind {
+                KeyEventKind::Press | KeyEventKind::Repeat => {
+                    chip.keypad().press(pad_key);
+                    self.held[pad_key as usize] = HOLD_FRAMES;
+                }
+                KeyEventKind::Release => {
+                    chip.keypad().release(pad_key);
+                    self.held[pad_key as usize] = 0;
+                }
+            }
+        }
+    }
+
+    fn render(&mut self, chip: &Chip8) -> std::io::Result<()> {
+        let display = chip.display();
+        let mut colors: Option<(Rgb, Rgb)> = None;
+
+        // Each text row holds two pixel rows: the upper half block takes the
+        // foreground color and the cell background shows through below it
+        for text_row in 0..SCREEN_HEIGHT / 2 {
+            queue!(self.out, MoveTo(0, text_row as u16))?;
+
+            for column in 0..SCREEN_WIDTH as usize {
+                let top = self.palette.color(display.is_lit(column, text_row * 2));
+                let bottom = self.palette.color(display.is_lit(column, text_row * 2 + 1));
+
+                if colors != Some((top, bottom)) {
+                    queue!(
+                        self.out,
+                        SetForegroundColor(to_color(top)),
+                        SetBackgroundColor(to_color(bottom))
+                    )?;
+                    colors = Some((top, bottom));
+                }
+
+                queue!(self.out, Print('▀'))?;
+            }
+        }
+
+        queue!(self.out, ResetColor)
+    }
+
+    fn render_status(&mut self, chip: &Chip8) -> std::io::Result<()> {
+        let state = chip.state();
+
+        queue!(
+            self.out,
+            MoveTo(0, (SCREEN_HEIGHT / 2) as u16),
+            Clear(ClearType::CurrentLine),
+            Print(format!(
+                "PC {:03X}  I {:03X}  DT {:3}  ST {:3}  Esc to quit",
+                state.pc, state.vi, state.delay_timer, state.sound_timer
+            ))
+        )
+    }
+}
+
+impl Frontend for Terminal {
+    fn handle_events(&mut self, chip: &mut Chip8) {
+        while let Ok(true) = event::poll(Duration::ZERO) {
+            if let Ok(Event::Key(key)) = event::read() {
+                self.handle_key(key, chip);
+            }
+        }
+
+        if !self.reports_release {
+            for (key, frames) in self.held.iter_mut().enumerate() {
+                if *frames > 0 {
+                    *frames -= 1;
+
+                    if *frames == 0 {
+                        chip.keypad().release(key as u8);
+                    }
+                }
+            }
+        }
+    }
+
+    fn update(&mut self, chip: &mut Chip8, _samples: &[f32]) {
+        // There is usually no audio over SSH, ring the bell when the buzzer starts
+        if chip.is_sound_playing() && !self.was_beeping {
+            let _ = queue!(self.out, Print('\x07'));
+        }
+        self.was_beeping = chip.is_sound_playing();
+
+        if chip.take_redraw() || !self.drawn {
+            let _ = self.render(chip);
+            self.drawn = true;
+        }
+
+        let _ = self.render_status(chip);
+        let _ = self.out.flush();
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.reports_release {
+            let _ = execute!(self.out, PopKeyboardEnhancementFlags);
+        }
+
+        let _ = execute!(self.out, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn to_color((r, g, b): Rgb) -> Color {
+    Color::Rgb { r, g, b }
+}
+
+fn char_to_u8(key: char) -> Option<u8> {
+    let key_mapping: HashMap<char, u8> = HashMap::from([
+        ('1', 0x1),
+        ('2', 0x2),
+        ('3', 0x3),
+        ('4', 0xC),
+        ('q', 0x4),
+        ('w', 0x5),
+        ('e', 0x6),
+        ('r', 0xD),
+        ('a', 0x7),
+        ('s', 0x8),
+        ('d', 0x9),
+        ('f', 0xE),
+        ('z', 0xA),
+        ('x', 0x0),
+        ('c', 0xB),
+        ('v', 0xF),
+    ]);
+
+    key_mapping.get(&key).copied()
+}
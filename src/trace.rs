@@ -0,0 +1,174 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::core::state::ChipState;
+use crate::disasm::disassemble;
+
+// Writes one line per executed instruction, e.g.
+// 00000012 0204 6A02 LD VA, 0x02          VA=02 I=0000 DT=00 ST=00
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    range: Option<(u16, u16)>,
+    classes: Option<u16>,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write + Send>) -> Tracer {
+        Tracer {
+            out,
+            range: None,
+            classes: None,
+        }
+    }
+
+    // "-" traces to stdout
+    pub fn create(path: &str) -> Result<Tracer, String> {
+        let out: Box<dyn Write + Send> = if path == "-" {
+            Box::new(BufWriter::new(io::stdout()))
+        } else {
+            Box::new(BufWriter::new(
+                File::create(path).map_err(|e| e.to_string())?,
+            ))
+        };
+
+        Ok(Tracer::new(out))
+    }
+
+    // Only trace instructions between these addresses, both included
+    pub fn set_range(&mut self, start: u16, end: u16) {
+        self.range = Some((start, end));
+    }
+
+    // Only trace instructions whose first nibble is in the list
+    pub fn set_classes(&mut self, nibbles: &[u8]) {
+        self.classes = Some(nibbles.iter().fold(0, |mask, n| mask | 1 << (n & 0xF)));
+    }
+
+    pub fn wants(&self, pc: u16, code: u16) -> bool {
+        let in_range = match self.range {
+            Some((start, end)) => (start..=end).contains(&pc),
+            None => true,
+        };
+        let in_class = match self.classes {
+            Some(mask) => mask & (1 << (code >> 12)) != 0,
+            None => true,
+        };
+
+        in_range && in_class
+    }
+
+    pub fn record(
+        &mut self,
+        cycle: u64,
+        pc: u16,
+        code: u16,
+        registers_before: &[u8; 16],
+        state: &ChipState,
+    ) {
+        let mut line = format!(
+            "{:08} {:04X} {:04X} {:<20}",
+            cycle,
+            pc,
+            code,
+            disassemble(code)
+        );
+
+        for (index, (before, after)) in registers_before.iter().zip(state.registers).enumerate() {
+            if *before != after {
+                line.push_str(&format!(" V{:X}={:02X}", index, after));
+            }
+        }
+
+        line.push_str(&format!(
+            " I={:04X} DT={:02X} ST={:02X}",
+            state.vi, state.delay_timer, state.sound_timer
+        ));
+
+        // A broken pipe should not take the emulator down with it
+        let _ = writeln!(self.out, "{}", line);
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::core::chip::Chip8;
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // 200: LD VA, 2  202: LD I, 300  204: ADD VA, 3  206: LD DT, VA  208: JP 208
+    const ROM: [u8; 10] = [0x6A, 0x02, 0xA3, 0x00, 0x7A, 0x03, 0xFA, 0x15, 0x12, 0x08];
+
+    fn trace(setup: impl Fn(&mut Tracer)) -> Vec<String> {
+        let out = Shared::default();
+        let mut tracer = Tracer::new(Box::new(out.clone()));
+        setup(&mut tracer);
+
+        let mut chip = Chip8::new();
+        chip.load_rom(&ROM).unwrap();
+        chip.set_tracer(Some(tracer));
+        for _ in 0..6 {
+            chip.step_instruction();
+        }
+        chip.set_tracer(None);
+
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn shows_changed_registers_and_timers() {
+        assert_eq!(
+            trace(|_| {}),
+            [
+                "00000000 0200 6A02 LD VA, 0x02          VA=02 I=0000 DT=00 ST=00",
+                "00000001 0202 A300 LD I, 0x300          I=0300 DT=00 ST=00",
+                "00000002 0204 7A03 ADD VA, 0x03         VA=05 I=0300 DT=00 ST=00",
+                "00000003 0206 FA15 LD DT, VA            I=0300 DT=05 ST=00",
+                "00000004 0208 1208 JP 0x208             I=0300 DT=05 ST=00",
+                "00000005 0208 1208 JP 0x208             I=0300 DT=05 ST=00",
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_by_address_and_class() {
+        let lines = trace(|tracer| tracer.set_range(0x202, 0x206));
+        let pcs: Vec<&str> = lines.iter().map(|line| &line[9..13]).collect();
+        assert_eq!(pcs, ["0202", "0204", "0206"]);
+
+        let lines = trace(|tracer| tracer.set_classes(&[0x7, 0xF]));
+        let codes: Vec<&str> = lines.iter().map(|line| &line[14..18]).collect();
+        assert_eq!(codes, ["7A03", "FA15"]);
+
+        let lines = trace(|tracer| {
+            tracer.set_range(0x200, 0x204);
+            tracer.set_classes(&[0x6, 0xF]);
+        });
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("00000000 0200 6A02"));
+    }
+}
@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+use crate::synth::SAMPLE_RATE;
+
+// Writes the buzzer as mono 16-bit PCM
+pub struct WavWriter {
+    file: BufWriter<File>,
+    samples: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &str) -> Result<WavWriter, String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+
+        let mut writer = WavWriter {
+            file: BufWriter::new(file),
+            samples: 0,
+        };
+        writer.write_header().map_err(|e| e.to_string())?;
+
+        Ok(writer)
+    }
+
+    // Takes the samples the synth produced for each frame
+    pub fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        for value in samples {
+            let sample = (value.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file
+                .write_all(&sample.to_le_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+
+        self.samples += samples.len() as u32;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        self.write_header().map_err(|e| e.to_string())?;
+        self.file.flush().map_err(|e| e.to_string())
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        let data_size = self.samples * 2;
+
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(b"RIFF")?;
+        self.file.write_all(&(36 + data_size).to_le_bytes())?;
+        self.file.write_all(b"WAVE")?;
+
+        self.file.write_all(b"fmt ")?;
+        self.file.write_all(&16u32.to_le_bytes())?;
+        self.file.write_all(&1u16.to_le_bytes())?; // PCM
+        self.file.write_all(&1u16.to_le_bytes())?; // Mono
+        self.file.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        self.file.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+        self.file.write_all(&2u16.to_le_bytes())?;
+        self.file.write_all(&16u16.to_le_bytes())?;
+
+        self.file.write_all(b"data")?;
+        self.file.write_all(&data_size.to_le_bytes())?;
+        self.file.seek(SeekFrom::End(0))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+    use crate::core::chip::Chip8;
+    use crate::synth::Synth;
+
+    const FRAME_SAMPLES: usize = SAMPLE_RATE as usize / 60;
+
+    fn read_u32(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn records_the_buzzer_for_as_long_as_the_sound_timer_runs() {
+        // LD V0, 3  LD ST, V0  JP 204
+        let rom = [0x60, 0x03, 0xF0, 0x18, 0x12, 0x04];
+        let mut chip = Chip8::new();
+        chip.load_rom(&rom).unwrap();
+
+        let path = env::temp_dir().join(format!("chip_8_wav_{}.wav", std::process::id()));
+        let mut wav = WavWriter::create(path.to_str().unwrap()).unwrap();
+        let mut synth = Synth::new();
+        let mut playing = Vec::new();
+
+        for _ in 0..6 {
+            chip.step(10);
+            playing.push(chip.is_sound_playing());
+            wav.write(&synth.frame(chip.is_sound_playing())).unwrap();
+        }
+        wav.finish().unwrap();
+
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(playing, [true, true, true, false, false, false]);
+
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(read_u32(&data, 24), SAMPLE_RATE);
+        assert_eq!(&data[36..40], b"data");
+
+        let samples: Vec<i16> = data[44..]
+            .chunks(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        assert_eq!(read_u32(&data, 40) as usize, samples.len() * 2);
+        assert_eq!(read_u32(&data, 4) as usize, 36 + samples.len() * 2);
+        assert_eq!(samples.len(), 6 * FRAME_SAMPLES);
+
+        let frames: Vec<&[i16]> = samples.chunks(FRAME_SAMPLES).collect();
+
+        // The tone starts with the first sample of the first frame the timer runs
+        assert_ne!(frames[0][0], 0);
+        assert!(frames[..3]
+            .iter()
+            .all(|frame| frame.iter().any(|&s| s != 0)));
+
+        // and fades out within the frame after it stops
+        assert_ne!(frames[3][0], 0);
+        assert_eq!(frames[3][FRAME_SAMPLES - 1], 0);
+        assert!(frames[4..]
+            .iter()
+            .all(|frame| frame.iter().all(|&s| s == 0)));
+    }
+}
@@ -0,0 +1,158 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::EventPump;
+
+use chip_8::core::chip::Chip8;
+use chip_8::palette::Palette;
+use chip_8::recorder::Recorder;
+use chip_8::screenshot;
+
+use crate::audio::Speaker;
+use crate::frontend::Frontend;
+use crate::input::scancode_to_u8;
+use crate::screen::{Screen, PIXEL_SCALE};
+
+pub struct Window {
+    display: Screen,
+    speaker: Speaker,
+    event_pump: EventPump,
+    recorder: Option<Recorder>,
+    record_scale: usize,
+}
+
+impl Window {
+    pub fn new(palette: Palette, record_scale: usize) -> Window {
+        // Initialize SDL2 and Event Pump
+        let sdl_context = sdl2::init().unwrap();
+        let event_pump = sdl_context.event_pump().unwrap();
+
+        // Initialize SDL2 Audio Subsystem
+        let audio_subsystem = sdl_context.audio().unwrap();
+
+        Window {
+            display: Screen::new(&sdl_context, palette),
+            speaker: Speaker::new(&audio_subsystem),
+            event_pump,
+            recorder: None,
+            record_scale,
+        }
+    }
+
+    // Returns whether the key was used by the frontend
+    fn handle_hotkey(&mut self, keycode: Keycode, chip: &mut Chip8) -> bool {
+        let filters = &mut self.display.filters;
+
+        match keycode {
+            Keycode::F1 => filters.phosphor = !filters.phosphor,
+            Keycode::F2 => filters.blend = !filters.blend,
+            Keycode::F3 => filters.vblank = !filters.vblank,
+            Keycode::F4 => self.speaker.muted = !self.speaker.muted,
+            Keycode::F11 => self.toggle_recording(),
+            Keycode::F12 => self.save_screenshot(chip),
+            _ => return false,
+        }
+
+        chip.request_redraw();
+
+        true
+    }
+
+    fn save_screenshot(&self, chip: &Chip8) {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let path = format!("screenshot-{}.png", seconds);
+
+        match screenshot::save_scaled(&path, chip.display(), &self.display.palette, PIXEL_SCALE) {
+            Ok(()) => eprintln!("Saved screenshot to {}", path),
+            Err(e) => eprintln!("Could not save screenshot: {}", e),
+        }
+    }
+
+    fn toggle_recording(&mut self) {
+        if self.recorder.is_some() {
+            self.stop_recording();
+            return;
+        }
+
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let path = format!("recording-{}.gif", seconds);
+
+        match Recorder::new(&path, self.display.palette.clone(), self.record_scale) {
+            Ok(recorder) => {
+                eprintln!("Recording to {}", path);
+                self.recorder = Some(recorder);
+            }
+            Err(e) => eprintln!("Could not start recording: {}", e),
+        }
+    }
+
+    fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            match recorder.save() {
+                Ok(()) => eprintln!("Saved {} recorded frames", recorder.frame_count()),
+                Err(e) => eprintln!("Could not save recording: {}", e),
+            }
+        }
+    }
+}
+
+impl Frontend for Window {
+    fn handle_events(&mut self, chip: &mut Chip8) {
+        // Check events, if exit then set running to false
+        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+
+        for event in events {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => chip.stop(),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat: false,
+                    ..
+                } if self.handle_hotkey(keycode, chip) => {}
+                Event::KeyDown {
+                    scancode: Some(scancode),
+                    ..
+                } => {
+                    if let Some(key) = scancode_to_u8(scancode) {
+                        chip.keypad().press(key);
+                    }
+                }
+                Event::KeyUp {
+                    scancode: Some(scancode),
+                    ..
+                } => {
+                    if let Some(key) = scancode_to_u8(scancode) {
+                        chip.keypad().release(key);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn update(&mut self, chip: &mut Chip8, samples: &[f32]) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.capture(chip.display());
+        }
+
+        self.speaker.play(samples);
+
+        // Render frame
+        self.display.update(chip);
+    }
+
+    fn finish(&mut self) {
+        self.stop_recording();
+    }
+}
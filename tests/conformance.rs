@@ -0,0 +1,129 @@
+// Boots the bundled test ROMs headlessly and compares the final framebuffer
+// against golden images in tests/golden. Run with UPDATE_GOLDEN=1 to rewrite them.
+
+use std::env;
+use std::fs::File;
+use std::path::PathBuf;
+
+use chip_8::core::chip::Chip8;
+use chip_8::core::display::{Display, SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip_8::core::quirks::Quirks;
+use chip_8::screenshot;
+
+const PRESETS: [(&str, u8); 3] = [("chip8", 1), ("schip", 2), ("xochip", 3)];
+
+// Timendus' test suite reads the platform from here instead of showing a menu
+const PLATFORM_ADDRESS: usize = 0x1FF;
+
+fn project_path(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+fn run_rom(rom: &str, preset: &str, platform: u8, frames: u32, ipf: u32) -> Display {
+    let mut chip = Chip8::with_quirks(Quirks::from_name(preset).unwrap());
+    chip.read_rom(&project_path(&format!("roms/{}", rom)).to_string_lossy())
+        .unwrap();
+    chip.state_mut().memory[PLATFORM_ADDRESS] = platform;
+
+    for _ in 0..frames {
+        chip.step(ipf);
+    }
+
+    Display {
+        screen_memory: chip.display().screen_memory,
+    }
+}
+
+fn load_golden(path: &PathBuf) -> Result<Display, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut reader = png::Decoder::new(file)
+        .read_info()
+        .map_err(|e| e.to_string())?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut pixels).map_err(|e| e.to_string())?;
+
+    let mut display = Display::new();
+    for (row, line) in display.screen_memory.iter_mut().enumerate() {
+        for column in 0..SCREEN_WIDTH as usize {
+            if pixels[row * SCREEN_WIDTH as usize + column] != 0 {
+                *line |= 1u64 << (SCREEN_WIDTH as usize - 1 - column);
+            }
+        }
+    }
+
+    Ok(display)
+}
+
+// '#' and '.' match, '+' is lit but should not be, '-' should be lit but is not
+fn diff(expected: &Display, actual: &Display) -> Option<String> {
+    let mut mismatches = 0;
+    let mut picture = String::new();
+
+    for row in 0..SCREEN_HEIGHT {
+        for column in 0..SCREEN_WIDTH as usize {
+            picture.push(
+                match (expected.is_lit(column, row), actual.is_lit(column, row)) {
+                    (true, true) => '#',
+                    (false, false) => '.',
+                    (false, true) => '+',
+                    (true, false) => '-',
+                },
+            );
+        }
+        picture.push('\n');
+
+        mismatches += (expected.screen_memory[row] ^ actual.screen_memory[row]).count_ones();
+    }
+
+    if mismatches == 0 {
+        None
+    } else {
+        Some(format!("{} mismatched pixels\n{}", mismatches, picture))
+    }
+}
+
+fn check_rom(rom: &str, frames: u32, ipf: u32) {
+    let mut failures = vec![];
+    let name = rom.split('.').next().unwrap();
+
+    for (preset, platform) in PRESETS {
+        let actual = run_rom(rom, preset, platform, frames, ipf);
+        let golden = project_path(&format!("tests/golden/{}-{}.png", name, preset));
+
+        if env::var("UPDATE_GOLDEN").is_ok() {
+            screenshot::save_native(&golden.to_string_lossy(), &actual).unwrap();
+            continue;
+        }
+
+        match load_golden(&golden) {
+            Ok(expected) => {
+                if let Some(report) = diff(&expected, &actual) {
+                    failures.push(format!("{} ({}): {}", rom, preset, report));
+                }
+            }
+            Err(e) => failures.push(format!("{} ({}): {}", rom, preset, e)),
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn ibm_logo() {
+    check_rom("ibm_logo.ch8", 20, 20);
+}
+
+#[test]
+fn chip8_logo() {
+    check_rom("chip8_logo.ch8", 60, 20);
+}
+
+#[test]
+fn corax() {
+    check_rom("corax.ch8", 300, 100);
+}
+
+#[test]
+fn quirks() {
+    check_rom("quirks.ch8", 300, 1000);
+}
@@ -0,0 +1,238 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use chip_8::core::chip::Chip8;
+use chip_8::dap::DapServer;
+
+struct ChannelReader {
+    receiver: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.receiver.recv() {
+                Ok(bytes) => self.pending = bytes,
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let count = buf.len().min(self.pending.len());
+        buf[..count].copy_from_slice(&self.pending[..count]);
+        self.pending.drain(..count);
+        Ok(count)
+    }
+}
+
+struct ChannelWriter(Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.send(buf.to_vec()).unwrap();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Client {
+    input: Sender<Vec<u8>>,
+    output: Receiver<Vec<u8>>,
+    received: Vec<u8>,
+    seq: i64,
+}
+
+impl Client {
+    fn send(&mut self, command: &str, arguments: Value) -> i64 {
+        self.seq += 1;
+        let body = json!({
+            "seq": self.seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        })
+        .to_string();
+
+        let message = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        self.input.send(message.into_bytes()).unwrap();
+        self.seq
+    }
+
+    // Next complete message, if one arrived
+    fn message(&mut self) -> Option<Value> {
+        while let Ok(bytes) = self.output.try_recv() {
+            self.received.extend(bytes);
+        }
+
+        let text = String::from_utf8_lossy(&self.received).into_owned();
+        let header_end = text.find("\r\n\r\n")?;
+        let length: usize = text[..header_end]
+            .trim_start_matches("Content-Length: ")
+            .parse()
+            .unwrap();
+
+        let start = header_end + 4;
+        if self.received.len() < start + length {
+            return None;
+        }
+
+        let message = serde_json::from_slice(&self.received[start..start + length]).unwrap();
+        self.received.drain(..start + length);
+        Some(message)
+    }
+
+    // Runs frames until a message matching `wanted` arrives
+    fn wait_for(
+        &mut self,
+        dap: &mut DapServer,
+        chip: &mut Chip8,
+        wanted: impl Fn(&Value) -> bool,
+    ) -> Value {
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        loop {
+            while let Some(message) = self.message() {
+                if wanted(&message) {
+                    return message;
+                }
+            }
+
+            assert!(
+                Instant::now() < deadline,
+                "timed out waiting for the adapter"
+            );
+            dap.run_frame(chip).unwrap();
+        }
+    }
+
+    fn request(
+        &mut self,
+        dap: &mut DapServer,
+        chip: &mut Chip8,
+        command: &str,
+        arguments: Value,
+    ) -> Value {
+        let seq = self.send(command, arguments);
+        let response = self.wait_for(dap, chip, |m| m["request_seq"] == json!(seq));
+        assert_eq!(response["success"], json!(true), "{}", response);
+        response["body"].clone()
+    }
+
+    fn stopped(&mut self, dap: &mut DapServer, chip: &mut Chip8) -> String {
+        let event = self.wait_for(dap, chip, |m| m["event"] == json!("stopped"));
+        event["body"]["reason"].as_str().unwrap().to_string()
+    }
+}
+
+#[test]
+fn debugs_a_rom_through_the_adapter() {
+    // 200: LD V0, 1  202: CALL 206  204: JP 204  206: LD V1, 2  208: RET
+    let rom = [0x60, 0x01, 0x22, 0x06, 0x12, 0x04, 0x61, 0x02, 0x00, 0xEE];
+    let dir = env::temp_dir().join(format!("chip_8_dap_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let program = dir.join("test.ch8");
+    let source = dir.join("test.8o");
+    let source_map = dir.join("test.map");
+    fs::write(&program, rom).unwrap();
+    fs::write(&source, "").unwrap();
+    fs::write(&source_map, "1 200\n2 202\n3 204\n5 206\n6 208\n").unwrap();
+
+    let (input, reader) = mpsc::channel();
+    let (writer, output) = mpsc::channel();
+    let mut client = Client {
+        input,
+        output,
+        received: Vec::new(),
+        seq: 0,
+    };
+
+    client.send("initialize", json!({ "adapterID": "chip8" }));
+    client.send(
+        "launch",
+        json!({
+            "program": program,
+            "ipf": 10,
+            "source": source,
+            "sourceMap": source_map,
+            "stopOnEntry": true,
+        }),
+    );
+    client.send("configurationDone", json!({}));
+
+    let mut dap = DapServer::new(
+        ChannelReader {
+            receiver: reader,
+            pending: Vec::new(),
+        },
+        ChannelWriter(writer),
+    );
+    let launch = dap.wait_for_launch().unwrap();
+    assert_eq!(launch.ipf, 10);
+
+    let mut chip = Chip8::new();
+    chip.load_rom(&fs::read(&launch.program).unwrap()).unwrap();
+    assert_eq!(client.stopped(&mut dap, &mut chip), "entry");
+
+    let breakpoints = client.request(
+        &mut dap,
+        &mut chip,
+        "setBreakpoints",
+        json!({ "source": { "path": source }, "breakpoints": [{ "line": 4 }] }),
+    );
+    assert_eq!(breakpoints["breakpoints"][0]["line"], json!(5));
+
+    client.request(&mut dap, &mut chip, "continue", json!({ "threadId": 1 }));
+    assert_eq!(client.stopped(&mut dap, &mut chip), "breakpoint");
+
+    let trace = client.request(&mut dap, &mut chip, "stackTrace", json!({ "threadId": 1 }));
+    let frames = trace["stackFrames"].as_array().unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0]["line"], json!(5));
+    assert_eq!(frames[1]["instructionPointerReference"], json!("0x202"));
+
+    let registers = client.request(
+        &mut dap,
+        &mut chip,
+        "variables",
+        json!({ "variablesReference": 1 }),
+    );
+    assert_eq!(registers["variables"][0]["value"], json!("0x01"));
+
+    client.request(&mut dap, &mut chip, "stepOut", json!({ "threadId": 1 }));
+    assert_eq!(client.stopped(&mut dap, &mut chip), "step");
+    assert_eq!(chip.state().pc, 0x204);
+    assert_eq!(chip.state().registers[1], 2);
+
+    let listing = client.request(
+        &mut dap,
+        &mut chip,
+        "disassemble",
+        json!({ "memoryReference": "0x200", "instructionCount": 3 }),
+    );
+    assert_eq!(
+        listing["instructions"][1]["instruction"],
+        json!("CALL 0x206")
+    );
+
+    let memory = client.request(
+        &mut dap,
+        &mut chip,
+        "readMemory",
+        json!({ "memoryReference": "0x200", "count": 4 }),
+    );
+    assert_eq!(memory["data"], json!("YAEiBg=="));
+
+    client.request(&mut dap, &mut chip, "disconnect", json!({}));
+    assert!(!chip.is_running());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,101 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+
+use chip_8::core::chip::Chip8;
+use chip_8::gdb::GdbServer;
+
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", data, checksum).unwrap();
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        self.reply()
+    }
+
+    fn reply(&mut self) -> String {
+        let mut packet = Vec::new();
+        let mut byte = [0u8; 1];
+
+        loop {
+            self.stream.read_exact(&mut byte).unwrap();
+            match byte[0] {
+                b'+' if packet.is_empty() => continue,
+                b'#' => break,
+                b => packet.push(b),
+            }
+        }
+
+        let mut checksum = [0u8; 2];
+        self.stream.read_exact(&mut checksum).unwrap();
+        self.stream.write_all(b"+").unwrap();
+
+        String::from_utf8(packet[1..].to_vec()).unwrap()
+    }
+}
+
+#[test]
+fn drives_the_chip_over_tcp() {
+    // 200: LD V0, 7  202: LD I, 0x300  204: LD [I], V0  206: JP 206
+    let mut chip = Chip8::new();
+    chip.load_rom(&[0x60, 0x07, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06])
+        .unwrap();
+
+    let mut server = GdbServer::bind("127.0.0.1:0", 10).unwrap();
+    let address = server.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut client = Client {
+            stream: TcpStream::connect(address).unwrap(),
+        };
+
+        assert_eq!(client.request("?"), "S05");
+        assert_eq!(client.request("m200,2"), "6007");
+
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p0"), "07");
+        assert_eq!(client.request("p11"), "0202");
+
+        assert_eq!(client.request("Z2,300,1"), "OK");
+        client.send("c");
+        assert_eq!(client.reply(), "T05watch:300;");
+        assert_eq!(client.request("m300,1"), "07");
+
+        // Watchpoints past the end of memory are refused
+        assert_eq!(client.request("Z2,ffff,4"), "E01");
+        assert_eq!(client.request("Z3,ffe,4"), "E01");
+        assert_eq!(client.request("Z4,ffc,4"), "OK");
+        assert_eq!(client.request("z4,ffc,4"), "OK");
+
+        assert_eq!(client.request("Z0,206,2"), "OK");
+        client.send("c");
+        assert_eq!(client.reply(), "T05swbreak:;");
+
+        // FX55 moved I past the stored byte
+        let registers = client.request("g");
+        assert_eq!(&registers[32..40], "01030602");
+
+        // A PC on the last byte runs on into the start of memory
+        assert_eq!(client.request("P11=ff0f"), "OK");
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p11"), "0100");
+        assert_eq!(client.request("sfff"), "S05");
+        assert_eq!(client.request("p11"), "0100");
+
+        client.send("k");
+    });
+
+    server.wait_for_client().unwrap();
+    while chip.is_running() && !client.is_finished() {
+        server.run_frame(&mut chip).unwrap();
+    }
+
+    client.join().unwrap();
+}
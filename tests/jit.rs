@@ -0,0 +1,23 @@
+#![cfg(feature = "jit")]
+
+use std::fs;
+
+use chip_8::core::jit;
+use chip_8::core::quirks::Quirks;
+
+// Every bundled ROM must leave the JIT and the interpreter in the same
+// state after every frame, at a high IPF so blocks run back to back
+#[test]
+fn bundled_roms_match_the_interpreter() {
+    for entry in fs::read_dir("roms").unwrap() {
+        let path = entry.unwrap().path();
+        let rom = fs::read(&path).unwrap();
+
+        for preset in ["chip8", "schip", "xochip"] {
+            let quirks = Quirks::from_name(preset).unwrap();
+            if let Err(e) = jit::compare(&rom, quirks, 120, 1000) {
+                panic!("{} under {}: {}", path.display(), preset, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use wasm_bindgen::prelude::*;
+
+use chip_8::core::chip::Chip8;
+use chip_8::core::display::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip_8::palette::Palette;
+
+#[wasm_bindgen]
+pub struct Emulator {
+    chip: Chip8,
+    palette: Palette,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Emulator {
+        Emulator {
+            chip: Chip8::new(),
+            palette: Palette::default(),
+        }
+    }
+
+    // Starts over with a fresh machine so the same emulator can switch ROMs,
+    // throwing when the ROM is empty or too large
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), String> {
+        self.chip = Chip8::new();
+        self.chip.load_rom(rom)
+    }
+
+    pub fn run_frame(&mut self, ipf: u32) {
+        self.chip.step(ipf);
+    }
+
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        if pressed {
+            self.chip.keypad().press(key);
+        } else {
+            self.chip.keypad().release(key);
+        }
+    }
+
+    // Colors as 0xRRGGBB
+    pub fn set_palette(&mut self, background: u32, foreground: u32) {
+        let rgb = |color: u32| ((color >> 16) as u8, (color >> 8) as u8, color as u8);
+
+        self.palette = Palette::new(rgb(background), rgb(foreground));
+    }
+
+    pub fn width(&self) -> u32 {
+        SCREEN_WIDTH as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        SCREEN_HEIGHT as u32
+    }
+
+    // Four bytes per pixel, ready for an ImageData of width() by height()
+    pub fn framebuffer(&self) -> Vec<u8> {
+        let display = self.chip.display();
+        let mut pixels = Vec::with_capacity(SCREEN_WIDTH as usize * SCREEN_HEIGHT * 4);
+
+        for row in 0..SCREEN_HEIGHT {
+            for column in 0..SCREEN_WIDTH as usize {
+                let (r, g, b) = self.palette.color(display.is_lit(column, row));
+                pixels.extend_from_slice(&[r, g, b, 255]);
+            }
+        }
+
+        pixels
+    }
+
+    pub fn sound_active(&self) -> bool {
+        self.chip.is_sound_playing()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.chip.is_running()
+    }
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Emulator::new()
+    }
+}
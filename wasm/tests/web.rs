@@ -0,0 +1,55 @@
+#![cfg(target_arch = "wasm32")]
+
+use wasm_bindgen_test::*;
+
+use chip_8_wasm::Emulator;
+
+const IBM_LOGO: &[u8] = include_bytes!("../../roms/ibm_logo.ch8");
+
+#[wasm_bindgen_test]
+fn framebuffer_is_rgba_sized() {
+    let emulator = Emulator::new();
+
+    let pixels = emulator.framebuffer();
+
+    assert_eq!(
+        pixels.len(),
+        (emulator.width() * emulator.height() * 4) as usize
+    );
+}
+
+#[wasm_bindgen_test]
+fn draws_ibm_logo() {
+    let mut emulator = Emulator::new();
+    emulator.set_palette(0x000000, 0xFFFFFF);
+    emulator.load_rom(IBM_LOGO).unwrap();
+
+    for _ in 0..10 {
+        emulator.run_frame(20);
+    }
+
+    let lit = emulator
+        .framebuffer()
+        .chunks(4)
+        .filter(|pixel| pixel[0] == 0xFF)
+        .count();
+
+    assert!(lit > 0);
+}
+
+#[wasm_bindgen_test]
+fn sound_follows_sound_timer() {
+    let mut emulator = Emulator::new();
+
+    // 6005: V0 = 5, F018: ST = V0, 1204: loop forever
+    emulator
+        .load_rom(&[0x60, 0x05, 0xF0, 0x18, 0x12, 0x04])
+        .unwrap();
+    emulator.run_frame(3);
+    assert!(emulator.sound_active());
+
+    for _ in 0..5 {
+        emulator.run_frame(3);
+    }
+    assert!(!emulator.sound_active());
+}
@@ -0,0 +1,87 @@
+import init, { Emulator } from "../pkg/chip_8_wasm.js";
+
+// Same layout as the desktop frontends, keyed by KeyboardEvent.code
+const KEYS = {
+  Digit1: 0x1, Digit2: 0x2, Digit3: 0x3, Digit4: 0xc,
+  KeyQ: 0x4, KeyW: 0x5, KeyE: 0x6, KeyR: 0xd,
+  KeyA: 0x7, KeyS: 0x8, KeyD: 0x9, KeyF: 0xe,
+  KeyZ: 0xa, KeyX: 0x0, KeyC: 0xb, KeyV: 0xf,
+};
+
+await init();
+
+const emulator = new Emulator();
+const canvas = document.getElementById("screen");
+const context = canvas.getContext("2d");
+const image = context.createImageData(emulator.width(), emulator.height());
+
+const FRAME_MS = 1000 / 60;
+
+let audio = null;
+let gain = null;
+let running = false;
+let lastTime = null;
+let pending = 0;
+
+function startAudio() {
+  if (audio) {
+    return;
+  }
+
+  audio = new AudioContext();
+  const oscillator = audio.createOscillator();
+  gain = audio.createGain();
+  oscillator.type = "square";
+  oscillator.frequency.value = 440;
+  gain.gain.value = 0;
+  oscillator.connect(gain).connect(audio.destination);
+  oscillator.start();
+}
+
+function frame(time) {
+  // Displays may refresh faster than 60 Hz, the timers must not
+  pending += lastTime === null ? FRAME_MS : time - lastTime;
+  lastTime = time;
+
+  const ipf = Number(document.getElementById("ipf").value);
+  for (; pending >= FRAME_MS; pending -= FRAME_MS) {
+    emulator.run_frame(ipf);
+  }
+
+  image.data.set(emulator.framebuffer());
+  context.putImageData(image, 0, 0);
+
+  if (gain) {
+    // Ramp instead of switching to avoid clicks
+    gain.gain.setTargetAtTime(emulator.sound_active() ? 0.05 : 0, audio.currentTime, 0.002);
+  }
+
+  if (running) {
+    requestAnimationFrame(frame);
+  }
+}
+
+document.getElementById("rom").addEventListener("change", async (event) => {
+  const file = event.target.files[0];
+  try {
+    emulator.load_rom(new Uint8Array(await file.arrayBuffer()));
+  } catch (error) {
+    alert(`Could not load ${file.name}: ${error}`);
+    return;
+  }
+  startAudio();
+
+  if (!running) {
+    running = true;
+    requestAnimationFrame(frame);
+  }
+});
+
+for (const [type, pressed] of [["keydown", true], ["keyup", false]]) {
+  document.addEventListener(type, (event) => {
+    if (event.code in KEYS) {
+      emulator.set_key(KEYS[event.code], pressed);
+      event.preventDefault();
+    }
+  });
+}